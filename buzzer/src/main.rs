@@ -9,9 +9,10 @@ use hal::port::{Pin, PinOps};
 use hal::port::mode::Output;
 use hal::clock::MHz12;
 
+use simplebus2::{Code, Message};
+
 const ADDR: u8 = 12;
-const MSG: u8 = 21;  // MSG_CALL_FROM_FLOOR_DOOR
-const CHECKSUM: u8 = (u8::count_ones(MSG) + u8::count_ones(ADDR)) as u8;
+const MSG: [u8; 3] = Message::new(Code::CallFloorDoor, ADDR).to_raw_bytes();
 
 #[attiny_hal::entry]
 fn main() -> ! {
@@ -29,9 +30,9 @@ fn main() -> ! {
     let mut output = pins.pb0.into_output_high();
 
     loop {
-        uart_tx(MSG << 6, &mut output);
-        uart_tx(ADDR << 4 | MSG >> 2, &mut output);
-        uart_tx(CHECKSUM << 4 | ADDR >> 4, &mut output);
+        for byte in MSG.iter() {
+            uart_tx(*byte, &mut output);
+        }
 
         delay.delay_ms(175_u8);  // 1750ms
     }
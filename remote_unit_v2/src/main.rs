@@ -7,7 +7,7 @@ use async_button::{Button, ButtonConfig, ButtonEvent};
 use core::mem::MaybeUninit;
 use defmt::*;
 use embassy_executor::Spawner;
-use embassy_futures::select::{select, Either};
+use embassy_futures::select::{select, select4, Either, Either4};
 use embassy_net::tcp::{ConnectError, TcpSocket};
 use embassy_net::{dns, Runner, Stack};
 use embassy_rp::gpio::{Input, Level, Output, Pull};
@@ -21,9 +21,11 @@ use embassy_rp::uart::{
 };
 use embassy_rp::{bind_interrupts, dma, uart, Peripheral};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
 use embassy_sync::mutex::Mutex;
 use embassy_sync::pubsub::{PubSubChannel, WaitResult};
-use embassy_time::{Instant, Timer};
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Instant, Ticker, Timer};
 use embedded_io_async::{Read, Write};
 use rand_core::RngCore;
 use rgb::RGB8;
@@ -31,7 +33,13 @@ use rust_mqtt::client::client::MqttClient;
 use rust_mqtt::client::client_config::ClientConfig;
 use rust_mqtt::packet::v5::reason_codes::ReasonCode;
 use rust_mqtt::utils::rng_generator::CountingRng;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
+use simplebus2::auth::{Key, Signer};
+use simplebus2::census::{Census, Seen};
+use simplebus2::config::{Command, Value, CALIBRATION_BYTE, CALIBRATION_LEN};
+use simplebus2::link::{Link, LinkConfig, Output as LinkOutput};
+use simplebus2::session::{CallEvent, CallTracker};
+use simplebus2::{Code, Frame, FrameDecoder, Message};
 use {defmt_rtt as _, panic_probe as _};
 
 mod network;
@@ -46,9 +54,49 @@ const WIFI_SSID: &str = env!("WIFI_SSID");
 const WIFI_PASSWORD: &str = env!("WIFI_PASSWORD");
 const MQTT_BROKER: &str = env!("MQTT_BROKER");
 
+/// Key shared with the repeater, passed as 32 hexadecimal digits in the
+/// INTERCOM_KEY environment variable at build time. When a key is set, the
+/// messages sent to the repeater are authenticated.
+const INTERCOM_KEY: Option<Key> = match option_env!("INTERCOM_KEY") {
+    Some(hex) => match Key::from_hex(hex) {
+        Some(key) => Some(key),
+        None => panic!("INTERCOM_KEY must be 32 hexadecimal digits"),
+    },
+    None => None,
+};
+
 const MY_INTERCOM_ADDRESS: u8 = 12;
 const MQTT_MSG_TOPIC: &str = "plusvic/intercom/messages";
 const MQTT_CMD_TOPIC: &str = "plusvic/intercom/commands";
+const MQTT_CONFIG_TOPIC: &str = "plusvic/intercom/config";
+const MQTT_CONFIG_VALUES_TOPIC: &str = "plusvic/intercom/config/values";
+const MQTT_ACK_TOPIC: &str = "plusvic/intercom/acks";
+const MQTT_CALL_TOPIC: &str = "plusvic/intercom/calls";
+const MQTT_CENSUS_TOPIC: &str = "plusvic/intercom/census";
+
+/// Maximum time between a message and its acknowledgement. This includes the
+/// time it takes to transmit the message over UART and the bus when the
+/// message is sent by us, and the repeater's retries.
+const ACK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Number of messages that can wait for their acknowledgement at the same
+/// time.
+const AWAITING_ACKS: usize = 4;
+
+/// Number of addresses tracked by the census of the bus.
+const CENSUS_SIZE: usize = 32;
+
+/// Interval between publications of the census of the bus.
+const CENSUS_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Settings of the link with the repeater. Set `sequenced` to false for
+/// sending frames that are neither acknowledged nor retransmitted.
+const UART_LINK: LinkConfig = LinkConfig::DEFAULT;
+
+/// The bytes in a frame are transmitted back-to-back. If no byte is received
+/// for this long, any partially received frame is discarded. At 4800 bps
+/// transmitting a byte takes ~2ms.
+const UART_FRAME_GAP: Duration = Duration::from_millis(10);
 
 #[embassy_executor::task]
 async fn net_task(mut runner: Runner<'static, NetDriver<'static>>) -> ! {
@@ -101,99 +149,357 @@ where
     }
 }
 
-/// SimpleBus2 message codes.
-#[derive(Clone, Debug, Default, Format, Serialize, Deserialize, PartialEq)]
-struct Code(u8);
-
-impl Code {
-    const OPEN_DOOR: Code = Code(16);
-    const CALL_SECONDARY_SWITCHBOARD: Code = Code(19);
-    const CAMERA_ON: Code = Code(20);
-    const CALL_FLOOR_DOOR: Code = Code(21);
-    const CALL: Code = Code(48);
-    const CALL_END: Code = Code(50);
-}
-
-/// Describes a SimpleBus2 message.
-#[derive(Clone, Debug, Format, Serialize, Deserialize)]
-struct Message {
-    /// Message code.
-    code: Code,
-    /// Intercom address. This is the target or the source of the message,
-    /// depending on the type of message. For instance, in `OpenDoor` messages
-    /// this contains message's source (i.e: the address of the intercom
-    /// sending the message), but in `CallFromEntryDoor` messages this contains
-    /// the message's target (i.e: the address of the intercom that it being
-    /// called).
-    address: u8,
-}
-
-impl Message {
-    pub fn from_raw_bytes(b: &[u8; 3]) -> Option<Self> {
-        let address = b[1] >> 4 | b[2] << 4;
-        let code = (b[0] >> 4 | b[1] << 4) >> 2;
-        let checksum = b[2] >> 4;
-        // Make sure the message is valid, the number of 1s in code and
-        // address must be equal to checksum.
-        if checksum != 0
-            && u8::count_ones(code) + u8::count_ones(address)
-                == checksum as u32
-        {
-            Some(Self { code: Code(code), address })
-        } else {
-            None
-        }
-    }
-
-    pub fn into_raw_bytes(&self, b: &mut [u8; 3]) {
-        let checksum =
-            u8::count_ones(self.code.0) + u8::count_ones(self.address);
-        b[0] = self.code.0 << 6;
-        b[1] = self.address << 4 | self.code.0 >> 2;
-        b[2] = (checksum as u8) << 4 | self.address >> 4;
-    }
-}
-
 /// A task responsible for UART communication.
 ///
 /// This task waits for messages to be received or transmitted over UART.
 /// When a message is received over UART the message is published in the
 /// UART_RX_MESSAGES pubsub to be consumed by other tasks. When a message
 /// is published to the UART_TX_MESSAGES pubsub by other tasks, this task
-/// consumes the message and transmit it over UART.
+/// consumes the message and transmit it over UART. Acknowledgements
+/// received over UART are sent to the ACKS channel.
+///
+/// Frames sent over UART are retransmitted until the repeater acknowledges
+/// them (see `simplebus2::link`).
+///
+/// When INTERCOM_KEY is set, messages are sent in authenticated frames. The
+/// counter starts at zero after every reboot. The repeater refuses the first
+/// message and answers with the last counter it accepted, then the message
+/// is sent again with the right counter.
 #[embassy_executor::task]
 async fn uart_task(mut uart: Uart<'static, UART0, Async>) {
-    let mut msg_bytes = [0u8; 3];
+    let mut byte = [0u8; 1];
+    let mut decoder = FrameDecoder::new();
+    let mut signer = INTERCOM_KEY.map(|key| Signer::new(key, 0));
+    let mut last_sent = None;
+
+    let mut link = Link::<8>::new(UART_LINK);
 
     let uart_rx = INBOUND_MESSAGES.publisher().unwrap();
     let mut uart_tx = OUTBOUND_MESSAGES.subscriber().unwrap();
 
     loop {
-        // Wait for a message received through UART, or a message that was published
-        // to OUTBOUND_MESSAGES and must be sent through UART, whatever comes first.
-        match select(uart.read(&mut msg_bytes), uart_tx.next_message()).await {
-            // A message was received through UART, it must be published to the
-            // INBOUND_MESSAGES pubsub.
-            Either::First(Ok(_)) => {
-                if let Some(msg) = Message::from_raw_bytes(&msg_bytes) {
-                    info!("UART RX: {:?}", msg);
-                    uart_rx.publish(msg).await;
-                } else {
-                    error!("Corrupted message: {:?}", msg_bytes);
+        // Transmit the packets waiting in the link, if any.
+        let now_ms = Instant::now().as_millis() as u32;
+        while let Some(output) = link.poll(now_ms) {
+            match output {
+                LinkOutput::Transmit(packet) => {
+                    let _ = uart.write(&packet.to_raw_bytes()).await;
+                }
+                LinkOutput::Failed(frame) => {
+                    error!("UART TX: not acknowledged {:?}", frame);
+                }
+            }
+        }
+
+        // Wait for a byte received through UART, a message that was
+        // published to OUTBOUND_MESSAGES and must be sent through UART, the
+        // time for retransmitting a packet, or a request for calibrating the
+        // repeater's oscillator, whatever comes first. The timeout for
+        // reading only applies while in the middle of a frame.
+        let in_frame = decoder.pending() > 0;
+        let read = async {
+            if in_frame {
+                with_timeout(UART_FRAME_GAP, uart.read(&mut byte)).await
+            } else {
+                Ok(uart.read(&mut byte).await)
+            }
+        };
+        let retransmit_ms = link.next_timeout_ms(now_ms);
+        let retransmit = async {
+            match retransmit_ms {
+                Some(ms) => Timer::after_millis(ms.into()).await,
+                None => core::future::pending().await,
+            }
+        };
+        match select4(
+            read,
+            uart_tx.next_message(),
+            retransmit,
+            CALIBRATE.wait(),
+        )
+        .await
+        {
+            // A byte was received through UART. If it completes a message, the
+            // message must be published to the INBOUND_MESSAGES pubsub.
+            Either4::First(Ok(Ok(_))) => {
+                let Some(packet) = decoder.push(byte[0]) else { continue };
+                let now_ms = Instant::now().as_millis() as u32;
+                let frame = link.receive(packet, now_ms);
+                // Once the repeater reports the outcome of the last message
+                // sent, it was accepted, and notices can't refer to it
+                // anymore. Otherwise a replay of the accepted frame would
+                // make us sign the message again.
+                if let Some(Frame::Ack(msg) | Frame::NoAck(msg)) = frame {
+                    if last_sent == Some(msg) {
+                        last_sent = None;
+                    }
+                }
+                match frame {
+                    Some(Frame::Message(msg)) => {
+                        info!("UART RX: {:?}", msg);
+                        uart_rx.publish(msg).await;
+                    }
+                    // The repeater applied a configuration command. Values
+                    // are reported with the command that sets them.
+                    Some(Frame::Ack(msg))
+                        if Command::from_message(&msg).is_some() =>
+                    {
+                        info!("UART RX: config applied {:?}", msg);
+                        if let Some(Command::Set(value, data)) =
+                            Command::from_message(&msg)
+                        {
+                            let _ = CONFIG_VALUES
+                                .try_send(ConfigValue::new(value, data));
+                        }
+                    }
+                    Some(Frame::NoAck(msg))
+                        if Command::from_message(&msg).is_some() =>
+                    {
+                        error!("UART RX: config refused {:?}", msg);
+                    }
+                    Some(Frame::Ack(msg)) => {
+                        info!("UART RX: ACK {:?}", msg);
+                        let _ = ACKS.try_send(AckReport {
+                            message: msg,
+                            acknowledged: true,
+                        });
+                    }
+                    // The repeater gave up transmitting our message.
+                    Some(Frame::NoAck(msg)) => {
+                        info!("UART RX: NO ACK {:?}", msg);
+                        let _ = ACKS.try_send(AckReport {
+                            message: msg,
+                            acknowledged: false,
+                        });
+                    }
+                    // The repeater refused the last message because of its
+                    // counter. The message is signed again at most once.
+                    Some(Frame::Authenticated(notice)) => {
+                        let Some(signer) = &mut signer else { continue };
+                        if last_sent == Some(notice.message)
+                            && signer.sync(&notice)
+                        {
+                            info!(
+                                "UART TX: {:?} (counter synced)",
+                                notice.message
+                            );
+                            let _ = link.send(signer.sign(notice.message));
+                        }
+                        last_sent = None;
+                    }
+                    None => {}
                 }
             }
             // Error while receiving message through UART.
-            Either::First(Err(err)) => {
+            Either4::First(Ok(Err(err))) => {
                 error!("Error reading from UART: {:?}", err);
+                decoder.reset();
+            }
+            // The line is idle, the bytes received so far don't form a
+            // frame and the remaining ones won't arrive.
+            Either4::First(Err(_)) => {
+                error!("Incomplete message: {} bytes", decoder.pending());
+                decoder.reset();
             }
             // A message was received from the OUTBOUND_MESSAGES pubsub, it must
             // be sent through UART.
-            Either::Second(WaitResult::Message(msg)) => {
+            Either4::Second(WaitResult::Message(msg)) => {
                 info!("UART TX: {:?}", msg);
-                msg.into_raw_bytes(&mut msg_bytes);
-                let _ = uart.write(&msg_bytes).await;
+                let frame = match &mut signer {
+                    Some(signer) => signer.sign(msg),
+                    None => Frame::Message(msg),
+                };
+                last_sent = Some(msg);
+                if link.send(frame).is_err() {
+                    error!("UART TX: queue full, {:?} dropped", msg);
+                }
+            }
+            Either4::Second(WaitResult::Lagged(_)) => {}
+            // A packet must be retransmitted, which is done at the start of
+            // the loop.
+            Either4::Third(()) => {}
+            // The repeater adjusts its oscillator while it receives the
+            // calibration stream, and reports the result like a `get osccal`
+            // (see `simplebus2::config`).
+            Either4::Fourth(()) => {
+                info!("UART TX: calibration stream");
+                let _ = uart.write(&CALIBRATION_STREAM).await;
+            }
+        }
+    }
+}
+
+/// Reports whether a message was acknowledged on the bus by its target.
+#[derive(Debug, Format, Serialize)]
+struct AckReport {
+    message: Message,
+    acknowledged: bool,
+}
+
+/// A value of the repeater's configuration (see `simplebus2::config`).
+#[derive(Debug, Format, Serialize)]
+struct ConfigValue {
+    name: &'static str,
+    value: u8,
+}
+
+impl ConfigValue {
+    fn new(value: Value, data: u8) -> Self {
+        Self { name: value.name(), value: data }
+    }
+}
+
+/// A task that tracks the acknowledgement of calls and open door commands.
+///
+/// For every relevant message seen on the bus or sent by us, this task waits
+/// for the acknowledgement relayed by the repeater and publishes an
+/// `AckReport` to the ACK_REPORTS channel, which is forwarded to MQTT.
+/// Several messages can wait at the same time, each acknowledgement is
+/// attributed to the oldest message it matches, like serial_bridge does.
+#[embassy_executor::task]
+async fn ack_task() {
+    let mut inbound = INBOUND_MESSAGES.subscriber().unwrap();
+    let mut outbound = OUTBOUND_MESSAGES.subscriber().unwrap();
+    // Messages waiting for an acknowledgement, and their deadlines.
+    let mut awaiting_ack: [Option<(Message, Instant)>; AWAITING_ACKS] =
+        [None; AWAITING_ACKS];
+
+    loop {
+        let deadline =
+            awaiting_ack.iter().flatten().map(|(_, deadline)| *deadline).min();
+        let timeout = async {
+            match deadline {
+                Some(deadline) => Timer::at(deadline).await,
+                None => core::future::pending().await,
+            }
+        };
+        match select4(
+            inbound.next_message(),
+            outbound.next_message(),
+            ACKS.receive(),
+            timeout,
+        )
+        .await
+        {
+            Either4::First(WaitResult::Message(message))
+            | Either4::Second(WaitResult::Message(message)) => {
+                await_ack(&mut awaiting_ack, message);
+            }
+            Either4::First(WaitResult::Lagged(_))
+            | Either4::Second(WaitResult::Lagged(_)) => {}
+            // Acknowledgements for other messages are discarded. The
+            // repeater reports the messages sent by us that it couldn't
+            // transmit, for the rest there's no report when they are not
+            // acknowledged.
+            Either4::Third(report) => {
+                let oldest = awaiting_ack
+                    .iter_mut()
+                    .filter(|slot| {
+                        matches!(slot, Some((m, _)) if *m == report.message)
+                    })
+                    .min_by_key(|slot| slot.map(|(_, deadline)| deadline));
+                if let Some(slot) = oldest {
+                    *slot = None;
+                    info!(
+                        "Acknowledged: {:?} {}",
+                        report.message, report.acknowledged
+                    );
+                    ACK_REPORTS.send(report).await;
+                }
+            }
+            Either4::Fourth(()) => {}
+        }
+
+        let now = Instant::now();
+        for slot in awaiting_ack.iter_mut() {
+            if let Some((message, deadline)) = *slot {
+                if deadline <= now {
+                    *slot = None;
+                    info!("Acknowledged: {:?} false", message);
+                    ACK_REPORTS
+                        .send(AckReport { message, acknowledged: false })
+                        .await;
+                }
+            }
+        }
+    }
+}
+
+/// Starts waiting for the acknowledgement of calls and door openings.
+fn await_ack(
+    awaiting_ack: &mut [Option<(Message, Instant)>; AWAITING_ACKS],
+    message: Message,
+) {
+    if !matches!(message.code, Code::Call | Code::CallEnd | Code::OpenDoor) {
+        return;
+    }
+    match awaiting_ack.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => *slot = Some((message, Instant::now() + ACK_TIMEOUT)),
+        None => warn!("Too many messages awaiting an ACK, {:?}", message),
+    }
+}
+
+/// A task that groups the messages sent during a call into call events.
+///
+/// The entry panel sends two calls and a call end for every visitor. This
+/// task publishes a `CallEvent` to the CALL_EVENTS pubsub when the call
+/// starts, for every ring after the first one and when the call ends, so
+/// that the visitor is notified once (see `simplebus2::session`).
+#[embassy_executor::task]
+async fn call_task() {
+    let mut inbound = INBOUND_MESSAGES.subscriber().unwrap();
+    let calls = CALL_EVENTS.publisher().unwrap();
+    let mut tracker = CallTracker::default();
+
+    loop {
+        // Wait for a message or for the call in progress to time out.
+        let now_ms = Instant::now().as_millis() as u32;
+        let timeout_ms = tracker.next_timeout_ms(now_ms);
+        let timeout = async {
+            match timeout_ms {
+                Some(ms) => Timer::after_millis(ms.into()).await,
+                None => core::future::pending().await,
+            }
+        };
+        let event = match select(inbound.next_message(), timeout).await {
+            Either::First(WaitResult::Message(message)) => {
+                tracker.message(&message, Instant::now().as_millis() as u32)
+            }
+            Either::First(WaitResult::Lagged(_)) => continue,
+            Either::Second(_) => None,
+        };
+        if let Some(event) = event {
+            info!("Call: {:?}", event);
+            calls.publish(event).await;
+        }
+        while let Some(event) = tracker.poll(Instant::now().as_millis() as u32)
+        {
+            info!("Call: {:?}", event);
+            calls.publish(event).await;
+        }
+    }
+}
+
+/// A task that records every message seen on the bus in the CENSUS table,
+/// which is published periodically by `mqtt_task`.
+#[embassy_executor::task]
+async fn census_task() {
+    let mut inbound = INBOUND_MESSAGES.subscriber().unwrap();
+
+    loop {
+        let WaitResult::Message(message) = inbound.next_message().await else {
+            continue;
+        };
+        let now_ms = Instant::now().as_millis() as u32;
+        match CENSUS.lock().await.record(&message, now_ms) {
+            Seen::NewAddress => {
+                info!("Census: new address {}", message.address)
+            }
+            Seen::NewCode => info!("Census: new code {:?}", message),
+            Seen::Known => {}
+            Seen::Dropped => {
+                warn!("Census: table full, {:?} dropped", message)
             }
-            Either::Second(WaitResult::Lagged(_)) => {}
         }
     }
 }
@@ -240,7 +546,9 @@ async fn mqtt_task_loop(stack: Stack<'static>) -> Result<(), MqttError> {
         .map_err(MqttError::ConnectError)?;
 
     let mut recv_buf = [0; 80];
-    let mut send_buf = [0; 80];
+    // Large enough for publishing the longest JSON payloads, which are the
+    // census reports.
+    let mut send_buf = [0; 512];
     let recv_buf_len = recv_buf.len();
     let send_buf_len = send_buf.len();
 
@@ -267,23 +575,38 @@ async fn mqtt_task_loop(stack: Stack<'static>) -> Result<(), MqttError> {
         .subscribe_to_topic(MQTT_CMD_TOPIC)
         .await
         .map_err(MqttError::MqttError)?;
+    mqtt_client
+        .subscribe_to_topic(MQTT_CONFIG_TOPIC)
+        .await
+        .map_err(MqttError::MqttError)?;
 
     info!("Connected to MQTT broker: {}", mqtt_broker_addr);
 
     let mut inbound =
         INBOUND_MESSAGES.subscriber().map_err(MqttError::PubSubError)?;
 
+    let mut calls =
+        CALL_EVENTS.subscriber().map_err(MqttError::PubSubError)?;
+
     let outbound =
         OUTBOUND_MESSAGES.publisher().map_err(MqttError::PubSubError)?;
 
+    let mut census_ticker = Ticker::every(CENSUS_INTERVAL);
+
     loop {
-        // Wait for a message received from UART or from the MQTT broker,
-        // whatever comes first.
-        match select(inbound.next_message(), mqtt_client.receive_message())
-            .await
+        // Wait for a message received from UART, an acknowledgement report,
+        // a configuration value, a call event, the time for publishing the
+        // census or a message from the MQTT broker, whatever comes first.
+        match select4(
+            inbound.next_message(),
+            select(ACK_REPORTS.receive(), CONFIG_VALUES.receive()),
+            select(calls.next_message(), census_ticker.next()),
+            mqtt_client.receive_message(),
+        )
+        .await
         {
             // Got a message from UART, forward it to MQTT.
-            Either::First(inbound_msg) => {
+            Either4::First(inbound_msg) => {
                 let message = match inbound_msg {
                     WaitResult::Message(message) => message,
                     WaitResult::Lagged(_) => {
@@ -293,6 +616,7 @@ async fn mqtt_task_loop(stack: Stack<'static>) -> Result<(), MqttError> {
                 info!("MQTT TX: {:?}", message);
                 mqtt_send_message(
                     &mut mqtt_client,
+                    MQTT_MSG_TOPIC,
                     serde_json_core::to_vec::<Message, 100>(&message)
                         .unwrap()
                         .as_ref(),
@@ -300,14 +624,90 @@ async fn mqtt_task_loop(stack: Stack<'static>) -> Result<(), MqttError> {
                 .await
                 .map_err(MqttError::MqttError)?;
             }
+            // Got an acknowledgement report, forward it to MQTT.
+            Either4::Second(Either::First(report)) => {
+                info!("MQTT TX: {:?}", report);
+                mqtt_send_message(
+                    &mut mqtt_client,
+                    MQTT_ACK_TOPIC,
+                    serde_json_core::to_vec::<AckReport, 100>(&report)
+                        .unwrap()
+                        .as_ref(),
+                )
+                .await
+                .map_err(MqttError::MqttError)?;
+            }
+            // Got a value of the repeater's configuration, forward it to
+            // MQTT.
+            Either4::Second(Either::Second(value)) => {
+                info!("MQTT TX: {:?}", value);
+                mqtt_send_message(
+                    &mut mqtt_client,
+                    MQTT_CONFIG_VALUES_TOPIC,
+                    serde_json_core::to_vec::<ConfigValue, 100>(&value)
+                        .unwrap()
+                        .as_ref(),
+                )
+                .await
+                .map_err(MqttError::MqttError)?;
+            }
+            // Got a call event, forward it to MQTT.
+            Either4::Third(Either::First(event)) => {
+                let event = match event {
+                    WaitResult::Message(event) => event,
+                    WaitResult::Lagged(_) => {
+                        continue;
+                    }
+                };
+                info!("MQTT TX: {:?}", event);
+                mqtt_send_message(
+                    &mut mqtt_client,
+                    MQTT_CALL_TOPIC,
+                    serde_json_core::to_vec::<CallEvent, 100>(&event)
+                        .unwrap()
+                        .as_ref(),
+                )
+                .await
+                .map_err(MqttError::MqttError)?;
+            }
+            // Time for publishing the census, one message per address. The
+            // table is not locked while publishing, so that `census_task`
+            // keeps recording.
+            Either4::Third(Either::Second(_)) => {
+                for i in 0..CENSUS_SIZE {
+                    let Some(device) =
+                        CENSUS.lock().await.devices().get(i).cloned()
+                    else {
+                        break;
+                    };
+                    let now_ms = Instant::now().as_millis() as u32;
+                    let Ok(payload) = serde_json_core::to_vec::<_, 384>(
+                        &device.report(now_ms),
+                    ) else {
+                        error!(
+                            "Census: report too long for {}",
+                            device.address()
+                        );
+                        continue;
+                    };
+                    info!("MQTT TX: census {}", device.address());
+                    mqtt_send_message(
+                        &mut mqtt_client,
+                        MQTT_CENSUS_TOPIC,
+                        payload.as_ref(),
+                    )
+                    .await
+                    .map_err(MqttError::MqttError)?;
+                }
+            }
             // Got a message from MQTT, forward it to UART.
-            Either::Second(mqtt_msg) => {
+            Either4::Fourth(mqtt_msg) => {
                 match mqtt_msg.map_err(MqttError::MqttError)? {
                     (MQTT_CMD_TOPIC, b"open_door") => {
                         info!("MQTT RX: open door");
                         outbound
                             .publish(Message {
-                                code: Code::OPEN_DOOR,
+                                code: Code::OpenDoor,
                                 address: MY_INTERCOM_ADDRESS,
                             })
                             .await;
@@ -316,11 +716,29 @@ async fn mqtt_task_loop(stack: Stack<'static>) -> Result<(), MqttError> {
                         info!("MQTT RX: camera on");
                         outbound
                             .publish(Message {
-                                code: Code::CAMERA_ON,
+                                code: Code::CameraOn,
                                 address: MY_INTERCOM_ADDRESS,
                             })
                             .await;
                     }
+                    (MQTT_CONFIG_TOPIC, b"calibrate") => {
+                        info!("MQTT RX: calibrate");
+                        CALIBRATE.signal(());
+                    }
+                    // Configuration commands for the repeater are sent like
+                    // any other message (see `simplebus2::config`).
+                    (MQTT_CONFIG_TOPIC, cmd) => {
+                        match core::str::from_utf8(cmd)
+                            .ok()
+                            .and_then(|cmd| cmd.parse::<Command>().ok())
+                        {
+                            Some(command) => {
+                                info!("MQTT RX: config {:?}", command);
+                                outbound.publish(command.to_message()).await;
+                            }
+                            None => error!("MQTT: unknown config {}", cmd),
+                        }
+                    }
                     (topic, cmd) => {
                         error!("MQTT: unknown command {}/{}", topic, cmd)
                     }
@@ -332,6 +750,7 @@ async fn mqtt_task_loop(stack: Stack<'static>) -> Result<(), MqttError> {
 
 pub(crate) async fn mqtt_send_message<'a, T, const MAX_PROPERTIES: usize, R>(
     client: &mut MqttClient<'a, T, MAX_PROPERTIES, R>,
+    topic: &str,
     message: &[u8],
 ) -> Result<(), ReasonCode>
 where
@@ -340,7 +759,7 @@ where
 {
     client
         .send_message(
-            MQTT_MSG_TOPIC,
+            topic,
             message,
             rust_mqtt::packet::v5::publish_packet::QualityOfService::QoS1,
             false,
@@ -348,6 +767,12 @@ where
         .await
 }
 
+/// A task that flashes the LED strip and runs the vibration motor when
+/// someone calls.
+///
+/// Calls from the entry door are taken from the CALL_EVENTS pubsub, so that
+/// the door is opened automatically once per visitor and not for every ring.
+/// Other calls are taken from INBOUND_MESSAGES.
 #[embassy_executor::task]
 async fn feedback_task(
     led_strip: &'static LedStrip<'static, PIO0, NUM_LEDS>,
@@ -355,56 +780,68 @@ async fn feedback_task(
 ) {
     let outbound = OUTBOUND_MESSAGES.publisher().unwrap();
     let mut inbound = INBOUND_MESSAGES.subscriber().unwrap();
+    let mut calls = CALL_EVENTS.subscriber().unwrap();
     let mut motor = Output::new(motor, Level::Low);
 
     loop {
-        match inbound.next_message().await {
-            WaitResult::Message(message) => match message.code {
-                Code::CALL
-                | Code::CALL_END
-                | Code::CALL_SECONDARY_SWITCHBOARD => {
-                    // The LED strip and the motor are turned on alternately
-                    // and not simultaneously to reduce peak power demand.
-                    led_strip.all(BLUE).await;
-                    Timer::after_millis(500).await;
-                    if *MUTED.lock().await {
-                        led_strip.all(MUTED_COLOR).await;
-                    } else {
-                        led_strip.all(BLACK).await;
-                        // Provide haptic feedback only if not muted.
-                        motor.set_high();
-                        Timer::after_millis(500).await;
-                        motor.set_low();
+        let (ring, auto_open) =
+            match select(inbound.next_message(), calls.next_message()).await {
+                Either::First(WaitResult::Message(message)) => {
+                    match message.code {
+                        Code::CallSwitchboard => (true, true),
+                        Code::CallFloorDoor => {
+                            led_strip.all(BLUE).await;
+                            Timer::after_millis(750).await;
+                            led_strip.all(RED).await;
+                            Timer::after_millis(750).await;
+                            led_strip
+                                .all(if *MUTED.lock().await {
+                                    MUTED_COLOR
+                                } else {
+                                    BLACK
+                                })
+                                .await;
+                            (false, false)
+                        }
+                        _ => (false, false),
                     }
-                    // The call arrives less than 30s since the last time the
-                    // door was opened. Open again automatically.
-                    let last_open = LAST_OPEN.lock().await;
-                    if Instant::elapsed(&last_open).as_secs() < 30 {
-                        outbound
-                            .publish(Message {
-                                code: Code::OPEN_DOOR,
-                                address: MY_INTERCOM_ADDRESS,
-                            })
-                            .await;
-                    };
                 }
-                Code::CALL_FLOOR_DOOR => {
-                    led_strip.all(BLUE).await;
-                    Timer::after_millis(750).await;
-                    led_strip.all(RED).await;
-                    Timer::after_millis(750).await;
-                    led_strip
-                        .all(if *MUTED.lock().await {
-                            MUTED_COLOR
-                        } else {
-                            BLACK
-                        })
-                        .await;
-                }
-                _ => {}
-            },
-            WaitResult::Lagged(_) => {}
+                Either::Second(WaitResult::Message(event)) => match event {
+                    CallEvent::Started { .. } => (true, true),
+                    CallEvent::Ringing { .. } => (true, false),
+                    CallEvent::Ended { .. } => (false, false),
+                },
+                _ => (false, false),
+            };
+
+        if !ring {
+            continue;
         }
+
+        // The LED strip and the motor are turned on alternately and not
+        // simultaneously to reduce peak power demand.
+        led_strip.all(BLUE).await;
+        Timer::after_millis(500).await;
+        if *MUTED.lock().await {
+            led_strip.all(MUTED_COLOR).await;
+        } else {
+            led_strip.all(BLACK).await;
+            // Provide haptic feedback only if not muted.
+            motor.set_high();
+            Timer::after_millis(500).await;
+            motor.set_low();
+        }
+        // The call arrives less than 30s since the last time the door was
+        // opened. Open again automatically.
+        let last_open = LAST_OPEN.lock().await;
+        if auto_open && Instant::elapsed(&last_open).as_secs() < 30 {
+            outbound
+                .publish(Message {
+                    code: Code::OpenDoor,
+                    address: MY_INTERCOM_ADDRESS,
+                })
+                .await;
+        };
     }
 }
 
@@ -413,7 +850,18 @@ static INBOUND_MESSAGES: PubSubChannel<
     CriticalSectionRawMutex,
     Message,
     5, // Capacity
-    3, // Subscribers, `uart_task`, `mqtt_task` and `feedback_task`.
+    // Subscribers, `mqtt_task`, `feedback_task`, `ack_task`, `call_task` and
+    // `census_task`.
+    5,
+    1, // Publishers
+> = PubSubChannel::new();
+
+/// PubSub channel where `call_task` puts the call events.
+static CALL_EVENTS: PubSubChannel<
+    CriticalSectionRawMutex,
+    CallEvent,
+    4, // Capacity
+    2, // Subscribers, `mqtt_task` and `feedback_task`.
     1, // Publishers
 > = PubSubChannel::new();
 
@@ -422,10 +870,34 @@ static OUTBOUND_MESSAGES: PubSubChannel<
     CriticalSectionRawMutex,
     Message,
     5, // Capacity
-    1, // Subscribers, `feedback_task` and main loop.
-    3, // Publishers, `uart_task`, `feedback_task` and main loop.
+    2, // Subscribers, `uart_task` and `ack_task`.
+    3, // Publishers, `mqtt_task`, `feedback_task` and main loop.
 > = PubSubChannel::new();
 
+/// Channel where `uart_task` puts the acknowledgements, and the failed
+/// transmissions, relayed by the repeater.
+static ACKS: Channel<CriticalSectionRawMutex, AckReport, 4> = Channel::new();
+
+/// Channel where `ack_task` puts the acknowledgement reports.
+static ACK_REPORTS: Channel<CriticalSectionRawMutex, AckReport, 2> =
+    Channel::new();
+
+/// Channel where `uart_task` puts the values of the repeater's configuration.
+static CONFIG_VALUES: Channel<CriticalSectionRawMutex, ConfigValue, 2> =
+    Channel::new();
+
+/// Signaled when the repeater's oscillator must be calibrated, `uart_task`
+/// sends the calibration stream.
+static CALIBRATE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Stream of bytes sent for calibrating the repeater's oscillator.
+static CALIBRATION_STREAM: [u8; CALIBRATION_LEN] =
+    [CALIBRATION_BYTE; CALIBRATION_LEN];
+
+/// Addresses and codes seen on the bus, recorded by `census_task`.
+static CENSUS: Mutex<CriticalSectionRawMutex, Census<CENSUS_SIZE>> =
+    Mutex::new(Census::new());
+
 /// When true, the haptic feedback is disabled.
 static MUTED: Mutex<CriticalSectionRawMutex, bool> = Mutex::new(false);
 
@@ -460,6 +932,9 @@ async fn main(spawner: Spawner) -> ! {
     led_strip.all(BLACK).await;
 
     unwrap!(spawner.spawn(feedback_task(led_strip, peripherals.PIN_10)));
+    unwrap!(spawner.spawn(ack_task()));
+    unwrap!(spawner.spawn(call_task()));
+    unwrap!(spawner.spawn(census_task()));
 
     // Spawn task that waits for messages over UART.
     let mut config = uart::Config::default();
@@ -499,7 +974,7 @@ async fn main(spawner: Spawner) -> ! {
             ButtonEvent::ShortPress { count: _ } => {
                 outbound
                     .publish(Message {
-                        code: Code::OPEN_DOOR,
+                        code: Code::OpenDoor,
                         address: MY_INTERCOM_ADDRESS,
                     })
                     .await;
@@ -5,77 +5,357 @@
 use core::mem;
 
 use attiny_hal as hal;
-use attiny_hal::pac::TC0;
+use attiny_hal::pac::{AC, CPU, EXINT, TC0, TC1};
 use attiny_hal::port::mode::{Input, PullUp};
 use attiny_hal::port::{PB2, PB3, PB4};
 use panic_halt as _;
 
-use hal::clock::MHz1;
+use hal::clock::{Clock, MHz1};
 use hal::port::mode::Output;
 use hal::port::{Pin, PinOps};
 use hal::prelude::*;
+use hal::Eeprom;
+
+use simplebus2::auth::{AuthError, Key, Verifier};
+use simplebus2::config::{Command, Settings, Value, CALIBRATION_BYTE};
+use simplebus2::link::{Link, LinkConfig, Output as LinkOutput};
+use simplebus2::pulse::{self, Decoder, Event, Pulses, Timing};
+use simplebus2::queue::Queue;
+use simplebus2::{Frame, FrameDecoder, Message, Packet};
 
 /// The device is shipped with its internal clock configured at 8MHz,
 /// but the CKDIV8 fuse is programmed by default, which means the clock
-/// frequency is actually 1MHz.
+/// frequency is actually 1MHz. The timers' pre-scalers and every timing
+/// constant are derived from this, so running at 8MHz with CKDIV8 cleared
+/// only requires changing it to `MHz8`.
 type ClockFreq = MHz1;
 
+/// Pre-scaler of Timer0 while it measures the silences on the bus. It's the
+/// largest one whose tick is at most 256us, so that the timer doesn't
+/// overflow before BUS_IDLE_GAP_US: 256 at 1MHz, 1024 at 8MHz.
+const TIMER0_PRESCALER: u32 = if ClockFreq::FREQ >= 4_000_000 {
+    1024
+} else {
+    256
+};
+
+/// Timer0 is incremented every 256us at 1MHz, and every 128us at 8MHz.
+const TIMER_TICK_US: u32 = TIMER0_PRESCALER * 1_000_000 / ClockFreq::FREQ;
+
+/// Pre-scaler of Timer1, which is incremented every 8us at any clock
+/// frequency: 8 at 1MHz, 64 at 8MHz.
+const TIMER1_PRESCALER: u32 = ClockFreq::FREQ / 125_000;
+
+/// Key shared with the remote unit, passed as 32 hexadecimal digits in the
+/// INTERCOM_KEY environment variable at build time. When a key is set, only
+/// authenticated frames are transmitted on the bus.
+const INTERCOM_KEY: Option<Key> = match option_env!("INTERCOM_KEY") {
+    Some(hex) => match Key::from_hex(hex) {
+        Some(key) => Some(key),
+        None => panic!("INTERCOM_KEY must be 32 hexadecimal digits"),
+    },
+    None => None,
+};
+
+/// Settings of the link with the remote unit. Set `sequenced` to false for
+/// sending frames that are neither acknowledged nor retransmitted.
+const UART_LINK: LinkConfig = LinkConfig::DEFAULT;
+
+/// Timer1 overflows every 256 ticks, or 2048us (see `main`).
+const CLOCK_TICK_US: u16 = (256 * TIMER1_PRESCALER * 1_000_000 / ClockFreq::FREQ) as u16;
+
+/// Speed of the UART connected to the HC-12.
+const UART_BAUD_RATE: u32 = 4800;
+
+/// Duration of a bit in Timer1 ticks. At 4800 bps this is 26 ticks, or
+/// 208us.
+const UART_BIT_TICKS: u8 = (ClockFreq::FREQ / TIMER1_PRESCALER / UART_BAUD_RATE) as u8;
+
+/// Duration of a byte, including the start and stop bits, in Timer1 ticks.
+const UART_BYTE_TICKS: u16 = UART_BIT_TICKS as u16 * 10;
+
+/// Number of bytes of the calibration stream in each measurement of the
+/// oscillator's frequency (see `uart_calibrate`). 16 bytes take 33ms.
+const CALIBRATION_BYTES: u8 = 16;
+
+/// Duration of CALIBRATION_BYTES bytes at UART_BAUD_RATE in Timer1 ticks,
+/// computed without rounding each byte like UART_BYTE_TICKS does.
+const CALIBRATION_TICKS: i16 =
+    (ClockFreq::FREQ / TIMER1_PRESCALER * 10 * CALIBRATION_BYTES as u32 / UART_BAUD_RATE) as i16;
+
+// The timing constants must fit in the timers' 8-bit registers. The compare
+// value of the carrier's longest half period is checked too (see `carrier`).
+const _: () = assert!(BUS_IDLE_GAP_US / TIMER_TICK_US < 256);
+const _: () = assert!(TIMER1_PRESCALER == 8 || TIMER1_PRESCALER == 64);
+const _: () =
+    assert!(ClockFreq::FREQ / 1_000_000 * *Settings::CARRIER_PERIODS.end() as u32 / 2 <= 256);
+
+/// Number of Timer1 overflows without receiving anything after which the
+/// bytes received so far are discarded. At 4800 bps a byte takes ~2ms, and
+/// the bytes in a frame are transmitted back-to-back.
+const UART_FRAME_GAP_TICKS: u8 = 3;
+
+/// Room for bytes waiting to be transmitted over UART.
+const UART_TX_BUFFER: usize = 32;
+
+/// Room for packets received over UART and not yet processed by the main
+/// loop. Packets take up to 16 bytes each, and RAM is scarce.
+const UART_RX_QUEUE: usize = 2;
+
+/// Room for frames waiting to be sent over the radio link, besides the one
+/// waiting for its acknowledgement. A message forwarded from the bus takes
+/// two frames.
+const UART_LINK_QUEUE: usize = 2;
+
+/// Silence required on the SimpleBus line before transmitting a message.
+/// It's longer than the preamble, so that the repeater doesn't transmit
+/// between the bits of a message being transmitted by someone else.
+const BUS_IDLE_GAP_US: u32 = 25_000;
+
+/// Maximum time a message waits for the SimpleBus line to be idle. After
+/// that the message is discarded, and the remote unit is notified.
+const BUS_TX_TIMEOUT_MS: u32 = 1_000;
+
+/// Number of compare matches of the carrier at the start and at the end of
+/// each burst transmitted, during which the comparator must see an edge. At
+/// 25KHz this is 200us.
+const BUS_ECHO_HALF_PERIODS: u16 = 10;
+
+/// Number of times a message is transmitted again when it's garbled,
+/// or when the target intercom doesn't acknowledge it.
+const BUS_TX_RETRIES: u8 = 2;
+
+/// Room for messages received over SimpleBus and not yet retransmitted
+/// over UART.
+const BUS_RX_QUEUE: usize = 4;
+
+/// Room for messages waiting to be transmitted over SimpleBus.
+const BUS_TX_QUEUE: usize = 4;
+
+/// EEPROM address where the counter of the last authenticated frame is
+/// stored.
+const EEPROM_AUTH_COUNTER: u16 = 0;
+
+/// EEPROM address where the settings are stored.
+const EEPROM_SETTINGS: u16 = 4;
+
 struct Bus {
     tx: Pin<Output, PB2>,
     timer: TC0,
-    received_bits: u32,
-    num_received_bits: u8,
-    received_msg: Option<Message>,
+    comparator: AC,
+    decoder: Decoder,
+    /// Messages received, waiting for the main loop.
+    received: Queue<Received, BUS_RX_QUEUE>,
+    /// Number of messages received that were dropped because `received` was
+    /// full.
+    rx_overflows: u16,
+    /// True while waiting for the acknowledgement of a message transmitted
+    /// by the repeater itself.
+    own_msg: bool,
+    /// True if the line has been silent since the last Timer0 overflow.
+    quiet: bool,
+    /// Messages waiting for the line to be idle. The message being
+    /// transmitted stays at the head of the queue until it's acknowledged
+    /// or all the attempts fail.
+    tx_queue: Queue<Outgoing, BUS_TX_QUEUE>,
+    /// Number of commands received over UART that were dropped because
+    /// `tx_queue` was full. The remote unit is notified of each of them.
+    tx_overflows: u16,
+    /// Message being transmitted, if any.
+    sending: Option<Sending>,
+    carrier: Carrier,
 }
 
-struct Uart {
-    rx: Pin<Input<PullUp>, PB4>,
-    tx: Pin<Output, PB3>,
-    received_msg: Option<Message>,
+/// Carrier of the bursts transmitted over SimpleBus, computed from the
+/// settings.
+#[derive(Clone, Copy)]
+struct Carrier {
+    /// While generating a burst Timer0 runs at the CPU clock, and each
+    /// compare match toggles the output, so the compare value is half the
+    /// carrier's period in CPU cycles, minus one.
+    compare: u8,
+    /// Number of compare matches in a burst. All the bursts have the same
+    /// duration.
+    half_periods: u16,
 }
 
-struct Message {
-    addr: u8,
-    code: u8,
-    checksum: u8,
+/// State of a transmission over SimpleBus. Bursts are generated by
+/// TIMER0_COMPA, which is scheduled at the end of each silence.
+struct Sending {
+    msg: Message,
+    pulses: Pulses,
+    /// True if the line didn't follow the transmission, or someone else
+    /// transmitted at the same time. The analog comparator must see edges
+    /// at the start and at the end of every burst, and none during the
+    /// silences.
+    garbled: bool,
+    /// True once the bus decoder has decoded the transmission as seen on
+    /// the line, and the decoded message is the one being transmitted.
+    echoed: bool,
 }
 
-impl Message {
-    pub fn new(addr: u8, code: u8, checksum: u8) -> Self {
-        Self {
-            addr,
-            code,
-            checksum,
-        }
-    }
+/// Measurement in progress of the oscillator's frequency, with the
+/// calibration stream sent by the remote unit (see `uart_calibrate`).
+#[derive(Clone, Copy, Default)]
+struct Calibration {
+    /// Difference between the time since the previous start bit and
+    /// UART_BYTE_TICKS, in Timer1 ticks.
+    deviation: i8,
+    /// True if the previous byte was a calibration byte.
+    after_byte: bool,
+    /// Calibration bytes measured so far, and the sum of their deviations.
+    bytes: u8,
+    sum: i16,
+    /// True once a measurement has been completed in the current stream.
+    streaming: bool,
+}
 
-    /// Makes sure that the message's checksum is the expected one. Returns
-    /// `None` if the message is invalid.
-    pub fn validate(self) -> Option<Self> {
-        if u8::count_ones(self.addr) + u8::count_ones(self.code) == self.checksum as u32 {
-            Some(self)
-        } else {
-            None
-        }
-    }
+/// Result of measuring CALIBRATION_BYTES bytes of the calibration stream.
+#[derive(Clone, Copy)]
+struct Measurement {
+    /// Difference between the bytes' duration and CALIBRATION_TICKS. It's
+    /// positive when the oscillator is too fast.
+    error: i16,
+    /// True if this is the first measurement of the stream.
+    first: bool,
+}
+
+/// State of the calibration of the oscillator, in the main loop.
+enum Tuning {
+    /// Waiting for a calibration stream, or the stream has been used already.
+    Idle,
+    /// Adjusting OSCCAL, with the value in effect before the stream, and the
+    /// previous value and its error, if any.
+    Adjusting {
+        initial: u8,
+        last: Option<(u8, i16)>,
+    },
+}
+
+/// A message waiting to be transmitted over SimpleBus.
+#[derive(Clone, Copy)]
+struct Outgoing {
+    msg: Message,
+    /// Value of UPTIME_MS when the message was queued, or when it was last
+    /// transmitted.
+    since_ms: u32,
+    /// Number of times the message has been transmitted.
+    attempts: u8,
+}
+
+/// A message received over SimpleBus.
+#[derive(Clone, Copy)]
+struct Received {
+    msg: Message,
+    /// True if the target intercom acknowledged the message.
+    acked: bool,
+    /// False if the message was transmitted by the repeater itself. In that
+    /// case only the acknowledgement is retransmitted over UART.
+    forward: bool,
+}
+
+/// Software UART driven by Timer1's compare matches: TIMER1_COMPA samples
+/// the bits received, and TIMER1_COMPB outputs the bits transmitted.
+struct Uart {
+    rx: Pin<Input<PullUp>, PB4>,
+    tx: Pin<Output, PB3>,
+    timer: TC1,
+    exint: EXINT,
+    decoder: FrameDecoder,
+    /// Packets received, waiting for the main loop.
+    received: Queue<Packet, UART_RX_QUEUE>,
+    /// Number of packets received that were dropped because `received` was
+    /// full. The remote unit retransmits sequenced packets that are lost.
+    rx_overflows: u16,
+    /// Bits received so far of the current byte, or `None` while waiting
+    /// for a start bit.
+    rx_bits: Option<u8>,
+    rx_byte: u8,
+    /// Timer1 overflows since the last byte was received.
+    rx_idle: u8,
+    /// Timer1's counter when the last start bit began.
+    rx_start: u8,
+    calibration: Calibration,
+    /// Measurement of the oscillator completed, waiting for the main loop.
+    measured: Option<Measurement>,
+    /// Bytes waiting to be transmitted.
+    tx_queue: Queue<u8, UART_TX_BUFFER>,
+    /// Bits of the byte being transmitted, including start and stop bits,
+    /// least significant first.
+    tx_bits: u16,
+    tx_count: u8,
+    /// Number of bits transmitted later than they should (see
+    /// `uart_tx_bit`).
+    tx_late: u16,
 }
 
 static mut BUS: mem::MaybeUninit<Bus> = mem::MaybeUninit::uninit();
 static mut UART: mem::MaybeUninit<Uart> = mem::MaybeUninit::uninit();
 
+/// Time since the device started, incremented by TIMER1_OVF.
+static mut UPTIME_MS: u32 = 0;
+
+/// Microseconds not yet added to UPTIME_MS.
+static mut UPTIME_US: u16 = 0;
+
 #[attiny_hal::entry]
 fn main() -> ! {
     let peripherals = hal::Peripherals::take().unwrap();
     let pins: hal::Pins = hal::pins!(peripherals);
 
+    // The settings are stored in the EEPROM. If they are missing, or they
+    // were written by a firmware with a different layout, the defaults are
+    // used. The default OSCCAL is the value calibrated at the factory, which
+    // is loaded at reset.
+    let mut eeprom = Eeprom::new(peripherals.EEPROM);
+    let mut settings = [0; Settings::LEN];
+    let _ = eeprom.read(EEPROM_SETTINGS, &mut settings);
+    let mut settings = Settings::from_bytes(&settings)
+        .unwrap_or_else(|| Settings::new(peripherals.CPU.osccal.read().bits()));
+
     // Calibrate internal oscillator. This changes from chip to chip.
     // https://becomingmaker.com/tuning-attiny-oscillator/
-    peripherals.CPU.osccal.write(|w| w.osccal().bits(105));
+    let cpu = peripherals.CPU;
+    cpu.osccal.write(|w| w.osccal().bits(settings.osccal));
+
+    // Timer0 measures the silences on the bus, it's incremented every
+    // TIMER_TICK_US.
+    timer0_measure(&peripherals.TC0);
 
-    // As the timer's pre-scaler is set to 256, the timer's clock is
-    // 1MHz/256 = 3.906KHz. In other words, the timer is incremented
-    // every 256us.
-    peripherals.TC0.tccr0b.write(|w| w.cs0().prescale_256());
+    // Timer1 times the bits transmitted and received over UART, and it's
+    // also used as a clock for retransmitting frames. The timer is
+    // incremented every 8us, and it overflows every 256 * 8us = 2048us.
+    peripherals.TC1.tccr1.write(|w| match TIMER1_PRESCALER {
+        64 => w.cs1().prescale_64(),
+        _ => w.cs1().prescale_8(),
+    });
+
+    // Timer0 reaches the compare value when the line has been silent for
+    // long enough for transmitting, which wakes up the main loop if there
+    // are messages waiting. While transmitting, the compare value is the
+    // end of the current silence instead (see `bus_tx`).
+    peripherals
+        .TC0
+        .ocr0a
+        .write(|w| w.bits((BUS_IDLE_GAP_US / TIMER_TICK_US) as u8));
+
+    // Enable the timer overflow interrupts. As Timer0 is reset on every
+    // ANA_COMP interrupt, an overflow means that the SimpleBus line has been
+    // silent for 256 * TIMER_TICK_US, 65ms at 1MHz and 32ms at 8MHz.
+    // Timer1's compare match interrupts occur every 2048us while the UART is
+    // idle, and do nothing.
+    peripherals.TC0.timsk.write(|w| {
+        w.toie0()
+            .set_bit()
+            .ocie0a()
+            .set_bit()
+            .toie1()
+            .set_bit()
+            .ocie1a()
+            .set_bit()
+            .ocie1b()
+            .set_bit()
+    });
 
     // Configure the Analog Comparator Interrupt to occur on both the
     // raising and falling edge. Also set the ACIE bit, which enables
@@ -91,21 +371,55 @@ fn main() -> ! {
     // Enable pin change interrupt.
     peripherals.EXINT.gimsk.write(|w| w.pcie().set_bit());
 
+    // The counter of the last authenticated frame survives reboots, so that
+    // frames can't be replayed after a power loss. The EEPROM is filled
+    // with 0xFF when erased.
+    let mut verifier = INTERCOM_KEY.map(|key| {
+        let mut counter = [0u8; 4];
+        let _ = eeprom.read(EEPROM_AUTH_COUNTER, &mut counter);
+        let counter = match u32::from_le_bytes(counter) {
+            u32::MAX => 0,
+            counter => counter,
+        };
+        Verifier::new(key, counter)
+    });
+
     // SAFETY: Interrupts are not enabled at this point so we can safely
     // initialize the global variables.
     unsafe {
         UART = mem::MaybeUninit::new(Uart {
             rx: pins.pb4.into_pull_up_input(),
             tx: pins.pb3.into_output_high(),
-            received_msg: None,
+            timer: peripherals.TC1,
+            exint: peripherals.EXINT,
+            decoder: FrameDecoder::new(),
+            received: Queue::new(),
+            rx_overflows: 0,
+            rx_bits: None,
+            rx_byte: 0,
+            rx_idle: 0,
+            rx_start: 0,
+            calibration: Calibration::default(),
+            measured: None,
+            tx_queue: Queue::new(),
+            tx_bits: 0,
+            tx_count: 0,
+            tx_late: 0,
         });
 
         BUS = mem::MaybeUninit::new(Bus {
             tx: pins.pb2.into_output(),
             timer: peripherals.TC0,
-            received_bits: 0,
-            num_received_bits: 0,
-            received_msg: None,
+            comparator: peripherals.AC,
+            decoder: Decoder::new(settings.timing()),
+            received: Queue::new(),
+            rx_overflows: 0,
+            own_msg: false,
+            quiet: true,
+            tx_queue: Queue::new(),
+            tx_overflows: 0,
+            sending: None,
+            carrier: carrier(&settings),
         });
     }
 
@@ -116,22 +430,201 @@ fn main() -> ! {
 
     let bus = unsafe { &mut *BUS.as_mut_ptr() };
     let uart = unsafe { &mut *UART.as_mut_ptr() };
+    let mut link = Link::<UART_LINK_QUEUE>::new(UART_LINK);
+    let mut tuning = Tuning::Idle;
 
     loop {
         // Go to sleep and wait for interrupts. Interrupts will occur
-        // when a message is received over UART or SimpleBus.
+        // when a message is received over UART or SimpleBus, and every
+        // 2ms when Timer1 overflows.
         avr_device::asm::sleep();
 
+        // The interrupt handlers leave what they receive in queues, which
+        // are emptied here one item at a time. The rest runs with interrupts
+        // enabled, so that the edges on the SimpleBus line and the UART bits
+        // are handled on time.
+        let now_ms = avr_device::interrupt::free(|_| unsafe { UPTIME_MS });
+
+        // If messages were received over SimpleBus, retransmit them over
+        // UART, followed by their acknowledgements if any. Messages are
+        // available only after the acknowledgement was received or timed
+        // out.
+        //
+        // Messages that don't pass the filter are dropped, together with
+        // their acknowledgements. Messages transmitted by the repeater
+        // itself are always reported. Messages are taken from the queue only
+        // when the link has room for both frames, otherwise they wait for
+        // the link to send the previous ones. The outcome of the repeater's
+        // own message is always taken, so that the message is done once
+        // it's acknowledged, even if its report doesn't fit in the link.
+        loop {
+            let room = link.room() >= 2;
+            let Some(received) = avr_device::interrupt::free(|_| match bus.received.peek() {
+                Some(received) if received.forward && !room => None,
+                _ => bus.received.pop(),
+            }) else {
+                break;
+            };
+            let report = !received.forward || settings.filter.allows(&received.msg);
+            if received.forward && report {
+                let _ = link.send(Frame::Message(received.msg));
+            }
+            if received.acked && report {
+                let _ = link.send(Frame::Ack(received.msg));
+            }
+            // The message transmitted by the repeater is done once it's
+            // acknowledged, otherwise it's transmitted again.
+            if !received.forward {
+                if received.acked {
+                    bus.tx_queue.pop();
+                } else {
+                    bus_tx_failed(bus, &mut link);
+                }
+            }
+        }
+
+        // If commands were received over UART, queue them until the
+        // SimpleBus line is idle. When the queue is full the command is
+        // dropped, and the remote unit is notified. Configuration commands
+        // are applied right away, and acknowledged.
+        //
+        // The link acknowledgements sent by the remote unit are always
+        // processed. The other packets are processed only when the link has
+        // room for their answer, otherwise they are dropped before being
+        // acknowledged, and the remote unit retransmits them.
+        while let Some(packet) = avr_device::interrupt::free(|_| uart.received.pop()) {
+            if link.room() == 0 && !matches!(packet, Packet::LinkAck { .. }) {
+                continue;
+            }
+            let Some(msg) = link
+                .receive(packet, now_ms)
+                .and_then(|frame| uart_command(&mut verifier, &mut eeprom, &mut link, frame))
+            else {
+                continue;
+            };
+            if let Some(command) = Command::from_message(&msg) {
+                let reply = uart_config(
+                    &cpu,
+                    bus,
+                    uart,
+                    &mut link,
+                    &mut eeprom,
+                    &mut settings,
+                    command,
+                );
+                let _ = link.send(reply);
+                continue;
+            }
+            let outgoing = Outgoing {
+                msg,
+                since_ms: now_ms,
+                attempts: 0,
+            };
+            if bus.tx_queue.push(outgoing).is_err() {
+                bus.tx_overflows = bus.tx_overflows.saturating_add(1);
+                let _ = link.send(Frame::NoAck(msg));
+            }
+        }
+
+        // While a calibration stream is being received, adjust OSCCAL after
+        // each measurement. Once done, the value is saved and reported to
+        // the remote unit like the answer to a `get osccal` command. The
+        // bytes measured with the previous value are discarded.
+        if let Some(measurement) = avr_device::interrupt::free(|_| uart.measured.take()) {
+            if measurement.first {
+                let initial = match tuning {
+                    Tuning::Adjusting { initial, .. } => initial,
+                    Tuning::Idle => settings.osccal,
+                };
+                tuning = Tuning::Adjusting {
+                    initial,
+                    last: None,
+                };
+            }
+            if let Tuning::Adjusting { last, .. } = &mut tuning {
+                let done = osccal_step(&mut settings, last, measurement.error);
+                avr_device::interrupt::free(|_| {
+                    cpu.osccal.write(|w| w.osccal().bits(settings.osccal));
+                    uart.calibration.bytes = 0;
+                    uart.calibration.sum = 0;
+                });
+                if done {
+                    tuning = Tuning::Idle;
+                    let _ = eeprom.write(EEPROM_SETTINGS, &settings.to_bytes());
+                    let msg = Command::Set(Value::Osccal, settings.osccal).to_message();
+                    let _ = link.send(Frame::Ack(msg));
+                }
+            }
+        }
+
+        // If the stream ended before the calibration was done, OSCCAL goes
+        // back to the value in effect before the stream, which is neither
+        // saved nor reported as applied. The remote unit is notified with a
+        // `NoAck` frame that contains it.
+        if let Tuning::Adjusting { initial, .. } = tuning {
+            let ended = avr_device::interrupt::free(|_| {
+                !uart.calibration.streaming && uart.measured.is_none()
+            });
+            if ended {
+                tuning = Tuning::Idle;
+                settings.osccal = initial;
+                avr_device::interrupt::free(|_| {
+                    cpu.osccal.write(|w| w.osccal().bits(initial));
+                });
+                let msg = Command::Set(Value::Osccal, initial).to_message();
+                let _ = link.send(Frame::NoAck(msg));
+            }
+        }
+
+        // Queue the frames waiting in the link for being transmitted over
+        // UART, including the link acknowledgements for the commands, if any.
+        // Frames are taken from the link only when there's room for them.
+        while UART_TX_BUFFER - avr_device::interrupt::free(|_| uart.tx_queue.len())
+            >= Frame::MAX_LEN
+        {
+            match link.poll(now_ms) {
+                Some(LinkOutput::Transmit(packet)) => uart_tx(uart, packet),
+                Some(_) => {}
+                None => break,
+            }
+        }
+
         avr_device::interrupt::free(|_| {
-            // If a message was received over SimpleBus, retransmit it
-            // over UART.
-            if let Some(msg) = bus.received_msg.take() {
-                uart_tx(&mut uart.tx, msg);
+            // Rebuild the decoder when the timing settings change. A decoder
+            // in the middle of a message, or waiting for an acknowledgement,
+            // keeps the old timing until it's done, so that the event isn't
+            // lost.
+            if bus.decoder.is_idle() && *bus.decoder.timing() != settings.timing() {
+                bus.decoder = Decoder::new(settings.timing());
+            }
+            // Nothing is done with the head of the queue while the outcome
+            // of its last transmission waits in `bus.received`, which happens
+            // when a forwarded message waits for room in the link before it.
+            // Otherwise an acknowledged message could be transmitted again.
+            let done = bus.received.is_empty();
+            // Discard the messages that waited for too long, the bus may be
+            // jammed, or someone else is talking for a long time.
+            while done && !bus.own_msg && bus.sending.is_none() {
+                match bus.tx_queue.peek() {
+                    Some(&Outgoing { msg, since_ms, .. })
+                        if now_ms.wrapping_sub(since_ms) >= BUS_TX_TIMEOUT_MS =>
+                    {
+                        bus.tx_queue.pop();
+                        let _ = link.send(Frame::NoAck(msg));
+                    }
+                    _ => break,
+                }
             }
-            // If a message was received over UART, retransmit it over
-            // SimpleBus.
-            if let Some(msg) = uart.received_msg.take() {
-                bus_tx(&mut bus.tx, msg);
+            // Retransmit the oldest queued message over SimpleBus, and
+            // listen for the target's acknowledgement. The rest wait for
+            // the acknowledgement and the next idle gap.
+            if done && bus_idle(bus) {
+                if let Some(outgoing) = bus.tx_queue.peek_mut() {
+                    outgoing.attempts += 1;
+                    outgoing.since_ms = now_ms;
+                    let msg = outgoing.msg;
+                    bus_tx(bus, msg, &settings.timing());
+                }
             }
         });
     }
@@ -141,34 +634,18 @@ fn main() -> ! {
 ///
 /// This interrupt occurs when there's a change in PB4, the UART's RX
 /// pin, which is connected to the HC-12 TX pin. So, the interrupt occurs
-/// when the HC-12 starts transmitting data.
+/// when the HC-12 starts transmitting a byte. The bits are sampled by
+/// TIMER1_COMPA.
 #[avr_device::interrupt(attiny85)]
 fn PCINT0() {
-    let mut delay = hal::delay::Delay::<ClockFreq>::new();
+    // SAFETY: Interrupts are enabled after UART has been initialized.
     let uart = unsafe { &mut *UART.as_mut_ptr() };
 
-    // Wait 70us into the start bit, we don't want to sample the bits
-    // right after the falling edge of the start bit.
-    delay.delay_us(70_u8);
-
     // RX must be low during the start bit. If not, this is a spurious
     // interrupt.
-    if uart.rx.is_high() {
-        return;
+    if uart.rx.is_low() {
+        uart_rx_start(uart);
     }
-
-    // Read 3 bytes from the UART.
-    let byte1 = uart_rx(&mut uart.rx);
-    let byte2 = uart_rx(&mut uart.rx);
-    let byte3 = uart_rx(&mut uart.rx);
-
-    // Decode the content the message, which will be processed
-    // in the main loop.
-    let addr = byte2 >> 4 | byte3 << 4;
-    let code = byte1 >> 6 | byte2 << 2;
-    let checksum = byte3 >> 4;
-
-    uart.received_msg = Message::new(addr, code, checksum).validate();
 }
 
 /// Analog comparator interrupt handler.
@@ -183,150 +660,636 @@ fn ANA_COMP() {
     let bus = unsafe { &mut *BUS.as_mut_ptr() };
 
     // Check the time elapsed since the last ANA_COMP interrupt. The timer is
-    // incremented every 256us. If a different CPU speed is used the ranges
-    // below must be adjusted accordingly.
-    match bus.timer.tcnt0.read().bits() {
-        // ~3ms elapsed (3000us / 256us = 11.719). A zero was received.
-        (8..=14) => {
-            bus.received_bits >>= 1;
-            bus.num_received_bits += 1;
-        }
-        // ~6ms elapsed (6000us / 256us = 23.437). A one was received.
-        (19..=26) => {
-            bus.received_bits |= 0b100_0000_0000_0000_0000;
-            bus.received_bits >>= 1;
-            bus.num_received_bits += 1;
+    // incremented every TIMER_TICK_US.
+    let gap_us = bus.timer.tcnt0.read().bits() as u32 * TIMER_TICK_US;
+
+    // The decoder ignores the short intervals between edges within a burst
+    // and decodes the silences between bursts.
+    let event = bus.decoder.gap(gap_us);
+    bus_event(bus, event);
+
+    // Set the timer counter back to zero.
+    bus.timer.tcnt0.reset();
+    bus.quiet = false;
+
+    // SAFETY: See PCINT0.
+    let uart = unsafe { &*UART.as_ptr() };
+    // The edges of a burst occur every 20us, so while bytes are transmitted
+    // over UART this interrupt would keep TIMER1_COMPB, which has a lower
+    // priority, from outputting the bits on time. The interrupt is disabled
+    // until the next bit is output, the edges meanwhile leave the flag set,
+    // and they are handled then, up to one bit late.
+    if uart.tx_count > 0 {
+        // Writing a zero to the flag leaves it as it is.
+        bus.comparator
+            .acsr
+            .modify(|_, w| w.acie().clear_bit().aci().clear_bit());
+    }
+}
+
+/// Timer0 compare match interrupt handler.
+///
+/// While a message is being transmitted, this interrupt occurs at the end of
+/// each silence, and generates the next burst. Otherwise it occurs when no
+/// ANA_COMP interrupt has occurred for BUS_IDLE_GAP_US, and it does nothing
+/// but waking up the main loop, which transmits the messages waiting for the
+/// line to be idle.
+#[avr_device::interrupt(attiny85)]
+fn TIMER0_COMPA() {
+    // SAFETY: See ANA_COMP.
+    let bus = unsafe { &mut *BUS.as_mut_ptr() };
+    bus_tx_next(bus);
+}
+
+/// Timer0 overflow interrupt handler.
+///
+/// This interrupt occurs when no ANA_COMP interrupt has occurred for
+/// 256 * TIMER_TICK_US (65ms at 1MHz), which means that the acknowledgement
+/// for the last message, if any, has already been received.
+#[avr_device::interrupt(attiny85)]
+fn TIMER0_OVF() {
+    // SAFETY: See ANA_COMP.
+    let bus = unsafe { &mut *BUS.as_mut_ptr() };
+    bus.quiet = true;
+    let event = bus.decoder.timeout();
+    bus_event(bus, event);
+}
+
+/// Timer1 overflow interrupt handler.
+///
+/// This interrupt occurs every 2048us, it keeps track of the time for
+/// retransmitting frames over UART, discards the incomplete frames received
+/// over UART, and wakes up the main loop.
+#[avr_device::interrupt(attiny85)]
+fn TIMER1_OVF() {
+    // SAFETY: UPTIME_MS and UPTIME_US are only modified here, and they are
+    // read with interrupts disabled.
+    unsafe {
+        UPTIME_US += CLOCK_TICK_US;
+        while UPTIME_US >= 1000 {
+            UPTIME_US -= 1000;
+            UPTIME_MS = UPTIME_MS.wrapping_add(1);
         }
-        // ~17ms elapsed (17000us / 256us = 66.406). Preamble.
-        (62..=70) => {
-            bus.received_bits = 0;
-            bus.num_received_bits = 0;
+    }
+
+    // SAFETY: See PCINT0.
+    let uart = unsafe { &mut *UART.as_mut_ptr() };
+    // The bytes in a frame are always transmitted back-to-back, so when the
+    // line becomes idle the bytes that don't form a complete frame are
+    // discarded. This way a lost byte doesn't affect the next frame.
+    if uart.rx_bits.is_none() {
+        uart.rx_idle = uart.rx_idle.saturating_add(1);
+        if uart.rx_idle == UART_FRAME_GAP_TICKS {
+            uart.decoder.reset();
+            uart.calibration = Calibration::default();
         }
-        _ => {}
     }
+}
+
+/// Timer1 compare match A interrupt handler.
+///
+/// While a byte is being received, this interrupt occurs in the middle of
+/// each bit.
+#[avr_device::interrupt(attiny85)]
+fn TIMER1_COMPA() {
+    // SAFETY: See PCINT0.
+    let uart = unsafe { &mut *UART.as_mut_ptr() };
+    uart_rx_bit(uart);
+}
 
-    if bus.num_received_bits == 18 {
-        let checksum: u8 = (bus.received_bits >> 14) as u8;
-        let addr = ((bus.received_bits >> 6) & 0b_11111111) as u8;
-        let code = (bus.received_bits & 0b_111111) as u8;
+/// Timer1 compare match B interrupt handler.
+///
+/// While bytes are being transmitted, this interrupt occurs at the start of
+/// each bit.
+#[avr_device::interrupt(attiny85)]
+fn TIMER1_COMPB() {
+    // SAFETY: See PCINT0.
+    let uart = unsafe { &mut *UART.as_mut_ptr() };
+    uart_tx_bit(uart);
 
-        bus.received_msg = Message::new(addr, code, checksum).validate();
-        bus.num_received_bits = 0;
+    // SAFETY: See ANA_COMP.
+    let bus = unsafe { &*BUS.as_ptr() };
+    // Enable the comparator's interrupt, which ANA_COMP disables while bytes
+    // are transmitted, unless the repeater is transmitting on the bus.
+    if bus.sending.is_none() {
+        bus.comparator
+            .acsr
+            .modify(|_, w| w.acie().set_bit().aci().clear_bit());
     }
+}
 
-    // Set the timer counter back to zero.
-    bus.timer.tcnt0.reset();
+/// Handles an event produced by the SimpleBus decoder.
+///
+/// Messages are queued for being retransmitted over UART when it's known
+/// whether they were acknowledged or not. If the queue is full the message
+/// is dropped and counted.
+fn bus_event(bus: &mut Bus, event: Option<Event>) {
+    let (msg, acked) = match event {
+        Some(Event::Ack(msg)) => (msg, true),
+        Some(Event::NoAck(msg)) => (msg, false),
+        _ => return,
+    };
+    let received = Received {
+        msg,
+        acked,
+        forward: !mem::take(&mut bus.own_msg),
+    };
+    if bus.received.push(received).is_err() {
+        bus.rx_overflows = bus.rx_overflows.saturating_add(1);
+    }
 }
 
-/// Transmits a message over the SimpleBus line.
-fn bus_tx<PIN: PinOps>(pin: &mut Pin<Output, PIN>, mut message: Message) {
-    let mut delay = hal::delay::Delay::<ClockFreq>::new();
+/// Returns true if the SimpleBus line has been idle for at least
+/// BUS_IDLE_GAP_US, and no message is being received. Must be called with
+/// interrupts disabled.
+fn bus_idle(bus: &Bus) -> bool {
+    // An edge that occurred while interrupts were disabled hasn't reset the
+    // timer yet, but its interrupt flag is set.
+    if bus.sending.is_some()
+        || !bus.decoder.is_idle()
+        || bus.comparator.acsr.read().aci().bit_is_set()
+    {
+        return false;
+    }
+    // The timer wraps around after an overflow, but then the line is known
+    // to be quiet.
+    bus.quiet || bus.timer.tcnt0.read().bits() as u32 * TIMER_TICK_US >= BUS_IDLE_GAP_US
+}
 
-    // Preamble.
-    burst_25khz_3ms(pin);
-    delay.delay_ms(17_u8);
-    burst_25khz_3ms(pin);
+/// Handles a failed transmission of the message at the head of the queue.
+/// The message stays in the queue for being transmitted again, unless all
+/// the attempts were used, in which case the remote unit is notified.
+fn bus_tx_failed<const N: usize>(bus: &mut Bus, link: &mut Link<N>) {
+    let Some(&outgoing) = bus.tx_queue.peek() else {
+        return;
+    };
+    if outgoing.attempts > BUS_TX_RETRIES {
+        bus.tx_queue.pop();
+        let _ = link.send(Frame::NoAck(outgoing.msg));
+    }
+}
 
-    for _ in 0..6 {
-        if (message.code & 1) == 1 {
-            delay.delay_ms(6_u8); // 6ms = 1
-        } else {
-            delay.delay_ms(3_u8); // 3ms = 0
+/// Returns the message that must be transmitted over SimpleBus, or the
+/// configuration command that must be applied, in response to a frame
+/// received over UART, if any.
+///
+/// Without a key, plain messages are accepted. With a key, only messages in
+/// authenticated frames are accepted. When an authenticated frame is refused
+/// because of its counter, the remote unit is notified so that it can
+/// resynchronize its counter and try again.
+fn uart_command<const N: usize>(
+    verifier: &mut Option<Verifier>,
+    eeprom: &mut Eeprom,
+    link: &mut Link<N>,
+    frame: Frame,
+) -> Option<Message> {
+    let Some(verifier) = verifier else {
+        return match frame {
+            Frame::Message(msg) => Some(msg),
+            _ => None,
+        };
+    };
+    match verifier.verify(&frame) {
+        Ok(msg) => {
+            // The counter is saved before transmitting the message.
+            let counter = verifier.counter().to_le_bytes();
+            let _ = eeprom.write(EEPROM_AUTH_COUNTER, &counter);
+            Some(msg)
+        }
+        Err(AuthError::Replayed(msg)) => {
+            let _ = link.send(verifier.notice(msg));
+            None
         }
-        burst_25khz_3ms(pin);
-        message.code >>= 1;
+        Err(_) => None,
     }
+}
+
+/// Starts transmitting a message over the SimpleBus line. Must be called
+/// with interrupts disabled.
+///
+/// The bursts are generated by TIMER0_COMPA, the first one on the next tick
+/// of Timer0.
+/// Meanwhile, the analog comparator interrupt is disabled and its flag is
+/// polled during every burst and at the end of every silence, for checking
+/// that the line follows the transmission. The transmission is decoded by
+/// the bus decoder, like the messages transmitted by others, which must be
+/// idle.
+fn bus_tx(bus: &mut Bus, msg: Message, timing: &Timing) {
+    bus.comparator.acsr.modify(|_, w| w.acie().clear_bit());
+    bus.decoder.reset();
+    bus.sending = Some(Sending {
+        msg,
+        // Preamble, followed by code, address and checksum, least
+        // significant bit first.
+        pulses: pulse::encode(&msg, timing),
+        garbled: false,
+        echoed: false,
+    });
+    let now = bus.timer.tcnt0.read().bits();
+    bus.timer.ocr0a.write(|w| w.bits(now.wrapping_add(1)));
+}
 
-    for _ in 0..8 {
-        if (message.addr & 1) == 1 {
-            delay.delay_ms(6_u8);
-        } else {
-            delay.delay_ms(3_u8);
+/// Generates the next burst of the message being transmitted, and schedules
+/// the following one at the end of the silence.
+///
+/// The silence that ends is passed to the bus decoder, as measured by
+/// Timer0 from the end of the previous burst. The comparator's flag checks
+/// that the line was silent meanwhile, and `burst` checks that the line
+/// followed the start and the end of the bursts, so this is the silence seen
+/// on the line within BUS_ECHO_HALF_PERIODS.
+///
+/// After the last burst the transmission ends: if the line followed the
+/// transmission, and the decoder got the message back, the repeater listens
+/// for the target's acknowledgement. Otherwise the transmission is handled
+/// like a message that wasn't acknowledged.
+fn bus_tx_next(bus: &mut Bus) {
+    let Some(sending) = &mut bus.sending else {
+        return;
+    };
+    // The line must be silent between bursts.
+    sending.garbled |= bus_edge(&bus.comparator);
+    if sending.pulses.len() < Pulses::LEN as usize {
+        let gap_us = bus.timer.tcnt0.read().bits() as u32 * TIMER_TICK_US;
+        if let Some(Event::Message(echo)) = bus.decoder.gap(gap_us) {
+            sending.echoed = echo == sending.msg;
         }
-        burst_25khz_3ms(pin);
-        message.addr >>= 1;
     }
-
-    for _ in 0..4 {
-        if (message.checksum & 1) == 1 {
-            delay.delay_ms(6_u8);
-        } else {
-            delay.delay_ms(3_u8);
+    if let Some(pulse) = sending.pulses.next() {
+        // Every burst must reach the comparator.
+        sending.garbled |= !burst(&mut bus.tx, &bus.timer, &bus.comparator, bus.carrier);
+        if pulse.gap_us > 0 {
+            // Rounded to the nearest tick.
+            let ticks = (pulse.gap_us + TIMER_TICK_US / 2) / TIMER_TICK_US;
+            bus.timer.ocr0a.write(|w| w.bits(ticks as u8));
+            return;
         }
-        burst_25khz_3ms(pin);
-        message.checksum >>= 1;
     }
-}
 
-/// Creates a 25KHz burst of pulses for 3ms.
-#[inline(never)]
-fn burst_25khz_3ms<PIN: PinOps>(pin: &mut Pin<Output, PIN>) {
-    let mut delay = hal::delay::Delay::<ClockFreq>::new();
-    for _ in 0..75 {
-        pin.set_high();
-        // In theory this should be 40us, but the delay_us seems to be
-        // very imprecise with low delays.
-        delay.delay_us(45_u8);
-        pin.set_low();
+    let Some(sending) = bus.sending.take() else {
+        return;
+    };
+    bus.timer
+        .ocr0a
+        .write(|w| w.bits((BUS_IDLE_GAP_US / TIMER_TICK_US) as u8));
+    // Enabling the interrupt also clears the comparator's flag.
+    bus.comparator.acsr.modify(|_, w| w.acie().set_bit());
+    bus.quiet = false;
+    bus.own_msg = true;
+    if !sending.garbled && sending.echoed {
+        bus.decoder.expect_ack(sending.msg);
+    } else {
+        bus_event(bus, Some(Event::NoAck(sending.msg)));
     }
 }
 
-/// Transmits a message over UART.
-fn uart_tx<PIN: PinOps>(pin: &mut Pin<Output, PIN>, msg: Message) {
-    uart_tx_byte(pin, msg.code << 6);
-    uart_tx_byte(pin, msg.addr << 4 | msg.code >> 2);
-    uart_tx_byte(pin, msg.checksum << 4 | msg.addr >> 4);
+/// Returns true if the analog comparator has seen an edge since the last
+/// call.
+fn bus_edge(comparator: &AC) -> bool {
+    let edge = comparator.acsr.read().aci().bit_is_set();
+    // The flag is cleared by writing a one to it.
+    comparator.acsr.modify(|_, w| w.aci().set_bit());
+    edge
 }
 
-/// Transmits a byte using bit-banged UART.
+/// Creates a burst of pulses with the given carrier, which is 25KHz by
+/// default.
 ///
-/// Settings: 4800 bps, 8 databits, no parity, 1 stop bit, no flow control.
+/// The carrier is timed by Timer0 in CTC mode. PB2 is not one of Timer0's
+/// compare outputs (those are PB0 and PB1, used by the analog comparator),
+/// so the pin is toggled on every compare match instead of by the timer
+/// itself. Afterwards Timer0 goes back to measuring silences, starting from
+/// the end of the burst.
+///
+/// The compare matches are polled, so this blocks for the whole burst. It's
+/// called by TIMER0_COMPA, and the other interrupts are enabled meanwhile so
+/// that the UART keeps working. A handler that runs during the burst delays
+/// the next toggles, and the burst gets longer if a compare match is missed.
+/// Timer1's outputs aren't usable either (PB1 and PB4, the comparator and
+/// UART RX), so this board can't generate the carrier in hardware, see
+/// "Bus access" in the README.
+///
+/// Returns true if the comparator saw edges during the first and the last
+/// BUS_ECHO_HALF_PERIODS compare matches, which means that the line followed
+/// the start and the end of the burst. Its flag is checked between the
+/// loops, which delays a single toggle by a few cycles.
 #[inline(never)]
-fn uart_tx_byte<PIN: PinOps>(pin: &mut Pin<Output, PIN>, mut byte: u8) {
-    let mut delay = hal::delay::Delay::<ClockFreq>::new();
-
-    // Start bit
+fn burst<PIN: PinOps>(
+    pin: &mut Pin<Output, PIN>,
+    timer: &TC0,
+    comparator: &AC,
+    carrier: Carrier,
+) -> bool {
+    timer.tccr0a.write(|w| w.wgm0().ctc());
+    timer.ocr0a.write(|w| w.bits(carrier.compare));
+    timer.tcnt0.reset();
+    timer.tccr0b.write(|w| w.cs0().direct());
+    // Timer0's interrupts must not occur on the carrier's compare matches,
+    // the analog comparator's interrupt is disabled while transmitting, and
+    // the other interrupts don't touch the bus.
+    timer
+        .timsk
+        .modify(|_, w| w.ocie0a().clear_bit().toie0().clear_bit());
+    // SAFETY: Nothing used by the interrupt handlers is modified during the
+    // burst.
+    unsafe { avr_device::interrupt::enable() };
+    bus_edge(comparator);
+    toggle(pin, timer, BUS_ECHO_HALF_PERIODS);
+    let start = bus_edge(comparator);
+    toggle(pin, timer, carrier.half_periods - 2 * BUS_ECHO_HALF_PERIODS);
+    bus_edge(comparator);
+    toggle(pin, timer, BUS_ECHO_HALF_PERIODS);
+    let end = bus_edge(comparator);
+    avr_device::interrupt::disable();
     pin.set_low();
 
-    // At 4800 bps 1 bit takes 208 microseconds.
-    delay.delay_us(208_u8);
+    timer.tccr0a.reset();
+    timer0_measure(timer);
+    timer.tcnt0.reset();
+    // The compare matches above must not trigger TIMER0_COMPA.
+    timer.tifr.write(|w| w.ocf0a().set_bit());
+    timer
+        .timsk
+        .modify(|_, w| w.ocie0a().set_bit().toie0().set_bit());
+    start && end
+}
+
+/// Toggles the pin on each of the next `half_periods` compare matches of
+/// Timer0 (see `burst`).
+#[inline(always)]
+fn toggle<PIN: PinOps>(pin: &mut Pin<Output, PIN>, timer: &TC0, half_periods: u16) {
+    for _ in 0..half_periods {
+        while timer.tifr.read().ocf0a().bit_is_clear() {}
+        timer.tifr.write(|w| w.ocf0a().set_bit());
+        pin.toggle();
+    }
+}
+
+/// Starts Timer0 with TIMER0_PRESCALER, for measuring silences.
+fn timer0_measure(timer: &TC0) {
+    timer.tccr0b.write(|w| match TIMER0_PRESCALER {
+        1024 => w.cs0().prescale_1024(),
+        _ => w.cs0().prescale_256(),
+    });
+}
+
+/// Returns the carrier of the bursts transmitted over SimpleBus. Divisions
+/// are slow on the ATtiny85, so this is computed when the settings change
+/// instead of for every burst.
+fn carrier(settings: &Settings) -> Carrier {
+    let period_us = settings.carrier_period as u32;
+    let cycles_per_us = ClockFreq::FREQ / 1_000_000;
+    Carrier {
+        compare: (cycles_per_us * period_us / 2 - 1) as u8,
+        half_periods: (settings.timing().burst_us * 2 / period_us) as u16,
+    }
+}
 
-    for _ in 0..8 {
-        if byte & 1 == 0 {
-            pin.set_low();
-        } else {
-            pin.set_high();
+/// Applies a configuration command received over UART. Returns the frame
+/// that answers the command: an acknowledgement if it was applied, which for
+/// [`Command::Get`] contains the current value, or a [`Frame::NoAck`] if it
+/// was refused.
+///
+/// Settings take effect right away, and they are saved in the EEPROM.
+/// Counters are kept in RAM only.
+fn uart_config<const N: usize>(
+    cpu: &CPU,
+    bus: &mut Bus,
+    uart: &mut Uart,
+    link: &mut Link<N>,
+    eeprom: &mut Eeprom,
+    settings: &mut Settings,
+    command: Command,
+) -> Frame {
+    match command {
+        Command::Get(value) => {
+            let count = avr_device::interrupt::free(|_| {
+                counter(bus, uart, link, value).map(|count| (*count).min(u8::MAX.into()) as u8)
+            });
+            // Every value is either a counter or a setting.
+            let data = count.or(settings.get(value)).unwrap_or_default();
+            return Frame::Ack(Command::Set(value, data).to_message());
+        }
+        Command::Set(value, data) => {
+            let count = avr_device::interrupt::free(|_| {
+                counter(bus, uart, link, value).map(|count| *count = data.into())
+            });
+            if count.is_some() {
+                return Frame::Ack(command.to_message());
+            }
+        }
+        _ => {}
+    }
+    if !settings.apply(command) {
+        return Frame::NoAck(command.to_message());
+    }
+    // Only the bytes that changed are written.
+    let _ = eeprom.write(EEPROM_SETTINGS, &settings.to_bytes());
+    // Changes to the timing are applied by the main loop, once the decoder
+    // is idle.
+    match command {
+        Command::Set(Value::Osccal, osccal) => {
+            avr_device::interrupt::free(|_| cpu.osccal.write(|w| w.osccal().bits(osccal)));
+        }
+        Command::Set(Value::CarrierPeriod, _) => {
+            let carrier = carrier(settings);
+            avr_device::interrupt::free(|_| bus.carrier = carrier);
         }
-        byte >>= 1;
-        // Wait for 190 us, compensating for the time it takes to run the loop
-        // control instructions.
-        delay.delay_us(190_u8);
+        _ => {}
     }
+    Frame::Ack(command.to_message())
+}
 
-    // Stop bit
-    pin.set_high();
-    delay.delay_us(208_u8);
+/// Returns the counter corresponding to a value, if any. Must be called with
+/// interrupts disabled.
+fn counter<'a, const N: usize>(
+    bus: &'a mut Bus,
+    uart: &'a mut Uart,
+    link: &'a mut Link<N>,
+    value: Value,
+) -> Option<&'a mut u16> {
+    match value {
+        Value::BusRxOverflows => Some(&mut bus.rx_overflows),
+        Value::BusTxOverflows => Some(&mut bus.tx_overflows),
+        Value::UartRxOverflows => Some(&mut uart.rx_overflows),
+        Value::UartTxLate => Some(&mut uart.tx_late),
+        Value::LinkOverflows => Some(&mut link.stats_mut().overflows),
+        _ => None,
+    }
 }
 
-#[inline(never)]
-fn uart_rx(pin: &mut Pin<Input<PullUp>, PB4>) -> u8 {
-    let mut delay = hal::delay::Delay::<ClockFreq>::new();
-    let mut result = 0u8;
-
-    // Skip start bit.
-    delay.delay_us(208_u8);
-
-    for _ in 0..8 {
-        result >>= 1;
-        if pin.is_high() {
-            result |= 0b1000_0000;
-        } else {
+/// Moves OSCCAL one step towards the frequency that makes the error of the
+/// last measurement zero. Returns true when the calibration is done, which
+/// happens when the error reaches zero or changes its sign. Then the value
+/// with the smallest error is kept.
+///
+/// Bit 7 of OSCCAL selects one of two overlapping frequency ranges, and the
+/// frequency increases with the other bits. Steps don't cross from one
+/// range to the other, the calibration stops at the end of the range.
+fn osccal_step(settings: &mut Settings, last: &mut Option<(u8, i16)>, error: i16) -> bool {
+    let osccal = settings.osccal;
+    if error == 0 {
+        return true;
+    }
+    if let Some((last_osccal, last_error)) = *last {
+        if (error > 0) != (last_error > 0) {
+            if last_error.abs() < error.abs() {
+                settings.osccal = last_osccal;
+            }
+            return true;
+        }
+    }
+    let next = if error > 0 {
+        osccal.wrapping_sub(1)
+    } else {
+        osccal.wrapping_add(1)
+    };
+    if next & 0x80 != osccal & 0x80 {
+        return true;
+    }
+    *last = Some((osccal, error));
+    settings.osccal = next;
+    false
+}
+
+/// Queues a packet for being transmitted over UART. There must be room for
+/// the packet in the queue.
+fn uart_tx(uart: &mut Uart, packet: Packet) {
+    avr_device::interrupt::free(|_| {
+        for &byte in packet.to_raw_bytes().iter() {
+            let _ = uart.tx_queue.push(byte);
+        }
+    });
+}
+
+/// Outputs the next bit transmitted over UART, and schedules the following
+/// one. When no byte is being transmitted, the next one in the queue starts.
+///
+/// Bits that start more than half a bit late are counted in `tx_late`, the
+/// byte they belong to is garbled. The delay is measured modulo 256 ticks.
+///
+/// Settings: 4800 bps, 8 databits, no parity, 1 stop bit, no flow control.
+fn uart_tx_bit(uart: &mut Uart) {
+    if uart.tx_count > 0 {
+        let now = uart.timer.tcnt1.read().bits();
+        let delay = now.wrapping_sub(uart.timer.ocr1b.read().bits());
+        if delay > UART_BIT_TICKS / 2 {
+            uart.tx_late = uart.tx_late.saturating_add(1);
         }
-        delay.delay_us(200_u8);
+    } else {
+        let Some(byte) = uart.tx_queue.pop() else {
+            return;
+        };
+        // Start bit, data bits and stop bit.
+        uart.tx_bits = (byte as u16) << 1 | 1 << 9;
+        uart.tx_count = 10;
+        // The interrupt occurs every 2048us while idle, the bits of this
+        // byte follow one another from now on.
+        let now = uart.timer.tcnt1.read().bits();
+        uart.timer.ocr1b.write(|w| w.bits(now));
     }
+    if uart.tx_bits & 1 == 0 {
+        uart.tx.set_low();
+    } else {
+        uart.tx.set_high();
+    }
+    uart.tx_bits >>= 1;
+    uart.tx_count -= 1;
+    let next = uart.timer.ocr1b.read().bits().wrapping_add(UART_BIT_TICKS);
+    uart.timer.ocr1b.write(|w| w.bits(next));
+}
+
+/// Starts receiving a byte over UART after the falling edge of the start
+/// bit. Pin change interrupts are disabled until the stop bit.
+fn uart_rx_start(uart: &mut Uart) {
+    uart.exint.gimsk.modify(|_, w| w.pcie().clear_bit());
+    uart.rx_bits = Some(0);
+    uart.rx_byte = 0;
+    // The first data bit is sampled in its middle, one and a half bits
+    // after the start bit begins.
+    let now = uart.timer.tcnt1.read().bits();
+    let sample = now.wrapping_add(UART_BIT_TICKS + UART_BIT_TICKS / 2);
+    // Timer1 overflows about once per byte, so the time since the previous
+    // start bit is only known modulo 256 ticks. It's taken as the value
+    // closest to the duration of a byte, which is right for the bytes of the
+    // calibration stream, the only ones where it matters.
+    let elapsed = now.wrapping_sub(uart.rx_start);
+    uart.calibration.deviation = elapsed.wrapping_sub(UART_BYTE_TICKS as u8) as i8;
+    uart.rx_start = now;
+    uart.timer.ocr1a.write(|w| w.bits(sample));
+}
 
-    // Skip stop bit.
-    delay.delay_us(208_u8);
+/// Samples the next bit received over UART, and schedules the following
+/// one. After the stop bit the byte is passed to the frame decoder, and the
+/// decoded packets are queued for the main loop. If the queue is full the
+/// packet is dropped and counted.
+fn uart_rx_bit(uart: &mut Uart) {
+    let Some(bits) = uart.rx_bits else {
+        return;
+    };
+    if bits < 8 {
+        uart.rx_byte >>= 1;
+        if uart.rx.is_high() {
+            uart.rx_byte |= 0b1000_0000;
+        }
+        uart.rx_bits = Some(bits + 1);
+        let next = uart.timer.ocr1a.read().bits().wrapping_add(UART_BIT_TICKS);
+        uart.timer.ocr1a.write(|w| w.bits(next));
+        return;
+    }
+    // The stop bit must be high, otherwise the byte is garbage and so is
+    // the frame it belongs to.
+    let received = uart.rx.is_high().then_some(uart.rx_byte);
+    if let Some(byte) = received {
+        if let Some(packet) = uart.decoder.push(byte) {
+            if uart.received.push(packet).is_err() {
+                uart.rx_overflows = uart.rx_overflows.saturating_add(1);
+            }
+        }
+    } else {
+        uart.decoder.reset();
+    }
+    uart_calibrate(uart, received);
+    uart.rx_bits = None;
+    uart.rx_idle = 0;
+    // Wait for the next start bit. The flag is cleared by writing a one to
+    // it, so that the edges within this byte don't trigger the interrupt.
+    uart.exint.gifr.write(|w| w.pcif().set_bit());
+    uart.exint.gimsk.modify(|_, w| w.pcie().set_bit());
+}
 
-    result
+/// Measures the oscillator's frequency with the calibration stream sent by
+/// the remote unit, which consists of bytes equal to CALIBRATION_BYTE
+/// transmitted back-to-back. The only falling edges in the stream are the
+/// start bits, so the time between them is the duration of a byte even when
+/// the oscillator is too far off for sampling the other bits on time.
+///
+/// Every CALIBRATION_BYTES consecutive calibration bytes a measurement is
+/// left for the main loop. Any other byte, a gap in the stream, or a byte
+/// whose duration deviates by more than 5% start a new one.
+fn uart_calibrate(uart: &mut Uart, received: Option<u8>) {
+    let calibration = &mut uart.calibration;
+    let calibration_byte = received == Some(CALIBRATION_BYTE);
+    let max_deviation = (UART_BYTE_TICKS / 20) as i8;
+    if calibration_byte
+        && calibration.after_byte
+        && (-max_deviation..=max_deviation).contains(&calibration.deviation)
+    {
+        calibration.bytes += 1;
+        calibration.sum += calibration.deviation as i16;
+        if calibration.bytes == CALIBRATION_BYTES {
+            let ticks = (CALIBRATION_BYTES as u16 * UART_BYTE_TICKS) as i16 + calibration.sum;
+            uart.measured = Some(Measurement {
+                error: ticks - CALIBRATION_TICKS,
+                first: !calibration.streaming,
+            });
+            calibration.streaming = true;
+            calibration.bytes = 0;
+            calibration.sum = 0;
+        }
+    } else {
+        calibration.bytes = 0;
+        calibration.sum = 0;
+        calibration.streaming &= calibration_byte;
+    }
+    calibration.after_byte = calibration_byte;
 }
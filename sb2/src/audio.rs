@@ -0,0 +1,250 @@
+//! Recordings of the line made with a sound card.
+//!
+//! The line can be recorded by connecting it to a sound card's input through
+//! a capacitor. At 96kHz the 25kHz bursts are only sampled about four times
+//! per cycle, which is not enough for recovering individual edges, but it's
+//! enough for telling bursts from silences. The envelope detector in
+//! [`bursts`] marks the samples louder than a threshold, and merges those
+//! that are close enough into bursts. The silences between bursts are then
+//! decoded with the same [`Decoder`] used by the repeater's firmware.
+
+use std::fmt;
+
+use simplebus2::pulse::{Decoder, Event, Pulse, Timing};
+
+/// Reasons why a file can't be read as a WAV file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WavError {
+    /// The file doesn't start with a RIFF/WAVE header.
+    NotWav,
+    /// The file ends in the middle of a chunk.
+    Truncated,
+    /// The file doesn't have a `fmt ` or a `data` chunk.
+    MissingChunk(&'static str),
+    /// The sample format is not supported.
+    Unsupported { format: u16, bits: u16 },
+    /// The requested channel doesn't exist.
+    Channel { channel: u16, channels: u16 },
+}
+
+impl fmt::Display for WavError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WavError::NotWav => f.write_str("not a WAV file"),
+            WavError::Truncated => f.write_str("the file is truncated"),
+            WavError::MissingChunk(id) => write!(f, "missing {id:?} chunk"),
+            WavError::Unsupported { format, bits } => {
+                write!(f, "unsupported format {format} with {bits} bits")
+            }
+            WavError::Channel { channel, channels } => {
+                write!(f, "no channel {channel}, the file has {channels}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WavError {}
+
+/// One channel of a WAV file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recording {
+    pub sample_rate: u32,
+    /// Samples, from -1.0 to 1.0.
+    pub samples: Vec<f32>,
+}
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+impl Recording {
+    /// Reads a channel from the contents of a WAV file. Integer PCM samples
+    /// of 8, 16, 24 or 32 bits and 32 or 64-bit floats are supported.
+    pub fn from_wav(bytes: &[u8], channel: u16) -> Result<Self, WavError> {
+        if bytes.get(..4) != Some(b"RIFF") || bytes.get(8..12) != Some(b"WAVE")
+        {
+            return Err(WavError::NotWav);
+        }
+        let mut chunks = &bytes[12..];
+        let mut fmt = None;
+        let mut data = None;
+        while let [a, b, c, d, l0, l1, l2, l3, rest @ ..] = chunks {
+            let len = u32::from_le_bytes([*l0, *l1, *l2, *l3]) as usize;
+            let body = rest.get(..len).ok_or(WavError::Truncated)?;
+            match &[*a, *b, *c, *d] {
+                b"fmt " => fmt = Some(body),
+                b"data" => data = Some(body),
+                _ => {}
+            }
+            // Chunks are padded to an even length.
+            chunks = rest.get(len + len % 2..).unwrap_or_default();
+        }
+        let fmt = fmt.ok_or(WavError::MissingChunk("fmt "))?;
+        let data = data.ok_or(WavError::MissingChunk("data"))?;
+
+        let u16_at = |i: usize| {
+            fmt.get(i..i + 2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .ok_or(WavError::Truncated)
+        };
+        let mut format = u16_at(0)?;
+        let channels = u16_at(2)?;
+        let sample_rate = u32::from(u16_at(4)?) | u32::from(u16_at(6)?) << 16;
+        let bits = u16_at(14)?;
+        if format == FORMAT_EXTENSIBLE {
+            // The actual format is in the first bytes of the sub-format GUID.
+            format = u16_at(24)?;
+        }
+        if sample_rate == 0 {
+            return Err(WavError::NotWav);
+        }
+        if channel >= channels {
+            return Err(WavError::Channel { channel, channels });
+        }
+
+        let width = bits as usize / 8;
+        let sample: fn(&[u8]) -> f32 = match (format, bits) {
+            (FORMAT_PCM, 8) => |b| (b[0] as f32 - 128.0) / 128.0,
+            (FORMAT_PCM, 16) => {
+                |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0
+            }
+            (FORMAT_PCM, 24) => |b| {
+                i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2147483648.0
+            },
+            (FORMAT_PCM, 32) => |b| {
+                i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32
+                    / 2147483648.0
+            },
+            (FORMAT_FLOAT, 32) => {
+                |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])
+            }
+            (FORMAT_FLOAT, 64) => |b| {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(b);
+                f64::from_le_bytes(bytes) as f32
+            },
+            _ => return Err(WavError::Unsupported { format, bits }),
+        };
+        let frame_len = width * channels as usize;
+        let offset = width * channel as usize;
+        let samples = data
+            .chunks_exact(frame_len)
+            .map(|frame| sample(&frame[offset..offset + width]))
+            .collect();
+        Ok(Self { sample_rate, samples })
+    }
+
+    /// Returns the recording's contents as a mono 16-bit WAV file.
+    pub fn to_wav(&self) -> Vec<u8> {
+        let data_len = self.samples.len() as u32 * 2;
+        let mut out = Vec::with_capacity(44 + data_len as usize);
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data_len).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&FORMAT_PCM.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&self.sample_rate.to_le_bytes());
+        out.extend_from_slice(&(self.sample_rate * 2).to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&16u16.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&data_len.to_le_bytes());
+        for sample in &self.samples {
+            let sample = (sample.clamp(-1.0, 1.0) * 32767.0).round() as i16;
+            out.extend_from_slice(&sample.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// Settings of the envelope detector.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Options {
+    /// Samples with an absolute value above this are part of a burst. If
+    /// `None`, half the loudest sample in the recording is used.
+    pub threshold: Option<f32>,
+    /// Samples above the threshold that are closer than this belong to the
+    /// same burst. It must be longer than a 25kHz cycle.
+    pub hold_us: u32,
+    /// Bursts shorter than this are considered noise and discarded.
+    pub min_burst_us: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { threshold: None, hold_us: 100, min_burst_us: 1_000 }
+    }
+}
+
+/// Finds the bursts in a recording. Returns the timestamp, in nanoseconds,
+/// where each burst starts, and the duration of the burst and of the
+/// silence that follows. The silence after the last burst is zero.
+pub fn bursts(recording: &Recording, options: &Options) -> Vec<(u64, Pulse)> {
+    let threshold = options.threshold.unwrap_or_else(|| {
+        recording.samples.iter().fold(0f32, |max, s| max.max(s.abs())) / 2.0
+    });
+    let ns = |sample: usize| {
+        sample as u64 * 1_000_000_000 / recording.sample_rate as u64
+    };
+    let hold_ns = options.hold_us as u64 * 1000;
+    let min_burst_ns = options.min_burst_us as u64 * 1000;
+
+    // Start and end of each burst, in nanoseconds.
+    let mut spans: Vec<(u64, u64)> = Vec::new();
+    let mut current: Option<(u64, u64)> = None;
+    let loud = recording
+        .samples
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| threshold > 0.0 && s.abs() > threshold)
+        .map(|(i, _)| ns(i));
+    for t in loud {
+        match current {
+            Some((start, end)) if t - end <= hold_ns => {
+                current = Some((start, t));
+            }
+            _ => {
+                spans.extend(current.filter(|(s, e)| e - s >= min_burst_ns));
+                current = Some((t, t));
+            }
+        }
+    }
+    spans.extend(current.filter(|(s, e)| e - s >= min_burst_ns));
+
+    spans
+        .iter()
+        .enumerate()
+        .map(|(i, &(start, end))| {
+            let next = spans.get(i + 1).map_or(end, |(next, _)| *next);
+            let pulse = Pulse {
+                burst_us: ((end - start) / 1000) as u32,
+                gap_us: ((next - end) / 1000) as u32,
+            };
+            (start, pulse)
+        })
+        .collect()
+}
+
+/// Decodes the bursts returned by [`bursts`]. Returns the events produced by
+/// the decoder and the timestamp, in nanoseconds, of the burst that
+/// produced each one.
+pub fn decode(bursts: &[(u64, Pulse)], timing: &Timing) -> Vec<(u64, Event)> {
+    let mut decoder = Decoder::new(*timing);
+    let mut events = Vec::new();
+    // Only the silences matter, each one is complete when the next burst
+    // starts.
+    for window in bursts.windows(2) {
+        let [(_, pulse), (next, _)] = window else { unreachable!() };
+        if let Some(event) = decoder.gap(pulse.gap_us) {
+            events.push((*next, event));
+        }
+    }
+    if let Some(event) = decoder.timeout() {
+        let end = bursts
+            .last()
+            .map_or(0, |(start, pulse)| start + pulse.burst_us as u64 * 1000);
+        events.push((end, event));
+    }
+    events
+}
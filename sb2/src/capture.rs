@@ -0,0 +1,353 @@
+//! Logic analyzer captures of the comparator output.
+//!
+//! Captures exported by sigrok/PulseView as CSV or VCD are converted into
+//! the timestamps of the edges in one of the channels, which are then
+//! decoded with the same [`Decoder`] used by the repeater's firmware.
+//!
+//! CSV files may have a time column in seconds (PulseView's export), or one
+//! row per sample and the sample rate in a comment (sigrok-cli's default
+//! output):
+//!
+//! ```text
+//! ; Samplerate: 1 MHz
+//! D0,D1
+//! 0,1
+//! ```
+
+use std::fmt;
+
+use simplebus2::pulse::{Decoder, Event, Timing};
+
+/// Error found while parsing a capture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptureError {
+    /// Line where the error was found, starting at 1. Zero if the error is
+    /// not related to a specific line.
+    pub line: usize,
+    pub message: String,
+}
+
+impl CaptureError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        Self { line, message: message.into() }
+    }
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "line {}: {}", self.line, self.message)
+        } else {
+            f.write_str(&self.message)
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+/// Capture file formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Vcd,
+}
+
+impl Format {
+    /// Guesses the format from a file name's extension.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (_, ext) = path.rsplit_once('.')?;
+        match ext.to_ascii_lowercase().as_str() {
+            "csv" => Some(Format::Csv),
+            "vcd" => Some(Format::Vcd),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the timestamps, in nanoseconds, of the edges in a channel of a
+/// capture. If `channel` is `None` the first channel is used.
+pub fn edges(
+    text: &str,
+    format: Format,
+    channel: Option<&str>,
+) -> Result<Vec<u64>, CaptureError> {
+    match format {
+        Format::Csv => csv_edges(text, channel),
+        Format::Vcd => vcd_edges(text, channel),
+    }
+}
+
+/// Keeps the timestamps where the level changes.
+#[derive(Default)]
+struct EdgeDetector {
+    level: Option<bool>,
+    edges: Vec<u64>,
+}
+
+impl EdgeDetector {
+    fn sample(&mut self, timestamp_ns: u64, level: bool) {
+        if self.level.is_some_and(|l| l != level) {
+            self.edges.push(timestamp_ns);
+        }
+        self.level = Some(level);
+    }
+}
+
+fn csv_edges(
+    text: &str,
+    channel: Option<&str>,
+) -> Result<Vec<u64>, CaptureError> {
+    let mut sample_period_ns = None;
+    let mut columns: Option<(Option<usize>, usize)> = None;
+    let mut detector = EdgeDetector::default();
+    let mut sample = 0u64;
+
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix(';') {
+            if let Some(rate) = comment.trim().strip_prefix("Samplerate:") {
+                let hz = parse_frequency(rate).ok_or_else(|| {
+                    CaptureError::new(
+                        line_no,
+                        format!("invalid sample rate {rate:?}"),
+                    )
+                })?;
+                sample_period_ns = Some(1e9 / hz);
+            }
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let Some((time_col, data_col)) = columns else {
+            columns = Some(csv_header(&fields, channel, line_no)?);
+            continue;
+        };
+        let value = |col: usize| {
+            fields.get(col).copied().ok_or_else(|| {
+                CaptureError::new(
+                    line_no,
+                    format!("missing column {}", col + 1),
+                )
+            })
+        };
+        let timestamp_ns = match time_col {
+            Some(col) => {
+                let seconds: f64 = value(col)?
+                    .parse()
+                    .map_err(|_| CaptureError::new(line_no, "invalid time"))?;
+                (seconds * 1e9).round() as u64
+            }
+            None => {
+                let period = sample_period_ns.ok_or_else(|| {
+                    CaptureError::new(
+                        line_no,
+                        "no time column and no sample rate",
+                    )
+                })?;
+                (sample as f64 * period).round() as u64
+            }
+        };
+        let level = match value(data_col)? {
+            "0" => false,
+            "1" => true,
+            other => {
+                return Err(CaptureError::new(
+                    line_no,
+                    format!("invalid level {other:?}"),
+                ))
+            }
+        };
+        detector.sample(timestamp_ns, level);
+        sample += 1;
+    }
+
+    if columns.is_none() {
+        return Err(CaptureError::new(0, "the capture is empty"));
+    }
+    Ok(detector.edges)
+}
+
+/// Returns the indexes of the time column, if any, and the data column.
+fn csv_header(
+    fields: &[&str],
+    channel: Option<&str>,
+    line_no: usize,
+) -> Result<(Option<usize>, usize), CaptureError> {
+    let time_col =
+        fields.iter().position(|f| f.to_ascii_lowercase().starts_with("time"));
+    let data_col = match channel {
+        Some(channel) => fields.iter().position(|f| *f == channel),
+        None => (0..fields.len()).find(|i| Some(*i) != time_col),
+    };
+    match data_col {
+        Some(data_col) => Ok((time_col, data_col)),
+        None => Err(CaptureError::new(
+            line_no,
+            format!("channel {:?} not found", channel.unwrap_or_default()),
+        )),
+    }
+}
+
+/// Parses a frequency like `"1 MHz"`, `"500kHz"` or `"1000"`.
+fn parse_frequency(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let split =
+        s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let multiplier = match unit.trim() {
+        "" | "Hz" => 1e0,
+        "kHz" => 1e3,
+        "MHz" => 1e6,
+        "GHz" => 1e9,
+        _ => return None,
+    };
+    let hz = number.parse::<f64>().ok()? * multiplier;
+    (hz > 0.0).then_some(hz)
+}
+
+fn vcd_edges(
+    text: &str,
+    channel: Option<&str>,
+) -> Result<Vec<u64>, CaptureError> {
+    let mut tokens = text.lines().enumerate().flat_map(|(i, line)| {
+        line.split_whitespace().map(move |token| (i + 1, token))
+    });
+
+    let mut timescale_ns = 1.0;
+    let mut id = None;
+    let mut now = 0u64;
+    let mut detector = EdgeDetector::default();
+
+    while let Some((line_no, token)) = tokens.next() {
+        match token {
+            "$timescale" => {
+                let mut scale = String::new();
+                for (_, token) in tokens.by_ref() {
+                    if token == "$end" {
+                        break;
+                    }
+                    scale.push_str(token);
+                }
+                timescale_ns = parse_timescale(&scale).ok_or_else(|| {
+                    CaptureError::new(
+                        line_no,
+                        format!("invalid timescale {scale:?}"),
+                    )
+                })?;
+            }
+            "$var" => {
+                // $var <type> <size> <id> <name> [range] $end
+                let fields: Vec<&str> = tokens
+                    .by_ref()
+                    .map(|(_, token)| token)
+                    .take_while(|token| *token != "$end")
+                    .collect();
+                let [_, _, var_id, name, ..] = fields[..] else {
+                    return Err(CaptureError::new(line_no, "invalid $var"));
+                };
+                if id.is_none() && channel.is_none_or(|c| c == name) {
+                    id = Some(var_id.to_string());
+                }
+            }
+            token if token.starts_with('$') => {
+                // Other sections are skipped. $dumpvars and friends contain
+                // value changes, so only their keywords are skipped.
+                if !matches!(
+                    token,
+                    "$dumpvars" | "$dumpall" | "$dumpon" | "$dumpoff" | "$end"
+                ) {
+                    for (_, token) in tokens.by_ref() {
+                        if token == "$end" {
+                            break;
+                        }
+                    }
+                }
+            }
+            token if token.starts_with('#') => {
+                let time: u64 = token[1..].parse().map_err(|_| {
+                    CaptureError::new(
+                        line_no,
+                        format!("invalid time {token:?}"),
+                    )
+                })?;
+                now = (time as f64 * timescale_ns).round() as u64;
+            }
+            token => {
+                let Some(id) = &id else {
+                    return Err(CaptureError::new(
+                        line_no,
+                        format!(
+                            "channel {:?} not found",
+                            channel.unwrap_or_default()
+                        ),
+                    ));
+                };
+                // Scalar changes look like "1!", vector changes like "b1 !".
+                let (value, var_id) = match token.strip_prefix(['b', 'B']) {
+                    Some(value) => {
+                        let var_id =
+                            tokens.next().map(|(_, t)| t).unwrap_or("");
+                        (value, var_id)
+                    }
+                    None => token.split_at(1),
+                };
+                if var_id != id {
+                    continue;
+                }
+                match value.trim_start_matches('0') {
+                    "" => detector.sample(now, false),
+                    "1" => detector.sample(now, true),
+                    // Unknown and high impedance values are ignored.
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    if id.is_none() {
+        return Err(CaptureError::new(
+            0,
+            format!("channel {:?} not found", channel.unwrap_or_default()),
+        ));
+    }
+    Ok(detector.edges)
+}
+
+/// Parses a timescale like `"1us"` and returns it in nanoseconds.
+fn parse_timescale(s: &str) -> Option<f64> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let multiplier = match unit {
+        "s" => 1e9,
+        "ms" => 1e6,
+        "us" => 1e3,
+        "ns" => 1e0,
+        "ps" => 1e-3,
+        "fs" => 1e-6,
+        _ => return None,
+    };
+    Some(number.parse::<f64>().ok()? * multiplier)
+}
+
+/// Decodes the edges in a capture. Returns the events produced by the
+/// decoder and the timestamp, in nanoseconds, of the edge that produced
+/// each one.
+pub fn decode(edges: &[u64], timing: &Timing) -> Vec<(u64, Event)> {
+    let mut decoder = Decoder::new(*timing);
+    let mut events = Vec::new();
+    for &timestamp_ns in edges {
+        // The decoder works with microseconds, wrapping around every 71
+        // minutes, like the firmware's timer.
+        let timestamp_us = (timestamp_ns / 1000) as u32;
+        if let Some(event) = decoder.edge(timestamp_us) {
+            events.push((timestamp_ns, event));
+        }
+    }
+    if let Some(event) = decoder.timeout() {
+        events.push((edges.last().copied().unwrap_or_default(), event));
+    }
+    events
+}
@@ -0,0 +1,107 @@
+//! ASCII timing diagrams of the signals on the bus.
+//!
+//! Bursts are drawn with `#` and silences with `_`, one character per
+//! [`Options::us_per_char`] microseconds. Below the signal, each bit's value
+//! is shown under the burst that starts it, and the name of each field under
+//! its first bit:
+//!
+//! ```text
+//! ###_________________###___###___###___###___###______###______###___###___
+//! preamble            0     0     0     0     1        1        0     0
+//!                     code                                      address
+//! ```
+
+use simplebus2::pulse::Pulse;
+
+use crate::wire::Meaning;
+
+/// Diagram settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Options {
+    /// Microseconds represented by each character.
+    pub us_per_char: u32,
+    /// Maximum line length. Longer diagrams are split in several blocks.
+    pub width: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { us_per_char: 1000, width: 80 }
+    }
+}
+
+/// Draws the given pulses, as returned by [`crate::wire::pulses`].
+pub fn render(pulses: &[(Pulse, Meaning)], options: &Options) -> String {
+    let mut signal = Vec::new();
+    let mut values = Vec::new();
+    let mut fields = Vec::new();
+
+    for (pulse, meaning) in pulses {
+        let col = signal.len();
+        match meaning {
+            Meaning::Preamble => put(&mut values, col, "preamble"),
+            Meaning::Bit { field, index, value } => {
+                put(&mut values, col, if *value { "1" } else { "0" });
+                if *index == 0 {
+                    put(&mut fields, col, field.name());
+                }
+            }
+            Meaning::End => {}
+            Meaning::Ack(0) => put(&mut values, col, "ack"),
+            Meaning::Ack(_) => {}
+        }
+        let burst = chars(pulse.burst_us, options.us_per_char);
+        let gap = chars(pulse.gap_us, options.us_per_char);
+        signal.extend(std::iter::repeat_n('#', burst));
+        signal.extend(std::iter::repeat_n('_', gap));
+    }
+
+    let width = options.width.max(1);
+    let mut out = String::new();
+    for start in (0..signal.len()).step_by(width) {
+        if start > 0 {
+            out.push('\n');
+        }
+        let lines: Vec<String> = [&signal, &values, &fields]
+            .iter()
+            .map(|row| {
+                let end = (start + width).min(row.len());
+                let line: String =
+                    row.get(start..end).unwrap_or_default().iter().collect();
+                line.trim_end().to_string()
+            })
+            .collect();
+        // Empty rows at the bottom of the block are omitted.
+        let rows =
+            lines.iter().rposition(|l| !l.is_empty()).map_or(0, |i| i + 1);
+        for line in &lines[..rows] {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Number of characters for a duration, rounded to the nearest integer but
+/// never zero for non-zero durations.
+fn chars(us: u32, us_per_char: u32) -> usize {
+    let us_per_char = us_per_char.max(1);
+    if us == 0 {
+        0
+    } else {
+        ((us + us_per_char / 2) / us_per_char).max(1) as usize
+    }
+}
+
+/// Writes `text` in `row` starting at column `col`, padding with spaces.
+fn put(row: &mut Vec<char>, col: usize, text: &str) {
+    if row.len() < col {
+        row.resize(col, ' ');
+    }
+    for (i, c) in text.chars().enumerate() {
+        match row.get_mut(col + i) {
+            Some(existing) => *existing = c,
+            None => row.push(c),
+        }
+    }
+}
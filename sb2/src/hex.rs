@@ -0,0 +1,49 @@
+//! Hexadecimal bytes, as typed by users and printed by the tool.
+
+use std::fmt;
+
+/// Error returned by [`parse`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseHexError(String);
+
+impl fmt::Display for ParseHexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid hexadecimal bytes: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseHexError {}
+
+/// Parses bytes written in hexadecimal.
+///
+/// Bytes can be written together (`"000343"`) or separated by spaces,
+/// commas or colons (`"00 03 43"`, `"00:03:43"`), and each group may have a
+/// `0x` prefix (`"0x00, 0x03, 0x43"`).
+pub fn parse(s: &str) -> Result<Vec<u8>, ParseHexError> {
+    let mut bytes = Vec::new();
+    for group in s.split(|c: char| c.is_whitespace() || c == ',' || c == ':') {
+        if group.is_empty() {
+            continue;
+        }
+        let digits = group
+            .strip_prefix("0x")
+            .or_else(|| group.strip_prefix("0X"))
+            .unwrap_or(group);
+        if digits.is_empty()
+            || digits.len() % 2 != 0
+            || !digits.bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            return Err(ParseHexError(group.to_string()));
+        }
+        for i in (0..digits.len()).step_by(2) {
+            // All the digits were validated above.
+            bytes.push(u8::from_str_radix(&digits[i..i + 2], 16).unwrap());
+        }
+    }
+    Ok(bytes)
+}
+
+/// Formats bytes as hexadecimal, separated by spaces.
+pub fn format(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>().join(" ")
+}
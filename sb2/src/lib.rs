@@ -0,0 +1,20 @@
+//! Host-side tooling for SimpleBus2, used by the `sb2` command-line tool.
+//!
+//! The protocol itself lives in the [`simplebus2`] crate, which is shared
+//! with the firmwares. This crate contains the parts that only make sense on
+//! a laptop: parsing and formatting hexadecimal bytes, human-readable
+//! reports, ASCII timing diagrams, decoding logic analyzer captures and
+//! sound card recordings, simulating a bus, and recording and replaying
+//! the traffic seen on it.
+
+pub mod audio;
+pub mod capture;
+pub mod diagram;
+pub mod hex;
+pub mod report;
+pub mod sim;
+pub mod traffic;
+pub mod wire;
+
+#[cfg(test)]
+mod tests;
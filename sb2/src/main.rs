@@ -0,0 +1,474 @@
+//! `sb2`: encode, decode and inspect SimpleBus2 messages from the command
+//! line.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::process::{self, ExitCode};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand, ValueEnum};
+use rumqttc::{
+    Client, Event as MqttEvent, MqttOptions, Packet as MqttPacket, QoS,
+    RecvTimeoutError,
+};
+use serde::Deserialize;
+
+use sb2::audio::{self, Recording};
+use sb2::capture::{self, Format};
+use sb2::diagram::{self, Options};
+use sb2::traffic::{self, Direction, Record, Recorder};
+use sb2::{hex, report, wire};
+use simplebus2::pulse::{Event, Timing};
+use simplebus2::{Code, Frame, FrameDecoder, Message, Packet};
+
+#[derive(Parser)]
+#[command(version, about = "Encode, decode and inspect SimpleBus2 messages")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the known message codes.
+    Codes,
+    /// Show the UART bytes and the wire timings of a message.
+    Encode {
+        /// Message code, either a name (e.g: call) or a number.
+        #[arg(value_parser = parse_code)]
+        code: Code,
+        /// Intercom address.
+        address: u8,
+        /// Include the acknowledgement sent by the target intercom.
+        #[arg(long)]
+        ack: bool,
+    },
+    /// Decode the bytes of a UART frame, written in hexadecimal.
+    Decode {
+        /// Bytes, like "00 03 43" or "000343".
+        #[arg(required = true)]
+        hex: Vec<String>,
+    },
+    /// Check the checksum of a message.
+    Verify {
+        /// Message code, either a name (e.g: call) or a number.
+        #[arg(value_parser = parse_code)]
+        code: Code,
+        /// Intercom address.
+        address: u8,
+        /// Checksum to verify.
+        checksum: u8,
+    },
+    /// Draw an ASCII timing diagram of a message.
+    Diagram {
+        /// Message code, either a name (e.g: call) or a number.
+        #[arg(value_parser = parse_code)]
+        code: Code,
+        /// Intercom address.
+        address: u8,
+        /// Include the acknowledgement sent by the target intercom.
+        #[arg(long)]
+        ack: bool,
+        /// Microseconds per character.
+        #[arg(long, default_value_t = 1000)]
+        scale: u32,
+        /// Maximum line length.
+        #[arg(long, default_value_t = 80)]
+        width: usize,
+    },
+    /// Decode the messages in a logic analyzer capture of the comparator's
+    /// output, exported by sigrok/PulseView as CSV or VCD.
+    Capture {
+        /// Capture file.
+        file: String,
+        /// File format. By default it's guessed from the file's extension.
+        #[arg(long, value_enum)]
+        format: Option<CaptureFormat>,
+        /// Channel to decode (e.g: D0). By default the first one.
+        #[arg(long)]
+        channel: Option<String>,
+        /// Maximum difference between the expected and the measured
+        /// silences, in microseconds.
+        #[arg(long, default_value_t = Timing::DEFAULT.tolerance_us)]
+        tolerance: u32,
+    },
+    /// Decode the messages in a WAV recording of the line made with a sound
+    /// card.
+    Audio {
+        /// WAV file.
+        file: String,
+        /// Channel to decode, starting at 0.
+        #[arg(long, default_value_t = 0)]
+        channel: u16,
+        /// Samples louder than this, from 0.0 to 1.0, are part of a burst. By
+        /// default half the loudest sample in the recording.
+        #[arg(long)]
+        threshold: Option<f32>,
+        /// Maximum difference between the expected and the measured
+        /// silences, in microseconds.
+        #[arg(long, default_value_t = Timing::DEFAULT.tolerance_us)]
+        tolerance: u32,
+        /// Print the duration of every burst and silence found.
+        #[arg(long)]
+        bursts: bool,
+    },
+    /// Record the traffic published over MQTT by the remote unit or the
+    /// serial bridge into a traffic log.
+    Record {
+        /// Traffic log to write.
+        file: String,
+        /// MQTT broker host.
+        #[arg(long)]
+        broker: String,
+        /// MQTT broker port.
+        #[arg(long, default_value_t = 1883)]
+        broker_port: u16,
+        /// Prefix of the MQTT topics.
+        #[arg(long, default_value = "plusvic/intercom")]
+        topic: String,
+        /// Address of our intercom, which commands are sent with.
+        #[arg(long, default_value_t = 12)]
+        address: u8,
+    },
+    /// Play back a traffic log into a simulated bus, or into the remote
+    /// unit's UART.
+    Replay {
+        /// Traffic log.
+        file: String,
+        /// Serial port connected to the remote unit, for example through an
+        /// HC-12 module. Messages are sent as the repeater would, and the
+        /// messages sent by the remote unit are printed. By default the
+        /// traffic is played into a simulated bus, and the messages decoded
+        /// from it are printed.
+        #[arg(long)]
+        port: Option<String>,
+        /// Playback speed when replaying into a serial port.
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+    },
+}
+
+/// Acknowledgement report published by the remote unit and the serial
+/// bridge.
+#[derive(Deserialize)]
+struct AckReport {
+    message: Message,
+    acknowledged: bool,
+}
+
+/// Time between a message and its acknowledgement when replaying into a
+/// serial port.
+const REPLAY_ACK_DELAY_MS: u64 = 50;
+
+/// Something done while replaying into a serial port.
+enum Step {
+    /// Send a frame to the remote unit.
+    Send(Frame),
+    /// The remote unit is expected to send a message.
+    Expect(Message),
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CaptureFormat {
+    Csv,
+    Vcd,
+}
+
+impl From<CaptureFormat> for Format {
+    fn from(format: CaptureFormat) -> Self {
+        match format {
+            CaptureFormat::Csv => Format::Csv,
+            CaptureFormat::Vcd => Format::Vcd,
+        }
+    }
+}
+
+fn parse_code(s: &str) -> Result<Code, String> {
+    let code: Code = s.parse().map_err(|_| {
+        format!("unknown code {s:?}, use `sb2 codes` for listing them")
+    })?;
+    if code.as_u8() > 0b11_1111 {
+        return Err("codes are 6 bits long, from 0 to 63".into());
+    }
+    Ok(code)
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> anyhow::Result<()> {
+    match cli.command {
+        Command::Codes => print!("{}", report::codes()),
+        Command::Encode { code, address, ack } => {
+            let message = Message::new(code, address);
+            print!("{}", report::encode(&message, &Timing::DEFAULT, ack));
+        }
+        Command::Decode { hex } => {
+            let bytes = hex::parse(&hex.join(" "))?;
+            let packet = report::decode(&bytes).with_context(|| {
+                format!("{} is not valid", hex::format(&bytes))
+            })?;
+            print!("{}", report::packet(&packet));
+        }
+        Command::Verify { code, address, checksum } => {
+            let expected = Message::new(code, address).checksum();
+            if checksum != expected {
+                bail!("checksum is {checksum}, expecting {expected}");
+            }
+            println!("checksum is correct");
+        }
+        Command::Diagram { code, address, ack, scale, width } => {
+            let message = Message::new(code, address);
+            let pulses = wire::pulses(&message, &Timing::DEFAULT, ack);
+            let options = Options { us_per_char: scale, width };
+            print!("{}", diagram::render(&pulses, &options));
+        }
+        Command::Capture { file, format, channel, tolerance } => {
+            let format = match format {
+                Some(format) => format.into(),
+                None => Format::from_path(&file).with_context(|| {
+                    format!("unknown format for {file}, use --format")
+                })?,
+            };
+            let text = fs::read_to_string(&file)
+                .with_context(|| format!("can't read {file}"))?;
+            let edges = capture::edges(&text, format, channel.as_deref())
+                .with_context(|| format!("can't parse {file}"))?;
+            let timing = Timing { tolerance_us: tolerance, ..Timing::DEFAULT };
+            print_events(&capture::decode(&edges, &timing));
+        }
+        Command::Audio { file, channel, threshold, tolerance, bursts } => {
+            let bytes = fs::read(&file)
+                .with_context(|| format!("can't read {file}"))?;
+            let recording = Recording::from_wav(&bytes, channel)
+                .with_context(|| format!("can't parse {file}"))?;
+            let options = audio::Options { threshold, ..Default::default() };
+            let found = audio::bursts(&recording, &options);
+            if bursts {
+                for (timestamp_ns, pulse) in &found {
+                    println!(
+                        "{:>14.6}s  burst {:<8} silence {}",
+                        *timestamp_ns as f64 / 1e9,
+                        wire::format_us(pulse.burst_us),
+                        wire::format_us(pulse.gap_us)
+                    );
+                }
+            } else {
+                let timing =
+                    Timing { tolerance_us: tolerance, ..Timing::DEFAULT };
+                print_events(&audio::decode(&found, &timing));
+            }
+        }
+        Command::Record { file, broker, broker_port, topic, address } => {
+            let out = File::create(&file)
+                .with_context(|| format!("can't create {file}"))?;
+            let recorder = Recorder::new(BufWriter::new(out))?;
+            let options = MqttOptions::new(
+                format!("sb2-record-{}", process::id()),
+                broker,
+                broker_port,
+            );
+            record(recorder, options, &topic, address)?;
+        }
+        Command::Replay { file, port, speed } => {
+            let text = fs::read_to_string(&file)
+                .with_context(|| format!("can't read {file}"))?;
+            let records = traffic::parse(&text)
+                .with_context(|| format!("can't parse {file}"))?;
+            match port {
+                Some(path) => {
+                    let port = serialport::new(&path, 4800)
+                        .timeout(Duration::from_millis(10))
+                        .open()
+                        .with_context(|| format!("can't open {path}"))?;
+                    replay(&records, port, speed)?;
+                }
+                None => {
+                    let bus = traffic::simulate(&records, Timing::DEFAULT);
+                    print_events(&capture::decode(
+                        &bus.run(),
+                        &Timing::DEFAULT,
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Records the messages and acknowledgements published over MQTT, and the
+/// commands sent to our intercom. Runs until interrupted.
+fn record<W: Write>(
+    mut recorder: Recorder<W>,
+    options: MqttOptions,
+    prefix: &str,
+    address: u8,
+) -> anyhow::Result<()> {
+    let messages = format!("{prefix}/messages");
+    let acks = format!("{prefix}/acks");
+    let commands = format!("{prefix}/commands");
+    let (client, mut connection) = Client::new(options, 10);
+    let start = Instant::now();
+
+    loop {
+        let event = connection.recv_timeout(Duration::from_millis(100));
+        let now_ms = start.elapsed().as_millis() as u64;
+        match event {
+            Ok(Ok(MqttEvent::Incoming(MqttPacket::ConnAck(_)))) => {
+                eprintln!("Connected to MQTT broker, recording");
+                for topic in [&messages, &acks, &commands] {
+                    client.subscribe(topic, QoS::AtLeastOnce)?;
+                }
+            }
+            Ok(Ok(MqttEvent::Incoming(MqttPacket::Publish(publish)))) => {
+                let record = if publish.topic == messages {
+                    let Ok(message) = serde_json::from_slice(&publish.payload)
+                    else {
+                        continue;
+                    };
+                    Record::new(now_ms, Direction::Rx, message)
+                } else if publish.topic == commands {
+                    let Some(code) = std::str::from_utf8(&publish.payload)
+                        .ok()
+                        .and_then(Code::from_name)
+                    else {
+                        continue;
+                    };
+                    let message = Message::new(code, address);
+                    Record::new(now_ms, Direction::Tx, message)
+                } else {
+                    if let Ok(AckReport { message, acknowledged: true }) =
+                        serde_json::from_slice(&publish.payload)
+                    {
+                        recorder.ack(&message);
+                    }
+                    continue;
+                };
+                println!("{record}");
+                recorder.record(record)?;
+            }
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+            // The connection is retried in the next iteration.
+            Ok(Err(err)) => {
+                eprintln!("MQTT error: {err}");
+                thread::sleep(Duration::from_secs(1));
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        recorder.flush(now_ms)?;
+    }
+    recorder.finish()?;
+    Ok(())
+}
+
+/// Sends the messages seen on the bus and their acknowledgements to the
+/// remote unit, the way the repeater does, and prints the messages the
+/// remote unit sends back. Messages sent by us in the recording are printed
+/// when they are expected.
+fn replay<P: Read + Write>(
+    records: &[Record],
+    mut port: P,
+    speed: f64,
+) -> anyhow::Result<()> {
+    let mut decoder = FrameDecoder::new();
+    let start = Instant::now();
+    let mut byte = [0u8];
+
+    // Waits until `time_ms` in the recording, handling the packets received
+    // in the meantime.
+    let mut wait = |port: &mut P, time_ms: u64| -> anyhow::Result<()> {
+        let deadline =
+            start + Duration::from_secs_f64(time_ms as f64 / 1e3 / speed);
+        while Instant::now() < deadline {
+            match port.read(&mut byte) {
+                Ok(0) => bail!("serial port closed"),
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::TimedOut => continue,
+                Err(err) => return Err(err.into()),
+            }
+            let (frame, reply) = match decoder.push(byte[0]) {
+                Some(Packet::Plain(frame)) => (frame, None),
+                Some(Packet::Sequenced { seq, frame }) => (
+                    frame,
+                    Some(Packet::LinkAck { seq, message: *frame.message() }),
+                ),
+                _ => continue,
+            };
+            if let Some(reply) = reply {
+                port.write_all(&reply.to_raw_bytes())?;
+            }
+            let elapsed = start.elapsed().as_secs_f64() * speed;
+            println!(
+                "{elapsed:>10.3}s  remote    {}",
+                report::event(&Event::Message(*frame.message()))
+            );
+        }
+        Ok(())
+    };
+
+    // Messages sent by us are not sent, they are expected from the remote
+    // unit. Their acknowledgements are sent, as the repeater relays them.
+    let mut steps = Vec::new();
+    for record in records {
+        let message = record.message;
+        steps.push(match record.direction {
+            Direction::Rx => {
+                (record.time_ms, Step::Send(Frame::Message(message)))
+            }
+            Direction::Tx => (record.time_ms, Step::Expect(message)),
+        });
+        if record.acknowledged {
+            let time_ms = record.time_ms + REPLAY_ACK_DELAY_MS;
+            steps.push((time_ms, Step::Send(Frame::Ack(message))));
+        }
+    }
+    steps.sort_by_key(|(time_ms, _)| *time_ms);
+
+    for (time_ms, step) in steps {
+        wait(&mut port, time_ms)?;
+        let seconds = time_ms as f64 / 1e3;
+        match step {
+            Step::Send(frame) => {
+                port.write_all(&Packet::Plain(frame).to_raw_bytes())?;
+                let event = match frame {
+                    Frame::Ack(message) => Event::Ack(message),
+                    _ => Event::Message(*frame.message()),
+                };
+                println!(
+                    "{seconds:>10.3}s  sent      {}",
+                    report::event(&event)
+                );
+            }
+            Step::Expect(message) => {
+                let event = Event::Message(message);
+                println!(
+                    "{seconds:>10.3}s  expected  {}",
+                    report::event(&event)
+                );
+            }
+        }
+    }
+
+    // Give the remote unit some time for reacting to the last messages.
+    let end_ms = records.last().map_or(0, |record| record.time_ms);
+    wait(&mut port, end_ms + traffic::ACK_WINDOW_MS)
+}
+
+fn print_events(events: &[(u64, Event)]) {
+    for (timestamp_ns, event) in events {
+        println!(
+            "{:>14.6}s  {}",
+            *timestamp_ns as f64 / 1e9,
+            report::event(event)
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! Human-readable descriptions of messages and frames.
+
+use std::fmt;
+use std::fmt::Write;
+
+use simplebus2::pulse::{Event, Timing};
+use simplebus2::{Code, Frame, Message, Packet};
+
+use crate::hex;
+use crate::wire::{self, Meaning};
+
+/// Describes a message's code, address and checksum.
+pub fn message(message: &Message) -> String {
+    format!(
+        "{} ({}), address {}, checksum {}",
+        message.code.name().unwrap_or("unknown"),
+        message.code.as_u8(),
+        message.address,
+        message.checksum()
+    )
+}
+
+/// Returns the bits of a message in the order they are transmitted, with
+/// the fields separated by spaces.
+pub fn bits(message: &Message) -> String {
+    raw_bits(message.to_bits(), Message::NUM_BITS)
+}
+
+/// Same as [`bits`], for the first `count` bits received in a message that
+/// may not be valid.
+fn raw_bits(bits: u32, count: u8) -> String {
+    (0..count)
+        .flat_map(|i| {
+            let sep = if i == 6 || i == 14 { Some(' ') } else { None };
+            let bit = if bits & (1 << i) != 0 { '1' } else { '0' };
+            sep.into_iter().chain([bit])
+        })
+        .collect()
+}
+
+/// Describes everything that is transmitted for a message: the bytes sent
+/// over UART and the pulses on the bus.
+pub fn encode(message: &Message, timing: &Timing, ack: bool) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "message   {}", self::message(message));
+    let _ =
+        writeln!(out, "bits      {} (in transmission order)", bits(message));
+    let _ =
+        writeln!(out, "uart      {}", hex::format(&message.to_raw_bytes()));
+    let _ = writeln!(out, "wire      burst  silence");
+    let mut total_us = 0;
+    for (pulse, meaning) in wire::pulses(message, timing, ack) {
+        total_us += pulse.burst_us + pulse.gap_us;
+        let gap = if pulse.gap_us > 0 {
+            wire::format_us(pulse.gap_us)
+        } else {
+            "".into()
+        };
+        let _ = writeln!(
+            out,
+            "          {:<6} {:<8} {}",
+            wire::format_us(pulse.burst_us),
+            gap,
+            describe(meaning)
+        );
+    }
+    let _ = writeln!(out, "duration  {}", wire::format_us(total_us));
+    out
+}
+
+fn describe(meaning: Meaning) -> String {
+    match meaning {
+        Meaning::Preamble => "preamble".into(),
+        Meaning::Bit { field, index, value } => {
+            format!("{} bit {} = {}", field.name(), index, value as u8)
+        }
+        Meaning::End => "end".into(),
+        Meaning::Ack(n) => format!("acknowledgement {}", n + 1),
+    }
+}
+
+/// Describes a packet received over UART.
+pub fn packet(packet: &Packet) -> String {
+    let (kind, frame) = match packet {
+        Packet::Plain(frame) => (String::new(), frame),
+        Packet::Sequenced { seq, frame } => {
+            (format!(", sequence {seq}"), frame)
+        }
+        Packet::LinkAck { seq, message } => {
+            return format!(
+                "kind      link acknowledgement, sequence {seq}\n\
+                 message   {}\n",
+                self::message(message)
+            );
+        }
+    };
+    let mut out = String::new();
+    match frame {
+        Frame::Message(msg) => {
+            let _ = writeln!(out, "kind      message{kind}");
+            let _ = writeln!(out, "message   {}", self::message(msg));
+        }
+        Frame::Ack(msg) => {
+            let _ = writeln!(out, "kind      bus acknowledgement{kind}");
+            let _ = writeln!(out, "message   {}", self::message(msg));
+        }
+        Frame::NoAck(msg) => {
+            let _ = writeln!(out, "kind      bus transmission failed{kind}");
+            let _ = writeln!(out, "message   {}", self::message(msg));
+        }
+        Frame::Authenticated(auth) => {
+            let _ = writeln!(out, "kind      authenticated message{kind}");
+            let _ =
+                writeln!(out, "message   {}", self::message(&auth.message));
+            let _ = writeln!(out, "counter   {}", auth.counter);
+            let _ = writeln!(out, "tag       {:016x}", auth.tag);
+        }
+    }
+    out
+}
+
+/// Describes an event produced by the pulse decoder.
+pub fn event(event: &Event) -> String {
+    match event {
+        Event::Message(msg) => format!("message    {}", message(msg)),
+        Event::Ack(msg) => format!("ack        {}", message(msg)),
+        Event::NoAck(msg) => format!("no ack     {}", message(msg)),
+        Event::BadChecksum(bits) => {
+            format!(
+                "malformed  bad checksum, bits {}",
+                raw_bits(*bits, Message::NUM_BITS)
+            )
+        }
+        Event::Truncated { count: 0, .. } => {
+            "malformed  truncated after the preamble".into()
+        }
+        Event::Truncated { bits, count } => {
+            format!(
+                "malformed  truncated after {count} bits {}",
+                raw_bits(*bits, *count)
+            )
+        }
+    }
+}
+
+/// Reasons why bytes received over UART are not a valid packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The packet's length doesn't match its kind.
+    Length { expected: usize, got: usize },
+    /// The message is all zeros, which is what a stuck line produces.
+    AllZeros,
+    /// The checksum doesn't match the code and the address.
+    Checksum { code: u8, address: u8, checksum: u8, expected: u8 },
+    /// The kind bits in the first byte are not valid.
+    Kind(u8),
+    /// The byte that follows an acknowledgement is not a valid result.
+    Result(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Length { expected, got } => {
+                write!(f, "expecting {expected} bytes, got {got}")
+            }
+            DecodeError::AllZeros => f.write_str("the message is all zeros"),
+            DecodeError::Checksum { code, address, checksum, expected } => {
+                write!(
+                    f,
+                    "code {code}, address {address}: checksum is {checksum}, \
+                     expecting {expected}"
+                )
+            }
+            DecodeError::Kind(kind) => {
+                write!(f, "invalid kind bits {kind:06b}")
+            }
+            DecodeError::Result(result) => {
+                write!(f, "invalid acknowledgement result {result:02x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decodes the bytes of a packet, explaining what's wrong if they are not
+/// valid.
+pub fn decode(bytes: &[u8]) -> Result<Packet, DecodeError> {
+    if let Some(packet) = Packet::from_raw_bytes(bytes) {
+        return Ok(packet);
+    }
+    let [b0, b1, b2, ..] = *bytes else {
+        return Err(DecodeError::Length {
+            expected: Frame::LEN,
+            got: bytes.len(),
+        });
+    };
+    let word = u32::from_le_bytes([b0, b1, b2, 0]);
+    let code = ((word >> 6) & 0b11_1111) as u8;
+    let address = ((word >> 12) & 0b1111_1111) as u8;
+    let checksum = ((word >> 20) & 0b1111) as u8;
+    if word >> 6 == 0 {
+        return Err(DecodeError::AllZeros);
+    }
+    let expected = Message::new(Code::from_u8(code), address).checksum();
+    if checksum != expected {
+        return Err(DecodeError::Checksum {
+            code,
+            address,
+            checksum,
+            expected,
+        });
+    }
+    // The message is valid, so either the kind or the length is wrong. See
+    // the layout of the kind bits in `Packet`.
+    let kind = b0 & 0b11_1111;
+    let sequenced = kind & 0b10_0000 != 0;
+    if !sequenced && (kind & 0b1_1100 != 0 || kind & 0b11 == 3) {
+        return Err(DecodeError::Kind(kind));
+    }
+    let expected = match kind & 0b11 {
+        1 if bytes.len() == Frame::ACK_LEN => {
+            return Err(DecodeError::Result(bytes[3]))
+        }
+        1 => Frame::ACK_LEN,
+        2 => Frame::MAX_LEN,
+        _ => Frame::LEN,
+    };
+    Err(DecodeError::Length { expected, got: bytes.len() })
+}
+
+/// Lists the known codes with their descriptions.
+pub fn codes() -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "code  name              description");
+    for code in Code::KNOWN {
+        let _ = writeln!(
+            out,
+            "{:>4}  {:<16}  {}",
+            code.as_u8(),
+            code.name().unwrap_or_default(),
+            code.description().unwrap_or_default()
+        );
+    }
+    out
+}
@@ -0,0 +1,230 @@
+//! Simulation of a SimpleBus2 line with several devices connected to it.
+//!
+//! The simulated [`Bus`] contains intercoms with their DIP switch addresses
+//! and an entry panel. Messages are scheduled at arbitrary times, and
+//! [`Bus::run`] returns the edges that the repeater's comparator would
+//! produce, which can be decoded with [`crate::capture::decode`] or fed one
+//! by one into a [`Decoder`].
+//!
+//! Bursts sent by different devices at the same time are merged, as they
+//! would be on the real line, so overlapping messages collide and are not
+//! received by anyone. Intercoms decode the line with the same [`Decoder`]
+//! used by the firmwares, and acknowledge the messages addressed to them
+//! that they receive correctly.
+
+use simplebus2::pulse::{self, Decoder, Event, Timing};
+use simplebus2::{Code, Message};
+
+/// An intercom connected to the bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Intercom {
+    /// Address configured in the DIP switch.
+    pub address: u8,
+    /// Whether the intercom acknowledges the messages addressed to it.
+    pub acknowledges: bool,
+}
+
+impl Intercom {
+    pub const fn new(address: u8) -> Self {
+        Self { address, acknowledges: true }
+    }
+}
+
+/// Disturbances added to the line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Noise {
+    /// Average number of spurious 20µs pulses per second.
+    pub glitches_per_s: u32,
+    /// Maximum deviation of the start and the end of every burst.
+    pub jitter_us: u32,
+    /// Seed of the random number generator, the same seed always produces
+    /// the same noise.
+    pub seed: u64,
+}
+
+/// Duration of a cycle of the 25kHz carrier.
+const CYCLE_US: u64 = 40;
+
+/// A simulated bus.
+#[derive(Clone, Debug)]
+pub struct Bus {
+    timing: Timing,
+    intercoms: Vec<Intercom>,
+    noise: Noise,
+    /// Interval between the messages sent by the entry panel during a call.
+    ring_interval_us: u64,
+    /// Start and end of the bursts sent so far, in microseconds.
+    bursts: Vec<(u64, u64)>,
+    rng: u64,
+}
+
+impl Bus {
+    /// Default interval between the messages in a call.
+    pub const RING_INTERVAL_US: u64 = 4_000_000;
+
+    pub fn new(timing: Timing) -> Self {
+        Self {
+            timing,
+            intercoms: Vec::new(),
+            noise: Noise::default(),
+            ring_interval_us: Self::RING_INTERVAL_US,
+            bursts: Vec::new(),
+            rng: 1,
+        }
+    }
+
+    /// Connects an intercom to the bus.
+    pub fn add(&mut self, intercom: Intercom) -> &mut Self {
+        self.intercoms.push(intercom);
+        self
+    }
+
+    /// Sets the noise added to the line. This must be called before sending
+    /// any message, as the jitter is applied when bursts are sent.
+    pub fn set_noise(&mut self, noise: Noise) -> &mut Self {
+        self.noise = noise;
+        // Xorshift gets stuck at zero.
+        self.rng = (noise.seed ^ 0x9e37_79b9_7f4a_7c15).max(1);
+        self
+    }
+
+    /// Sets the interval between the messages sent by the entry panel during
+    /// a call.
+    pub fn set_ring_interval(&mut self, interval_us: u64) -> &mut Self {
+        self.ring_interval_us = interval_us;
+        self
+    }
+
+    /// Sends a message starting at `at_us`. Returns the time at which the
+    /// message ends.
+    pub fn send(&mut self, at_us: u64, message: &Message) -> u64 {
+        self.transmit(at_us, pulse::encode(message, &self.timing))
+    }
+
+    /// Sends a sequence of bursts and silences starting at `at_us`, like a
+    /// message recorded from a real line. Returns the time at which the last
+    /// burst ends.
+    pub fn send_pulses(&mut self, at_us: u64, pulses: &[pulse::Pulse]) -> u64 {
+        self.transmit(at_us, pulses.iter().copied())
+    }
+
+    /// Makes the entry panel call an intercom starting at `at_us`. The panel
+    /// sends two [`Code::Call`] messages followed by a [`Code::CallEnd`].
+    pub fn call(&mut self, at_us: u64, address: u8) -> &mut Self {
+        for (i, code) in
+            [Code::Call, Code::Call, Code::CallEnd].iter().enumerate()
+        {
+            let message = Message::new(*code, address);
+            self.send(at_us + i as u64 * self.ring_interval_us, &message);
+        }
+        self
+    }
+
+    /// Runs the simulation, returning the timestamps, in nanoseconds, of the
+    /// edges seen on the line.
+    pub fn run(&self) -> Vec<u64> {
+        let mut bus = self.clone();
+        let end_us = bus.bursts.iter().map(|(_, end)| *end).max().unwrap_or(0);
+        let glitches = bus.noise.glitches_per_s as u64 * end_us / 1_000_000;
+        for _ in 0..glitches {
+            let at = bus.random(end_us);
+            bus.bursts.push((at, at + CYCLE_US / 2));
+        }
+        // Every acknowledgement changes the line, which may change what the
+        // intercoms receive afterwards, so the line is decoded again from
+        // the start after adding one.
+        let mut acknowledged = Vec::new();
+        loop {
+            let edges = bus.edges();
+            let Some(at_ns) = bus.unacknowledged(&edges, &acknowledged) else {
+                return edges;
+            };
+            acknowledged.push(at_ns);
+            // The message is received when its last burst starts, the
+            // acknowledgement starts after the burst and a short silence.
+            let at_us = at_ns / 1000
+                + (bus.timing.burst_us + bus.timing.zero_us) as u64;
+            bus.transmit(at_us, pulse::encode_ack(&bus.timing));
+        }
+    }
+
+    /// Returns the timestamp of the first message received by an intercom
+    /// that must acknowledge it, excluding those already acknowledged.
+    fn unacknowledged(
+        &self,
+        edges: &[u64],
+        acknowledged: &[u64],
+    ) -> Option<u64> {
+        let mut decoder = Decoder::new(self.timing);
+        edges.iter().copied().find(|at_ns| {
+            let Some(Event::Message(message)) =
+                decoder.edge((at_ns / 1000) as u32)
+            else {
+                return false;
+            };
+            !acknowledged.contains(at_ns)
+                && self
+                    .intercoms
+                    .iter()
+                    .any(|i| i.acknowledges && i.address == message.address)
+        })
+    }
+
+    /// Adds the bursts in a sequence of pulses starting at `at_us`. Returns
+    /// the time at which the last burst ends.
+    fn transmit(
+        &mut self,
+        at_us: u64,
+        pulses: impl Iterator<Item = pulse::Pulse>,
+    ) -> u64 {
+        let mut now = at_us;
+        let mut end = at_us;
+        for pulse in pulses {
+            let start = self.jitter(now);
+            end = self.jitter(now + pulse.burst_us as u64).max(start + 1);
+            self.bursts.push((start, end));
+            now += (pulse.burst_us + pulse.gap_us) as u64;
+        }
+        end
+    }
+
+    /// Returns the edges produced by the bursts. Overlapping bursts are
+    /// merged, and every burst is a 25kHz square wave.
+    fn edges(&self) -> Vec<u64> {
+        let mut bursts = self.bursts.clone();
+        bursts.sort_unstable();
+        let mut merged: Vec<(u64, u64)> = Vec::new();
+        for (start, end) in bursts {
+            match merged.last_mut() {
+                Some((_, last_end)) if start <= *last_end => {
+                    *last_end = (*last_end).max(end);
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+        let mut edges = Vec::new();
+        for (start, end) in merged {
+            for rise in (start..end).step_by(CYCLE_US as usize) {
+                edges.push(rise * 1000);
+                edges.push((rise + CYCLE_US / 2).min(end) * 1000);
+            }
+        }
+        edges
+    }
+
+    fn jitter(&mut self, at_us: u64) -> u64 {
+        let jitter = self.noise.jitter_us as u64;
+        if jitter == 0 {
+            return at_us;
+        }
+        (at_us + self.random(2 * jitter + 1)).saturating_sub(jitter)
+    }
+
+    /// Returns a random number from 0 to `max - 1`.
+    fn random(&mut self, max: u64) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng % max.max(1)
+    }
+}
@@ -0,0 +1,158 @@
+use std::f32::consts::PI;
+
+use simplebus2::pulse::{self, Event, Pulse, Timing};
+use simplebus2::{Code, Message};
+
+use crate::audio::{self, Options, Recording, WavError};
+
+const SAMPLE_RATE: u32 = 96_000;
+
+/// Records a sequence of pulses as 25kHz sine bursts starting at
+/// `start_us`.
+fn record(
+    recording: &mut Recording,
+    start_us: u64,
+    pulses: impl Iterator<Item = Pulse>,
+) {
+    let sample = |us: u64| (us * SAMPLE_RATE as u64 / 1_000_000) as usize;
+    let mut now = start_us;
+    for pulse in pulses {
+        let (start, end) = (sample(now), sample(now + pulse.burst_us as u64));
+        recording.samples.resize(recording.samples.len().max(end), 0.0);
+        for i in start..end {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            recording.samples[i] += 0.4 * (2.0 * PI * 25_000.0 * t).sin();
+        }
+        now += (pulse.burst_us + pulse.gap_us) as u64;
+    }
+}
+
+fn add_noise(recording: &mut Recording) {
+    let mut state = 0x2545f491u32;
+    for sample in &mut recording.samples {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        *sample += (state as f32 / u32::MAX as f32 - 0.5) * 0.1;
+    }
+}
+
+/// A call to intercom 12 and its acknowledgement, followed by a door
+/// opening command that is not acknowledged.
+fn bus() -> Recording {
+    let timing = Timing::DEFAULT;
+    let call = Message::new(Code::Call, 12);
+    let mut recording =
+        Recording { sample_rate: SAMPLE_RATE, samples: vec![] };
+    record(&mut recording, 10_000, pulse::encode(&call, &timing));
+    record(&mut recording, 159_000, pulse::encode_ack(&timing));
+    let open = Message::new(Code::OpenDoor, 12);
+    record(&mut recording, 400_000, pulse::encode(&open, &timing));
+    recording.samples.resize(recording.samples.len() + 9_600, 0.0);
+    add_noise(&mut recording);
+    recording
+}
+
+#[test]
+fn bursts() {
+    let recording = Recording::from_wav(&bus().to_wav(), 0).unwrap();
+    let bursts = audio::bursts(&recording, &Options::default());
+    assert_eq!(bursts.len(), 20 + 4 + 20);
+    // The bursts are 25kHz cycles long, and the samples above the threshold
+    // may be half a cycle away from the start and the end.
+    for (_, pulse) in &bursts {
+        assert!(pulse.burst_us.abs_diff(3_000) <= 40, "{pulse:?}");
+    }
+    assert!(bursts[0].0.abs_diff(10_000_000) <= 40_000);
+    assert!(bursts[0].1.gap_us.abs_diff(17_000) <= 40);
+    assert_eq!(bursts.last().unwrap().1.gap_us, 0);
+}
+
+#[test]
+fn decode() {
+    let recording = Recording::from_wav(&bus().to_wav(), 0).unwrap();
+    let bursts = audio::bursts(&recording, &Options::default());
+    let events = audio::decode(&bursts, &Timing::DEFAULT);
+    let call = Message::new(Code::Call, 12);
+    let open = Message::new(Code::OpenDoor, 12);
+    assert_eq!(
+        events.iter().map(|(_, e)| *e).collect::<Vec<_>>(),
+        [
+            Event::Message(call),
+            Event::Ack(call),
+            Event::Message(open),
+            Event::NoAck(open),
+        ]
+    );
+    // The call is complete when its final burst starts.
+    assert!(events[0].0.abs_diff(10_000_000 + 143_000_000) <= 40_000);
+}
+
+#[test]
+fn threshold() {
+    let recording = bus();
+    // Nothing is loud enough.
+    let options = Options { threshold: Some(0.9), ..Default::default() };
+    assert!(audio::bursts(&recording, &options).is_empty());
+    // The noise is above the threshold, so everything is a single burst.
+    let options = Options { threshold: Some(0.01), ..Default::default() };
+    assert_eq!(audio::bursts(&recording, &options).len(), 1);
+    // Silence.
+    let silence =
+        Recording { sample_rate: SAMPLE_RATE, samples: vec![0.0; 100] };
+    assert!(audio::bursts(&silence, &Options::default()).is_empty());
+    assert!(audio::decode(&[], &Timing::DEFAULT).is_empty());
+}
+
+/// Returns a WAV file with the given format and interleaved samples.
+fn wav(format: u16, channels: u16, bits: u16, data: &[u8]) -> Vec<u8> {
+    let mut out = b"RIFF\0\0\0\0WAVE".to_vec();
+    out.extend_from_slice(b"fmt \x10\0\0\0");
+    out.extend_from_slice(&format.to_le_bytes());
+    out.extend_from_slice(&channels.to_le_bytes());
+    out.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    out.extend_from_slice(&[0; 6]);
+    out.extend_from_slice(&bits.to_le_bytes());
+    // An odd-length chunk that must be skipped, with its padding.
+    out.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    out
+}
+
+#[test]
+fn formats() {
+    let stereo_u8 = wav(1, 2, 8, &[128, 0, 192, 255]);
+    assert_eq!(
+        Recording::from_wav(&stereo_u8, 1).unwrap().samples,
+        [-1.0, 127.0 / 128.0]
+    );
+    let i24 = wav(1, 1, 24, &[0, 0, 0x40, 0, 0, 0xc0]);
+    assert_eq!(Recording::from_wav(&i24, 0).unwrap().samples, [0.5, -0.5]);
+    let f32 = wav(3, 1, 32, &0.25f32.to_le_bytes());
+    let recording = Recording::from_wav(&f32, 0).unwrap();
+    assert_eq!(recording.samples, [0.25]);
+    assert_eq!(recording.sample_rate, SAMPLE_RATE);
+}
+
+#[test]
+fn errors() {
+    assert_eq!(Recording::from_wav(b"RIFF", 0), Err(WavError::NotWav));
+    assert_eq!(
+        Recording::from_wav(&wav(1, 2, 16, &[]), 2),
+        Err(WavError::Channel { channel: 2, channels: 2 })
+    );
+    assert_eq!(
+        Recording::from_wav(&wav(2, 1, 4, &[]), 0),
+        Err(WavError::Unsupported { format: 2, bits: 4 })
+    );
+    let mut truncated = wav(1, 1, 16, &[0; 4]);
+    truncated.truncate(truncated.len() - 1);
+    assert_eq!(Recording::from_wav(&truncated, 0), Err(WavError::Truncated));
+    let no_data = &wav(1, 1, 16, &[])[..36];
+    assert_eq!(
+        Recording::from_wav(no_data, 0),
+        Err(WavError::MissingChunk("data"))
+    );
+}
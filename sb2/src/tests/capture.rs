@@ -0,0 +1,155 @@
+use std::fmt::Write;
+
+use simplebus2::pulse::{self, Event, Pulse, Timing};
+use simplebus2::{Code, Message};
+
+use crate::capture::{self, CaptureError, Format};
+
+/// Returns the comparator's output for a sequence of pulses starting at
+/// `start_us`, as a list of timestamps in microseconds and levels. Bursts
+/// are a 25kHz square wave.
+fn signal(
+    start_us: u64,
+    pulses: impl Iterator<Item = Pulse>,
+) -> Vec<(u64, bool)> {
+    let mut levels = vec![(0, false)];
+    let mut now = start_us;
+    for pulse in pulses {
+        let end = now + pulse.burst_us as u64;
+        while now < end {
+            levels.push((now, true));
+            levels.push((now + 20, false));
+            now += 40;
+        }
+        now += pulse.gap_us as u64;
+    }
+    levels
+}
+
+/// A call to intercom 12 and its acknowledgement, followed by a message
+/// that is cut short after its preamble and 5 bits.
+fn bus() -> Vec<(u64, bool)> {
+    let timing = Timing::DEFAULT;
+    let msg = Message::new(Code::Call, 12);
+    let mut levels = signal(1_000, pulse::encode(&msg, &timing));
+    // The acknowledgement starts 3ms after the end of the message.
+    let end = levels.last().unwrap().0 + 20;
+    levels
+        .extend(signal(end + 3_000, pulse::encode_ack(&timing)).split_off(1));
+    levels.extend(
+        signal(500_000, pulse::encode(&msg, &timing).take(7)).split_off(1),
+    );
+    levels
+}
+
+fn vcd(levels: &[(u64, bool)]) -> String {
+    let mut out = String::from(
+        "$date today $end\n\
+         $timescale 1 us $end\n\
+         $scope module libsigrok $end\n\
+         $var wire 1 ! D0 $end\n\
+         $var wire 1 \" D1 $end\n\
+         $upscope $end\n\
+         $enddefinitions $end\n",
+    );
+    for (i, (t, level)) in levels.iter().enumerate() {
+        let _ = write!(out, "#{t} {}\"", *level as u8);
+        if i == 0 {
+            out.push_str(" 1!");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn expected_events() -> Vec<Event> {
+    let msg = Message::new(Code::Call, 12);
+    vec![
+        Event::Message(msg),
+        Event::Ack(msg),
+        Event::Truncated { bits: msg.to_bits() & 0b1_1111, count: 5 },
+    ]
+}
+
+fn events(edges: &[u64]) -> Vec<Event> {
+    capture::decode(edges, &Timing::DEFAULT)
+        .into_iter()
+        .map(|(_, event)| event)
+        .collect()
+}
+
+#[test]
+fn vcd_capture() {
+    let text = vcd(&bus());
+    let edges = capture::edges(&text, Format::Vcd, Some("D1")).unwrap();
+    let decoded = capture::decode(&edges, &Timing::DEFAULT);
+    assert_eq!(
+        decoded.iter().map(|(_, e)| *e).collect::<Vec<_>>(),
+        expected_events()
+    );
+    // The message is complete with the first edge of its final burst,
+    // 146ms minus the final burst after the first edge.
+    assert_eq!(decoded[0].0, (1_000 + 143_000) * 1_000);
+    // D0 doesn't change.
+    let edges = capture::edges(&text, Format::Vcd, Some("D0")).unwrap();
+    assert!(edges.is_empty());
+    // D0 is the first channel.
+    assert_eq!(capture::edges(&text, Format::Vcd, None).unwrap(), edges);
+}
+
+#[test]
+fn csv_with_time() {
+    let mut text = String::from("Time [s],D0\n");
+    for (t, level) in bus() {
+        let _ = writeln!(text, "{:.6},{}", t as f64 / 1e6, level as u8);
+    }
+    let edges = capture::edges(&text, Format::Csv, None).unwrap();
+    assert_eq!(events(&edges), expected_events());
+}
+
+#[test]
+fn csv_with_sample_rate() {
+    // Samples every 10us, D0 is always high.
+    let levels = bus();
+    let end = levels.last().unwrap().0 + 100_000;
+    let mut text = String::from(
+        "; CSV, generated by libsigrok\n\
+         ; Channels (2/8): D0, D1\n\
+         ; Samplerate: 100 kHz\n\
+         D0,D1\n",
+    );
+    let mut next = levels.iter().peekable();
+    let mut level = false;
+    for t in (0..end).step_by(10) {
+        while let Some((_, l)) = next.next_if(|(at, _)| *at <= t) {
+            level = *l;
+        }
+        let _ = writeln!(text, "1,{}", level as u8);
+    }
+    let edges = capture::edges(&text, Format::Csv, Some("D1")).unwrap();
+    assert_eq!(events(&edges), expected_events());
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        capture::edges("time,D0\n0,0\n1,x\n", Format::Csv, None),
+        Err(CaptureError { line: 3, message: "invalid level \"x\"".into() })
+    );
+    assert_eq!(
+        capture::edges("D0\n0\n", Format::Csv, Some("D3")).unwrap_err().line,
+        1
+    );
+    assert_eq!(
+        capture::edges("D0\n0\n", Format::Csv, None).unwrap_err().message,
+        "no time column and no sample rate"
+    );
+    assert_eq!(
+        capture::edges(&vcd(&bus()), Format::Vcd, Some("D3"))
+            .unwrap_err()
+            .to_string(),
+        "line 8: channel \"D3\" not found"
+    );
+    assert_eq!(Format::from_path("bus.VCD"), Some(Format::Vcd));
+    assert_eq!(Format::from_path("bus"), None);
+}
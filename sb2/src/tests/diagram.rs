@@ -0,0 +1,43 @@
+use simplebus2::pulse::Timing;
+use simplebus2::{Code, Message};
+
+use crate::diagram::{render, Options};
+use crate::wire;
+
+#[test]
+fn message() {
+    let msg = Message::new(Code::Call, 12);
+    let pulses = wire::pulses(&msg, &Timing::DEFAULT, false);
+    let diagram = render(&pulses, &Options { us_per_char: 1000, width: 80 });
+    let lines: Vec<&str> = diagram.lines().collect();
+    assert_eq!(
+        lines[..3],
+        [
+            "###_________________###___###___###___###___###______###______###___###___###___",
+            "preamble            0     0     0     0     1        1        0     0     1",
+            "                    code                                      address",
+        ]
+    );
+    // The blocks are separated by an empty line.
+    assert_eq!(lines[3], "");
+    assert!(lines.iter().all(|l| l.len() <= 80));
+    // The signal is complete.
+    let signal: usize = lines
+        .iter()
+        .filter(|l| l.starts_with(['#', '_']))
+        .map(|l| l.len())
+        .sum();
+    assert_eq!(signal, 146);
+}
+
+#[test]
+fn scale() {
+    let msg = Message::new(Code::OpenDoor, 12);
+    let pulses = wire::pulses(&msg, &Timing::DEFAULT, true);
+    let diagram = render(&pulses, &Options { us_per_char: 3000, width: 1000 });
+    let lines: Vec<&str> = diagram.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("#______#_#_#_#_#__#"));
+    assert!(lines[0].ends_with("#_#_#_#"));
+    assert!(lines[1].ends_with("ack"));
+}
@@ -0,0 +1,26 @@
+use crate::hex::{format, parse};
+
+#[test]
+fn formats() {
+    let expected = vec![0x00, 0xcc, 0x40];
+    assert_eq!(parse("00cc40"), Ok(expected.clone()));
+    assert_eq!(parse("00 CC 40"), Ok(expected.clone()));
+    assert_eq!(parse("00:cc:40"), Ok(expected.clone()));
+    assert_eq!(parse("0x00, 0xcc, 0x40"), Ok(expected.clone()));
+    assert_eq!(parse("  0x00cc 40\n"), Ok(expected.clone()));
+    assert_eq!(parse(""), Ok(vec![]));
+    assert_eq!(format(&expected), "00 cc 40");
+}
+
+#[test]
+fn errors() {
+    assert!(parse("00c").is_err());
+    assert!(parse("0x").is_err());
+    assert!(parse("zz").is_err());
+    assert!(parse("+1").is_err());
+    assert!(parse("éé").is_err());
+    assert_eq!(
+        parse("00 0xg0").unwrap_err().to_string(),
+        r#"invalid hexadecimal bytes: "0xg0""#
+    );
+}
@@ -0,0 +1,7 @@
+mod audio;
+mod capture;
+mod diagram;
+mod hex;
+mod report;
+mod sim;
+mod traffic;
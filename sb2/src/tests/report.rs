@@ -0,0 +1,158 @@
+use simplebus2::auth::{Key, Signer};
+use simplebus2::pulse::Timing;
+use simplebus2::{Code, Frame, Message, Packet};
+
+use crate::report::{self, DecodeError};
+use crate::wire::{self, Field, Meaning};
+
+#[test]
+fn message() {
+    let msg = Message::new(Code::Call, 12);
+    assert_eq!(report::message(&msg), "call (48), address 12, checksum 4");
+    // The README's oscilloscope capture.
+    assert_eq!(report::bits(&msg), "000011 00110000 0010");
+    let msg = Message::new(Code::Unknown(33), 1);
+    assert_eq!(report::message(&msg), "unknown (33), address 1, checksum 3");
+}
+
+#[test]
+fn pulses() {
+    let msg = Message::new(Code::Call, 12);
+    let pulses = wire::pulses(&msg, &Timing::DEFAULT, false);
+    assert_eq!(pulses.len(), 20);
+    assert_eq!(pulses[0].1, Meaning::Preamble);
+    assert_eq!(pulses[0].0.gap_us, 17_000);
+    assert_eq!(
+        pulses[5].1,
+        Meaning::Bit { field: Field::Code, index: 4, value: true }
+    );
+    assert_eq!(pulses[5].0.gap_us, 6_000);
+    assert_eq!(
+        pulses[10].1,
+        Meaning::Bit { field: Field::Address, index: 3, value: true }
+    );
+    assert_eq!(
+        pulses[17].1,
+        Meaning::Bit { field: Field::Checksum, index: 2, value: true }
+    );
+    assert_eq!(pulses[19].1, Meaning::End);
+    assert_eq!(pulses[19].0.gap_us, 0);
+
+    let with_ack = wire::pulses(&msg, &Timing::DEFAULT, true);
+    assert_eq!(with_ack.len(), 24);
+    assert_eq!(with_ack[19].0.gap_us, 3_000);
+    assert_eq!(with_ack[20].1, Meaning::Ack(0));
+    assert_eq!(with_ack[23].0.gap_us, 0);
+}
+
+#[test]
+fn encode() {
+    let msg = Message::new(Code::OpenDoor, 12);
+    let report = report::encode(&msg, &Timing::DEFAULT, false);
+    assert!(report.contains("uart      00 c4 30\n"), "{report}");
+    assert!(report.contains("3ms    17ms     preamble\n"), "{report}");
+    assert!(report.contains("3ms    6ms      code bit 4 = 1\n"), "{report}");
+    assert!(report.contains("3ms             end\n"), "{report}");
+    assert!(report.ends_with("duration  146ms\n"), "{report}");
+}
+
+#[test]
+fn format_us() {
+    assert_eq!(wire::format_us(3_000), "3ms");
+    assert_eq!(wire::format_us(2_500), "2.5ms");
+    assert_eq!(wire::format_us(40), "0.04ms");
+}
+
+#[test]
+fn decode() {
+    let msg = Message::new(Code::Call, 12);
+    let packet = report::decode(&msg.to_raw_bytes()).unwrap();
+    assert_eq!(packet, Packet::Plain(Frame::Message(msg)));
+    assert_eq!(
+        report::packet(&packet),
+        "kind      message\nmessage   call (48), address 12, checksum 4\n"
+    );
+
+    let packet = Packet::Sequenced { seq: 2, frame: Frame::Ack(msg) };
+    assert_eq!(
+        report::packet(&report::decode(&packet.to_raw_bytes()).unwrap()),
+        "kind      bus acknowledgement, sequence 2\n\
+         message   call (48), address 12, checksum 4\n"
+    );
+
+    let packet = Packet::Plain(Frame::NoAck(msg));
+    assert_eq!(
+        report::packet(&report::decode(&packet.to_raw_bytes()).unwrap()),
+        "kind      bus transmission failed\n\
+         message   call (48), address 12, checksum 4\n"
+    );
+
+    let packet = Packet::LinkAck { seq: 7, message: msg };
+    assert!(report::packet(&report::decode(&packet.to_raw_bytes()).unwrap())
+        .starts_with("kind      link acknowledgement, sequence 7\n"));
+
+    let frame = Signer::new(Key::new([0; 16]), 41).sign(msg);
+    let report = report::packet(&Packet::Plain(frame));
+    assert!(report.contains("kind      authenticated message\n"));
+    assert!(report.contains("counter   42\n"));
+}
+
+#[test]
+fn decode_errors() {
+    let bytes = Message::new(Code::Call, 12).to_raw_bytes();
+    assert_eq!(
+        report::decode(&bytes[..2]),
+        Err(DecodeError::Length { expected: 3, got: 2 })
+    );
+    assert_eq!(
+        report::decode(&[bytes[0], bytes[1], bytes[2], 0]),
+        Err(DecodeError::Length { expected: 3, got: 4 })
+    );
+    assert_eq!(report::decode(&[0, 0, 0]), Err(DecodeError::AllZeros));
+
+    let mut wrong = bytes;
+    wrong[2] ^= 0b1_0000;
+    assert_eq!(
+        report::decode(&wrong),
+        Err(DecodeError::Checksum {
+            code: 48,
+            address: 12,
+            checksum: 5,
+            expected: 4
+        })
+    );
+    assert_eq!(
+        report::decode(&wrong).unwrap_err().to_string(),
+        "code 48, address 12: checksum is 5, expecting 4"
+    );
+
+    let mut authenticated = bytes;
+    authenticated[0] |= 2;
+    assert_eq!(
+        report::decode(&authenticated),
+        Err(DecodeError::Length { expected: 15, got: 3 })
+    );
+
+    let mut ack = [bytes[0] | 1, bytes[1], bytes[2], 2];
+    assert_eq!(
+        report::decode(&ack[..3]),
+        Err(DecodeError::Length { expected: 4, got: 3 })
+    );
+    assert_eq!(report::decode(&ack), Err(DecodeError::Result(2)));
+    ack[3] = 0;
+    assert_eq!(
+        report::decode(&ack),
+        Ok(Packet::Plain(Frame::Ack(Message::new(Code::Call, 12))))
+    );
+
+    let mut kind = bytes;
+    kind[0] |= 0b100;
+    assert_eq!(report::decode(&kind), Err(DecodeError::Kind(0b100)));
+}
+
+#[test]
+fn codes() {
+    let codes = report::codes();
+    assert_eq!(codes.lines().count(), Code::KNOWN.len() + 1);
+    assert!(codes.contains("  48  call              Building door's ring"));
+}
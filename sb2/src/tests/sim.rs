@@ -0,0 +1,114 @@
+use simplebus2::pulse::{Event, Timing};
+use simplebus2::{Code, Message};
+
+use crate::capture;
+use crate::sim::{Bus, Intercom, Noise};
+
+fn events(bus: &Bus) -> Vec<Event> {
+    capture::decode(&bus.run(), &Timing::DEFAULT)
+        .into_iter()
+        .map(|(_, event)| event)
+        .collect()
+}
+
+#[test]
+fn call() {
+    let mut bus = Bus::new(Timing::DEFAULT);
+    bus.add(Intercom::new(11)).add(Intercom::new(12)).call(1_000, 12);
+    let call = Message::new(Code::Call, 12);
+    let end = Message::new(Code::CallEnd, 12);
+    assert_eq!(
+        events(&bus),
+        [
+            Event::Message(call),
+            Event::Ack(call),
+            Event::Message(call),
+            Event::Ack(call),
+            Event::Message(end),
+            Event::Ack(end),
+        ]
+    );
+    // The first message is received when its final burst starts.
+    let edges = bus.run();
+    let first = capture::decode(&edges, &Timing::DEFAULT)[0].0;
+    assert_eq!(first, (1_000 + 143_000) * 1_000);
+}
+
+#[test]
+fn no_ack() {
+    let mut bus = Bus::new(Timing::DEFAULT);
+    let mut silent = Intercom::new(10);
+    silent.acknowledges = false;
+    bus.add(Intercom::new(12)).add(silent);
+    bus.send(0, &Message::new(Code::OpenDoor, 13));
+    bus.send(500_000, &Message::new(Code::CameraOn, 10));
+    assert_eq!(
+        events(&bus),
+        [
+            Event::Message(Message::new(Code::OpenDoor, 13)),
+            Event::NoAck(Message::new(Code::OpenDoor, 13)),
+            Event::Message(Message::new(Code::CameraOn, 10)),
+            Event::NoAck(Message::new(Code::CameraOn, 10)),
+        ]
+    );
+}
+
+#[test]
+fn collision() {
+    let mut bus = Bus::new(Timing::DEFAULT);
+    bus.add(Intercom::new(12)).add(Intercom::new(13));
+    // The second message starts during the first one's preamble, the
+    // silences between the merged bursts don't match any symbol.
+    bus.send(0, &Message::new(Code::Call, 12));
+    bus.send(8_000, &Message::new(Code::Call, 13));
+    let events = events(&bus);
+    assert!(events
+        .iter()
+        .all(|e| !matches!(e, Event::Message(_) | Event::Ack(_))));
+    // A message sent later is received.
+    bus.send(1_000_000, &Message::new(Code::HookOff, 13));
+    assert_eq!(
+        self::events(&bus)[events.len()..],
+        [
+            Event::Message(Message::new(Code::HookOff, 13)),
+            Event::Ack(Message::new(Code::HookOff, 13)),
+        ]
+    );
+}
+
+#[test]
+fn noise() {
+    let call = Message::new(Code::Call, 12);
+    let end = Message::new(Code::CallEnd, 12);
+    let call_events = [
+        Event::Message(call),
+        Event::Ack(call),
+        Event::Message(call),
+        Event::Ack(call),
+        Event::Message(end),
+        Event::Ack(end),
+    ];
+
+    // The decoder tolerates some jitter.
+    let mut bus = Bus::new(Timing::DEFAULT);
+    bus.set_noise(Noise { jitter_us: 200, seed: 7, ..Default::default() });
+    bus.add(Intercom::new(12)).call(0, 12);
+    assert_eq!(events(&bus), call_events);
+
+    // Glitches break some of the messages, always the same ones for a given
+    // seed.
+    let noisy = |seed| {
+        let mut bus = Bus::new(Timing::DEFAULT);
+        bus.set_noise(Noise { glitches_per_s: 1, jitter_us: 0, seed });
+        bus.add(Intercom::new(12)).call(0, 12);
+        bus
+    };
+    let bus = noisy(1);
+    assert_eq!(bus.run(), bus.run());
+    assert_eq!(noisy(1).run(), bus.run());
+    let lost = (1..20)
+        .map(|seed| events(&noisy(seed)))
+        .filter(|events| events[..] != call_events)
+        .count();
+    assert!(lost > 0 && lost < 19, "{lost}");
+}
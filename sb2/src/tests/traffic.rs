@@ -0,0 +1,36 @@
+use simplebus2::pulse::{self, Event, Timing};
+use simplebus2::{Code, Message};
+
+use crate::capture;
+use crate::traffic;
+
+const CALL: Message = Message::new(Code::Call, 12);
+const OPEN: Message = Message::new(Code::OpenDoor, 12);
+
+#[test]
+fn simulate() {
+    let mut records = traffic::parse(
+        "sb2-traffic 1\n1000 rx call 12 ack\n2000 rx call 12 -\n3000 rx open_door 7 -",
+    )
+    .unwrap();
+    // The last message was recorded with its pulses.
+    records[2].pulses = pulse::encode(&OPEN, &Timing::DEFAULT).collect();
+
+    let bus = traffic::simulate(&records, Timing::DEFAULT);
+    let events: Vec<Event> = capture::decode(&bus.run(), &Timing::DEFAULT)
+        .into_iter()
+        .map(|(_, event)| event)
+        .collect();
+    // Intercom 12 acknowledges every message addressed to it.
+    assert_eq!(
+        events,
+        [
+            Event::Message(CALL),
+            Event::Ack(CALL),
+            Event::Message(CALL),
+            Event::Ack(CALL),
+            Event::Message(OPEN),
+            Event::Ack(OPEN),
+        ]
+    );
+}
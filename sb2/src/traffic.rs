@@ -0,0 +1,38 @@
+//! Recordings of the traffic seen on the bus.
+//!
+//! The format of traffic logs is defined in [`simplebus2::traffic`], which
+//! is shared with `serial_bridge`. This module adds playing them back into
+//! a simulated [`Bus`].
+
+use simplebus2::pulse::Timing;
+pub use simplebus2::traffic::*;
+
+use crate::sim::{Bus, Intercom};
+
+/// Returns a simulated bus where the records are sent at their times.
+///
+/// Intercoms are added for the addresses that acknowledged some message.
+/// Records with pulses are sent with their original timings, the rest are
+/// encoded with `timing`.
+pub fn simulate(records: &[Record], timing: Timing) -> Bus {
+    let mut bus = Bus::new(timing);
+    let mut addresses: Vec<u8> = records
+        .iter()
+        .filter(|record| record.acknowledged)
+        .map(|record| record.message.address)
+        .collect();
+    addresses.sort_unstable();
+    addresses.dedup();
+    for address in addresses {
+        bus.add(Intercom::new(address));
+    }
+    for record in records {
+        let at_us = record.time_ms * 1000;
+        if record.pulses.is_empty() {
+            bus.send(at_us, &record.message);
+        } else {
+            bus.send_pulses(at_us, &record.pulses);
+        }
+    }
+    bus
+}
@@ -0,0 +1,91 @@
+//! What a message looks like on the bus.
+
+use simplebus2::pulse::{self, Pulse, Timing};
+use simplebus2::Message;
+
+/// Fields of a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Code,
+    Address,
+    Checksum,
+}
+
+impl Field {
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Code => "code",
+            Field::Address => "address",
+            Field::Checksum => "checksum",
+        }
+    }
+}
+
+/// Meaning of a burst and the silence that follows it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Meaning {
+    Preamble,
+    /// The `index`-th bit in a field, the least significant is 0.
+    Bit {
+        field: Field,
+        index: u8,
+        value: bool,
+    },
+    /// The burst that terminates the last bit.
+    End,
+    /// The `n`-th burst of the acknowledgement sent by the target intercom.
+    Ack(u8),
+}
+
+/// Returns the pulses of a message and their meaning. If `ack` is true, the
+/// message is followed by its acknowledgement.
+pub fn pulses(
+    message: &Message,
+    timing: &Timing,
+    ack: bool,
+) -> Vec<(Pulse, Meaning)> {
+    let bits = message.to_bits();
+    let mut pulses: Vec<_> = pulse::encode(message, timing)
+        .enumerate()
+        .map(|(i, pulse)| {
+            let meaning = match i {
+                0 => Meaning::Preamble,
+                i if i <= Message::NUM_BITS as usize => {
+                    let bit = i as u8 - 1;
+                    let (field, index) = match bit {
+                        0..=5 => (Field::Code, bit),
+                        6..=13 => (Field::Address, bit - 6),
+                        _ => (Field::Checksum, bit - 14),
+                    };
+                    let value = bits & (1 << bit) != 0;
+                    Meaning::Bit { field, index, value }
+                }
+                _ => Meaning::End,
+            };
+            (pulse, meaning)
+        })
+        .collect();
+    if ack {
+        // The acknowledgement follows the message after the same silence
+        // used between its own bursts.
+        if let Some((last, _)) = pulses.last_mut() {
+            last.gap_us = timing.zero_us;
+        }
+        pulses.extend(
+            pulse::encode_ack(timing)
+                .enumerate()
+                .map(|(n, pulse)| (pulse, Meaning::Ack(n as u8))),
+        );
+    }
+    pulses
+}
+
+/// Formats a duration given in microseconds as milliseconds.
+pub fn format_us(us: u32) -> String {
+    if us.is_multiple_of(1000) {
+        format!("{}ms", us / 1000)
+    } else {
+        let ms = format!("{}.{:03}", us / 1000, us % 1000);
+        format!("{}ms", ms.trim_end_matches('0'))
+    }
+}
@@ -0,0 +1,227 @@
+//! Forwarding between the repeater and the MQTT broker.
+//!
+//! Topics and payloads are the same used by `mqtt_task_loop` in the remote
+//! unit, so consumers don't need to know which one is running:
+//!
+//! * `<prefix>/messages`: JSON of every message seen on the bus, like
+//!   `{"code":"call","address":12}`.
+//! * `<prefix>/acks`: JSON reporting whether calls and door openings were
+//!   acknowledged by their target, like
+//!   `{"message":{"code":"call","address":12},"acknowledged":true}`.
+//! * `<prefix>/calls`: JSON of the events of calls from the entry door,
+//!   which group the messages sent during a call (see
+//!   [`simplebus2::session`]), like `{"event":"call_started","address":12}`.
+//! * `<prefix>/commands`: receives `open_door` and `camera_on`, which are
+//!   sent on the bus with our intercom's address.
+
+use std::io::{Read, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use serde::Serialize;
+use simplebus2::session::CallTracker;
+use simplebus2::traffic::{Direction, Record, Recorder};
+use simplebus2::{Code, Message};
+
+use crate::uart::{Received, Uart};
+
+/// Maximum time between a message and its acknowledgement. This includes the
+/// time it takes to transmit the message over UART and the bus when the
+/// message is sent by us, and the repeater's retries.
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// MQTT topics.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Topics {
+    pub messages: String,
+    pub commands: String,
+    pub acks: String,
+    pub calls: String,
+}
+
+impl Topics {
+    /// Returns the topics under `prefix` (e.g: `plusvic/intercom`).
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            messages: format!("{prefix}/messages"),
+            commands: format!("{prefix}/commands"),
+            acks: format!("{prefix}/acks"),
+            calls: format!("{prefix}/calls"),
+        }
+    }
+}
+
+/// Reports whether a message was acknowledged on the bus by its target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct AckReport {
+    pub message: Message,
+    pub acknowledged: bool,
+}
+
+/// Returns the message that must be sent for a command received over MQTT.
+pub fn command(payload: &[u8], address: u8) -> Option<Message> {
+    let code = match payload {
+        b"open_door" => Code::OpenDoor,
+        b"camera_on" => Code::CameraOn,
+        _ => return None,
+    };
+    Some(Message { code, address })
+}
+
+/// Creates an MQTT client and starts a thread that keeps the connection
+/// alive, subscribing to the commands topic every time it connects. The
+/// commands received are converted into messages with the given address
+/// and sent to the returned channel.
+pub fn connect(
+    options: MqttOptions,
+    topics: &Topics,
+    address: u8,
+) -> (Client, Receiver<Message>) {
+    let (client, mut connection) = Client::new(options, 10);
+    let (tx, rx) = mpsc::channel();
+    let subscriber = client.clone();
+    let commands = topics.commands.clone();
+    thread::spawn(move || {
+        for event in connection.iter() {
+            match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    eprintln!("Connected to MQTT broker");
+                    if let Err(err) =
+                        subscriber.subscribe(&commands, QoS::AtLeastOnce)
+                    {
+                        eprintln!("MQTT error: {err}");
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish)))
+                    if publish.topic == commands =>
+                {
+                    let Some(message) = command(&publish.payload, address)
+                    else {
+                        eprintln!(
+                            "MQTT: unknown command {}/{}",
+                            publish.topic,
+                            String::from_utf8_lossy(&publish.payload)
+                        );
+                        continue;
+                    };
+                    eprintln!("MQTT RX: {message:?}");
+                    if tx.send(message).is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                // The connection is retried in the next iteration.
+                Err(err) => {
+                    eprintln!("MQTT error: {err}");
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+        }
+    });
+    (client, rx)
+}
+
+/// Forwards messages between the repeater and the MQTT broker, recording
+/// the traffic with `recorder`. Returns when the commands channel is closed,
+/// or when the serial port fails.
+pub fn run<P: Read + Write, W: Write>(
+    uart: &mut Uart<P>,
+    client: &Client,
+    commands: &Receiver<Message>,
+    topics: &Topics,
+    recorder: &mut Recorder<W>,
+) -> anyhow::Result<()> {
+    // Messages waiting for an acknowledgement, and their deadlines.
+    let mut awaiting_ack: Vec<(Message, Instant)> = Vec::new();
+    let mut calls = CallTracker::default();
+    let start = Instant::now();
+    let now_ms = || start.elapsed().as_millis() as u64;
+    let publish = |topic: &str, payload: Vec<u8>| {
+        client
+            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .with_context(|| format!("can't publish to {topic}"))
+    };
+
+    loop {
+        loop {
+            match commands.try_recv() {
+                Ok(message) => {
+                    eprintln!("UART TX: {message:?}");
+                    if !uart.send(message) {
+                        eprintln!("UART TX: queue full, {message:?} dropped");
+                    }
+                    await_ack(&mut awaiting_ack, message);
+                    recorder.record(Record::new(
+                        now_ms(),
+                        Direction::Tx,
+                        message,
+                    ))?;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+
+        match uart.poll().context("can't use the serial port")? {
+            Some(Received::Message(message)) => {
+                eprintln!("UART RX: {message:?}");
+                publish(&topics.messages, serde_json::to_vec(&message)?)?;
+                await_ack(&mut awaiting_ack, message);
+                recorder.record(Record::new(
+                    now_ms(),
+                    Direction::Rx,
+                    message,
+                ))?;
+                if let Some(event) = calls.message(&message, now_ms() as u32) {
+                    publish(&topics.calls, serde_json::to_vec(&event)?)?;
+                }
+            }
+            // Acknowledgements for other messages are discarded. The
+            // repeater reports the messages it couldn't transmit, so there's
+            // no need to wait for the timeout.
+            Some(
+                received @ (Received::Ack(message) | Received::NoAck(message)),
+            ) => {
+                let acknowledged = matches!(received, Received::Ack(_));
+                if acknowledged {
+                    eprintln!("UART RX: ACK {message:?}");
+                    recorder.ack(&message);
+                } else {
+                    eprintln!("UART RX: NO ACK {message:?}");
+                }
+                if let Some(i) =
+                    awaiting_ack.iter().position(|(m, _)| *m == message)
+                {
+                    awaiting_ack.remove(i);
+                    let report = AckReport { message, acknowledged };
+                    publish(&topics.acks, serde_json::to_vec(&report)?)?;
+                }
+            }
+            None => {}
+        }
+
+        recorder.flush(now_ms())?;
+        while let Some(event) = calls.poll(now_ms() as u32) {
+            publish(&topics.calls, serde_json::to_vec(&event)?)?;
+        }
+
+        let now = Instant::now();
+        while let Some(i) =
+            awaiting_ack.iter().position(|(_, deadline)| *deadline <= now)
+        {
+            let (message, _) = awaiting_ack.remove(i);
+            let report = AckReport { message, acknowledged: false };
+            publish(&topics.acks, serde_json::to_vec(&report)?)?;
+        }
+    }
+}
+
+/// Starts waiting for the acknowledgement of calls and door openings.
+fn await_ack(awaiting_ack: &mut Vec<(Message, Instant)>, message: Message) {
+    if matches!(message.code, Code::Call | Code::CallEnd | Code::OpenDoor) {
+        awaiting_ack.push((message, Instant::now() + ACK_TIMEOUT));
+    }
+}
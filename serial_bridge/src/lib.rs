@@ -0,0 +1,13 @@
+//! Bridge between the repeater and an MQTT broker, for setups where the
+//! HC-12 module is connected to a computer through a USB-serial adapter
+//! instead of to the remote unit.
+//!
+//! The bridge talks to the repeater with the same frames used by the remote
+//! unit (see [`uart`]), and publishes to the same MQTT topics (see
+//! [`bridge`]).
+
+pub mod bridge;
+pub mod uart;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,97 @@
+//! `serial_bridge`: forwards SimpleBus2 messages between an HC-12 module
+//! connected over USB and an MQTT broker.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+
+use anyhow::Context;
+use clap::Parser;
+use rumqttc::MqttOptions;
+use simplebus2::traffic::Recorder;
+
+use serial_bridge::bridge::{self, Topics};
+use serial_bridge::uart::{Uart, BAUD_RATE, FRAME_GAP};
+use simplebus2::auth::Key;
+use simplebus2::link::LinkConfig;
+
+#[derive(Parser)]
+#[command(version, about = "Bridge between an HC-12 module and MQTT")]
+struct Cli {
+    /// Serial port where the HC-12 module is connected.
+    #[arg(long, default_value = "/dev/ttyUSB0")]
+    port: String,
+    /// Serial port's baud rate.
+    #[arg(long, default_value_t = BAUD_RATE)]
+    baud: u32,
+    /// MQTT broker's host name.
+    #[arg(long, env = "MQTT_BROKER", default_value = "localhost")]
+    broker: String,
+    /// MQTT broker's port.
+    #[arg(long, default_value_t = 1883)]
+    broker_port: u16,
+    /// MQTT client identifier.
+    #[arg(long, default_value = "serial_bridge")]
+    client_id: String,
+    /// Prefix of the MQTT topics.
+    #[arg(long, default_value = "plusvic/intercom")]
+    topic: String,
+    /// Address of our intercom, used in the commands sent to the bus.
+    #[arg(long, default_value_t = 12)]
+    address: u8,
+    /// Key shared with the repeater, as 32 hexadecimal digits. When set,
+    /// commands are sent in authenticated frames.
+    #[arg(long, env = "INTERCOM_KEY", hide_env_values = true)]
+    key: Option<String>,
+    /// Send frames that are neither acknowledged nor retransmitted by the
+    /// repeater.
+    #[arg(long)]
+    unsequenced: bool,
+    /// Record the traffic into a file, which can be played back with
+    /// `sb2 replay`.
+    #[arg(long)]
+    record: Option<String>,
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> anyhow::Result<()> {
+    let key = match &cli.key {
+        Some(hex) => Some(
+            Key::from_hex(hex)
+                .context("the key must be 32 hexadecimal digits")?,
+        ),
+        None => None,
+    };
+    let link =
+        LinkConfig { sequenced: !cli.unsequenced, ..LinkConfig::DEFAULT };
+    let port = serialport::new(&cli.port, cli.baud)
+        .timeout(FRAME_GAP)
+        .open()
+        .with_context(|| format!("can't open {}", cli.port))?;
+    let mut uart = Uart::new(port, link, key);
+
+    let out: Box<dyn Write> = match &cli.record {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path)
+                .with_context(|| format!("can't create {path}"))?,
+        )),
+        None => Box::new(io::sink()),
+    };
+    let mut recorder = Recorder::new(out)?;
+
+    let topics = Topics::new(&cli.topic);
+    let mut options =
+        MqttOptions::new(cli.client_id, cli.broker, cli.broker_port);
+    options.set_keep_alive(std::time::Duration::from_secs(30));
+    let (client, commands) = bridge::connect(options, &topics, cli.address);
+    bridge::run(&mut uart, &client, &commands, &topics, &mut recorder)
+}
@@ -0,0 +1,320 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rumqttc::MqttOptions;
+use serialport::{SerialPort, TTYPort};
+use simplebus2::auth::{AuthError, Key, Verifier};
+use simplebus2::link::LinkConfig;
+use simplebus2::traffic::{self, Direction, Recorder};
+use simplebus2::{Code, Frame, FrameDecoder, Message, Packet};
+
+use super::broker::{Broker, Request};
+use crate::bridge::{self, Topics};
+use crate::uart::{Uart, FRAME_GAP};
+
+const KEY: Key = Key::new(*b"0123456789abcdef");
+
+/// The repeater's end of the serial port.
+struct Repeater {
+    port: TTYPort,
+    decoder: FrameDecoder,
+}
+
+impl Repeater {
+    fn send(&mut self, packet: Packet) {
+        self.port.write_all(&packet.to_raw_bytes()).unwrap();
+    }
+
+    fn receive(&mut self) -> Packet {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut byte = [0u8];
+        while Instant::now() < deadline {
+            match self.port.read(&mut byte) {
+                Ok(_) => {
+                    if let Some(packet) = self.decoder.push(byte[0]) {
+                        return packet;
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::TimedOut => {}
+                Err(err) => panic!("{err}"),
+            }
+        }
+        panic!("no packet received");
+    }
+
+    /// Returns true if no packet is received for `duration`.
+    fn is_quiet(&mut self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        let mut byte = [0u8];
+        while Instant::now() < deadline {
+            match self.port.read(&mut byte) {
+                Ok(_) => {
+                    if self.decoder.push(byte[0]).is_some() {
+                        return false;
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::TimedOut => {}
+                Err(err) => panic!("{err}"),
+            }
+        }
+        true
+    }
+}
+
+/// Traffic log shared with the bridge's thread.
+#[derive(Clone, Default)]
+struct Log(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Log {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Starts a bridge connected to a broker and to a repeater.
+fn start(key: Option<Key>) -> (Broker, Repeater) {
+    start_recording(key, io::sink())
+}
+
+/// Starts a bridge that records the traffic into `out`.
+fn start_recording<W: Write + Send + 'static>(
+    key: Option<Key>,
+    out: W,
+) -> (Broker, Repeater) {
+    let broker = Broker::start();
+    let (mut master, mut slave) = TTYPort::pair().unwrap();
+    master.set_timeout(Duration::from_millis(100)).unwrap();
+    slave.set_timeout(FRAME_GAP).unwrap();
+
+    let topics = Topics::new("test/intercom");
+    let options = MqttOptions::new("test", "127.0.0.1", broker.port());
+    let (client, commands) = bridge::connect(options, &topics, 12);
+    thread::spawn(move || {
+        let mut uart = Uart::new(slave, LinkConfig::DEFAULT, key);
+        let mut recorder = Recorder::new(out).unwrap();
+        bridge::run(&mut uart, &client, &commands, &topics, &mut recorder)
+    });
+
+    assert_eq!(
+        broker.request(),
+        Request::Subscribe("test/intercom/commands".into())
+    );
+    (broker, Repeater { port: master, decoder: FrameDecoder::new() })
+}
+
+fn published(topic: &str, json: &str) -> Request {
+    Request::Publish(format!("test/intercom/{topic}"), json.into())
+}
+
+#[test]
+fn messages() {
+    let (broker, mut repeater) = start(None);
+    let call = Message::new(Code::Call, 12);
+
+    repeater.send(Packet::Sequenced { seq: 0, frame: Frame::Message(call) });
+    assert_eq!(repeater.receive(), Packet::LinkAck { seq: 0, message: call });
+    assert_eq!(
+        broker.request(),
+        published("messages", r#"{"code":"call","address":12}"#)
+    );
+    assert_eq!(
+        broker.request(),
+        published("calls", r#"{"event":"call_started","address":12}"#)
+    );
+
+    repeater.send(Packet::Sequenced { seq: 1, frame: Frame::Ack(call) });
+    assert_eq!(repeater.receive(), Packet::LinkAck { seq: 1, message: call });
+    assert_eq!(
+        broker.request(),
+        published(
+            "acks",
+            r#"{"message":{"code":"call","address":12},"acknowledged":true}"#
+        )
+    );
+
+    // Plain frames are accepted too. Unknown codes are published as numbers,
+    // and door openings that are not acknowledged are reported.
+    let unknown = Message::new(Code::Unknown(33), 1);
+    let open = Message::new(Code::OpenDoor, 3);
+    repeater.send(Packet::Plain(Frame::Message(unknown)));
+    repeater.send(Packet::Plain(Frame::Message(open)));
+    assert_eq!(
+        broker.request(),
+        published("messages", r#"{"code":33,"address":1}"#)
+    );
+    assert_eq!(
+        broker.request(),
+        published("messages", r#"{"code":"open_door","address":3}"#)
+    );
+    assert_eq!(
+        broker.request(),
+        published(
+            "acks",
+            r#"{"message":{"code":"open_door","address":3},"acknowledged":false}"#
+        )
+    );
+}
+
+#[test]
+fn calls() {
+    let (broker, mut repeater) = start(None);
+    let call = Message::new(Code::Call, 12);
+    let call_end = Message::new(Code::CallEnd, 12);
+    for message in [call, call, call_end] {
+        repeater.send(Packet::Plain(Frame::Message(message)));
+    }
+
+    // Nobody acknowledges the calls, those reports are skipped.
+    let mut events = Vec::new();
+    while events.len() < 3 {
+        match broker.request() {
+            Request::Publish(topic, payload)
+                if topic == "test/intercom/calls" =>
+            {
+                events.push(
+                    serde_json::from_slice::<serde_json::Value>(&payload)
+                        .unwrap(),
+                );
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(events[0]["event"], "call_started");
+    assert_eq!(events[1]["event"], "ringing");
+    assert_eq!(events[1]["rings"], 2);
+    assert_eq!(events[2]["event"], "call_ended");
+    assert_eq!(events[2]["address"], 12);
+    assert_eq!(events[2]["rings"], 3);
+    assert!(events[2]["duration_ms"].as_u64().unwrap() < 1000);
+}
+
+#[test]
+fn commands() {
+    let (broker, mut repeater) = start(None);
+    let open = Message::new(Code::OpenDoor, 12);
+
+    broker.publish("test/intercom/commands", b"bogus");
+    broker.publish("test/intercom/commands", b"open_door");
+    let packet = Packet::Sequenced { seq: 0, frame: Frame::Message(open) };
+    assert_eq!(repeater.receive(), packet);
+    // Retransmitted until acknowledged.
+    assert_eq!(repeater.receive(), packet);
+    repeater.send(Packet::LinkAck { seq: 0, message: open });
+    // The intercom at the entry door didn't acknowledge it, and the repeater
+    // gave up retrying.
+    repeater.send(Packet::Sequenced { seq: 0, frame: Frame::NoAck(open) });
+    assert_eq!(repeater.receive(), Packet::LinkAck { seq: 0, message: open });
+    assert_eq!(
+        broker.request(),
+        published(
+            "acks",
+            r#"{"message":{"code":"open_door","address":12},"acknowledged":false}"#
+        )
+    );
+
+    broker.publish("test/intercom/commands", b"camera_on");
+    let camera = Message::new(Code::CameraOn, 12);
+    assert_eq!(
+        repeater.receive(),
+        Packet::Sequenced { seq: 1, frame: Frame::Message(camera) }
+    );
+    repeater.send(Packet::LinkAck { seq: 1, message: camera });
+    // Acknowledgements are tracked only for calls and door openings.
+    assert!(broker.is_quiet(Duration::from_millis(1500)));
+}
+
+#[test]
+fn authenticated() {
+    let (broker, mut repeater) = start(Some(KEY));
+    let open = Message::new(Code::OpenDoor, 12);
+    // The repeater has accepted 10 commands before, the bridge starts at 0.
+    let mut verifier = Verifier::new(KEY, 10);
+
+    broker.publish("test/intercom/commands", b"open_door");
+    let Packet::Sequenced { seq: 0, frame } = repeater.receive() else {
+        panic!("expecting a sequenced packet");
+    };
+    assert_eq!(verifier.verify(&frame), Err(AuthError::Replayed(open)));
+    repeater.send(Packet::LinkAck { seq: 0, message: open });
+
+    // The bridge catches up with the repeater's counter and sends the command
+    // again.
+    let notice = verifier.notice(open);
+    repeater.send(Packet::Sequenced { seq: 0, frame: notice });
+    assert_eq!(repeater.receive(), Packet::LinkAck { seq: 0, message: open });
+    let Packet::Sequenced { seq: 1, frame } = repeater.receive() else {
+        panic!("expecting a sequenced packet");
+    };
+    assert_eq!(verifier.verify(&frame), Ok(open));
+    assert_eq!(verifier.counter(), 11);
+}
+
+#[test]
+fn replayed() {
+    let (broker, mut repeater) = start(Some(KEY));
+    let open = Message::new(Code::OpenDoor, 12);
+    let mut verifier = Verifier::new(KEY, 0);
+
+    broker.publish("test/intercom/commands", b"open_door");
+    let Packet::Sequenced { seq: 0, frame } = repeater.receive() else {
+        panic!("expecting a sequenced packet");
+    };
+    assert_eq!(verifier.verify(&frame), Ok(open));
+    repeater.send(Packet::LinkAck { seq: 0, message: open });
+
+    // Someone replays the accepted frame, and the repeater refuses it with a
+    // notice. The bridge must not sign the command again, neither before
+    // nor after the repeater reports its outcome.
+    assert_eq!(verifier.verify(&frame), Err(AuthError::Replayed(open)));
+    let notice = verifier.notice(open);
+    repeater.send(Packet::Sequenced { seq: 0, frame: notice });
+    assert_eq!(repeater.receive(), Packet::LinkAck { seq: 0, message: open });
+    repeater.send(Packet::Sequenced { seq: 1, frame: Frame::Ack(open) });
+    assert_eq!(repeater.receive(), Packet::LinkAck { seq: 1, message: open });
+    repeater.send(Packet::Sequenced { seq: 2, frame: notice });
+    assert_eq!(repeater.receive(), Packet::LinkAck { seq: 2, message: open });
+    assert!(repeater.is_quiet(Duration::from_millis(1500)));
+    assert_eq!(verifier.counter(), 1);
+}
+
+#[test]
+fn record() {
+    let log = Log::default();
+    let (broker, mut repeater) = start_recording(None, log.clone());
+    let call = Message::new(Code::Call, 12);
+    let open = Message::new(Code::OpenDoor, 12);
+
+    repeater.send(Packet::Plain(Frame::Message(call)));
+    repeater.send(Packet::Plain(Frame::Ack(call)));
+    broker.publish("test/intercom/commands", b"open_door");
+    let Packet::Sequenced { seq: 0, .. } = repeater.receive() else {
+        panic!("expecting a sequenced packet");
+    };
+    repeater.send(Packet::LinkAck { seq: 0, message: open });
+
+    // Records are written when their acknowledgement can't arrive anymore.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let records = loop {
+        let text = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let records = traffic::parse(&text).unwrap();
+        if records.len() == 2 || Instant::now() > deadline {
+            break records;
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    assert_eq!(records.len(), 2);
+    assert_eq!(
+        (records[0].direction, records[0].message, records[0].acknowledged),
+        (Direction::Rx, call, true)
+    );
+    assert_eq!(
+        (records[1].direction, records[1].message, records[1].acknowledged),
+        (Direction::Tx, open, false)
+    );
+}
@@ -0,0 +1,147 @@
+//! Minimal MQTT 3.1.1 broker that accepts a single client, for testing the
+//! bridge without a real broker.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const CONNECT: u8 = 1;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const PINGREQ: u8 = 12;
+const DISCONNECT: u8 = 14;
+
+/// Something the client did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    Subscribe(String),
+    Publish(String, Vec<u8>),
+}
+
+pub struct Broker {
+    port: u16,
+    requests: Receiver<Request>,
+    client: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl Broker {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, requests) = mpsc::channel();
+        let client = Arc::new(Mutex::new(None));
+        let writer = client.clone();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            *writer.lock().unwrap() = Some(stream.try_clone().unwrap());
+            let _ = serve(stream, &tx);
+        });
+        Self { port, requests, client }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Waits for the next request from the client.
+    pub fn request(&self) -> Request {
+        self.requests.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    /// Returns true if the client doesn't send anything for a while.
+    pub fn is_quiet(&self, timeout: Duration) -> bool {
+        self.requests.recv_timeout(timeout).is_err()
+    }
+
+    /// Sends a QoS 0 message to the client.
+    pub fn publish(&self, topic: &str, payload: &[u8]) {
+        let mut body = string(topic);
+        body.extend_from_slice(payload);
+        self.send(PUBLISH << 4, &body);
+    }
+
+    fn send(&self, header: u8, body: &[u8]) {
+        let mut client = self.client.lock().unwrap();
+        write_packet(client.as_mut().unwrap(), header, body).unwrap();
+    }
+}
+
+fn serve(mut stream: TcpStream, requests: &Sender<Request>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    loop {
+        let (header, body) = read_packet(&mut stream)?;
+        match header >> 4 {
+            CONNECT => write_packet(&mut writer, 0x20, &[0, 0])?,
+            SUBSCRIBE => {
+                let topic_len =
+                    u16::from_be_bytes([body[2], body[3]]) as usize;
+                let topic = String::from_utf8_lossy(&body[4..4 + topic_len]);
+                let _ = requests.send(Request::Subscribe(topic.into()));
+                write_packet(&mut writer, 0x90, &[body[0], body[1], 1])?;
+            }
+            PUBLISH => {
+                let qos = (header >> 1) & 0b11;
+                let topic_len =
+                    u16::from_be_bytes([body[0], body[1]]) as usize;
+                let topic = String::from_utf8_lossy(&body[2..2 + topic_len]);
+                let mut payload = &body[2 + topic_len..];
+                if qos > 0 {
+                    write_packet(&mut writer, 0x40, &payload[..2])?;
+                    payload = &payload[2..];
+                }
+                let _ = requests
+                    .send(Request::Publish(topic.into(), payload.into()));
+            }
+            PINGREQ => write_packet(&mut writer, 0xd0, &[])?,
+            DISCONNECT => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+fn read_packet(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0u8];
+    stream.read_exact(&mut byte)?;
+    let header = byte[0];
+    let mut len = 0usize;
+    let mut shift = 0;
+    loop {
+        stream.read_exact(&mut byte)?;
+        len |= ((byte[0] & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body)?;
+    Ok((header, body))
+}
+
+fn write_packet(
+    stream: &mut TcpStream,
+    header: u8,
+    body: &[u8],
+) -> io::Result<()> {
+    let mut packet = vec![header];
+    let mut len = body.len();
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        packet.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    stream.write_all(&packet)
+}
+
+fn string(s: &str) -> Vec<u8> {
+    let mut out = (s.len() as u16).to_be_bytes().to_vec();
+    out.extend_from_slice(s.as_bytes());
+    out
+}
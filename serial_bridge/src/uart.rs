@@ -0,0 +1,153 @@
+//! Link with the repeater through an HC-12 module connected to a serial
+//! port.
+//!
+//! This is the host counterpart of `uart_task` in the remote unit: frames
+//! are sent through a [`Link`] that retransmits them until the repeater
+//! acknowledges them, and when a key is set commands are sent in
+//! authenticated frames.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+use simplebus2::auth::{Key, Signer};
+use simplebus2::link::{Link, LinkConfig, Output};
+use simplebus2::{Frame, FrameDecoder, Message};
+
+/// Baud rate used by the HC-12 modules.
+pub const BAUD_RATE: u32 = 4800;
+
+/// The bytes in a frame are transmitted back-to-back. If no byte is received
+/// for this long, any partially received frame is discarded. At 4800 bps
+/// transmitting a byte takes ~2ms. Serial ports must be opened with this
+/// read timeout.
+pub const FRAME_GAP: Duration = Duration::from_millis(10);
+
+/// Frames received from the repeater.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Received {
+    /// A message seen on the bus.
+    Message(Message),
+    /// The target intercom acknowledged a message on the bus.
+    Ack(Message),
+    /// The repeater gave up transmitting a message sent by us.
+    NoAck(Message),
+}
+
+/// Sends and receives frames through a serial port.
+pub struct Uart<P> {
+    port: P,
+    decoder: FrameDecoder,
+    link: Link<8>,
+    signer: Option<Signer>,
+    /// Last message sent, which is sent again if the repeater refuses it
+    /// because of its counter.
+    last_sent: Option<Message>,
+    start: Instant,
+}
+
+impl<P: Read + Write> Uart<P> {
+    /// Creates a link with the repeater through `port`. Messages are sent
+    /// in authenticated frames if `key` is set, the counter starts at zero
+    /// and it's synchronized with the repeater's one on the first message.
+    pub fn new(port: P, config: LinkConfig, key: Option<Key>) -> Self {
+        Self {
+            port,
+            decoder: FrameDecoder::new(),
+            link: Link::new(config),
+            signer: key.map(|key| Signer::new(key, 0)),
+            last_sent: None,
+            start: Instant::now(),
+        }
+    }
+
+    /// Queues a message that must be transmitted on the bus. Returns false
+    /// if the queue is full and the message was dropped.
+    pub fn send(&mut self, message: Message) -> bool {
+        let frame = match &mut self.signer {
+            Some(signer) => signer.sign(message),
+            None => Frame::Message(message),
+        };
+        self.last_sent = Some(message);
+        self.link.send(frame).is_ok()
+    }
+
+    /// Transmits the packets waiting in the link, then waits for a byte for
+    /// up to the port's timeout. Returns a frame if the byte completes one.
+    pub fn poll(&mut self) -> io::Result<Option<Received>> {
+        while let Some(output) = self.link.poll(self.now_ms()) {
+            match output {
+                Output::Transmit(packet) => {
+                    self.port.write_all(&packet.to_raw_bytes())?;
+                    self.port.flush()?;
+                }
+                Output::Failed(frame) => {
+                    eprintln!("UART TX: not acknowledged {frame:?}");
+                }
+            }
+        }
+
+        let mut byte = [0u8; 1];
+        match self.port.read(&mut byte) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => {}
+            // The line is idle, the bytes received so far don't form a frame
+            // and the remaining ones won't arrive.
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::TimedOut | ErrorKind::WouldBlock
+                ) =>
+            {
+                if self.decoder.pending() > 0 {
+                    eprintln!(
+                        "Incomplete message: {} bytes",
+                        self.decoder.pending()
+                    );
+                    self.decoder.reset();
+                }
+                return Ok(None);
+            }
+            Err(err) => return Err(err),
+        }
+
+        let Some(packet) = self.decoder.push(byte[0]) else {
+            return Ok(None);
+        };
+        let frame = self.link.receive(packet, self.now_ms());
+        // Once the repeater reports the outcome of the last message sent, it
+        // was accepted, and notices can't refer to it anymore. Otherwise a
+        // replay of the accepted frame would make us sign it again.
+        if let Some(Frame::Ack(msg) | Frame::NoAck(msg)) = frame {
+            if self.last_sent == Some(msg) {
+                self.last_sent = None;
+            }
+        }
+        match frame {
+            Some(Frame::Message(msg)) => Ok(Some(Received::Message(msg))),
+            Some(Frame::Ack(msg)) => Ok(Some(Received::Ack(msg))),
+            Some(Frame::NoAck(msg)) => Ok(Some(Received::NoAck(msg))),
+            // The repeater refused the last message because of its counter.
+            // The message is signed again at most once.
+            Some(Frame::Authenticated(notice)) => {
+                if let Some(signer) = &mut self.signer {
+                    if self.last_sent == Some(notice.message)
+                        && signer.sync(&notice)
+                    {
+                        eprintln!(
+                            "UART TX: {:?} (counter synced)",
+                            notice.message
+                        );
+                        let _ = self.link.send(signer.sign(notice.message));
+                    }
+                    self.last_sent = None;
+                }
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn now_ms(&self) -> u32 {
+        self.start.elapsed().as_millis() as u32
+    }
+}
@@ -0,0 +1,272 @@
+//! Authenticated frames exchanged over the radio link.
+//!
+//! Plain [`Frame::Message`] frames are protected only by the message's
+//! checksum, so anyone with a radio module on the same channel can make the
+//! repeater transmit commands on the bus. When the remote unit and the
+//! repeater share a [`Key`], commands are sent as [`Frame::Authenticated`]
+//! frames instead, which carry a rolling counter and a message
+//! authentication code computed with SipHash-2-4.
+//!
+//! The sender ([`Signer`]) increments the counter for every frame, and the
+//! receiver ([`Verifier`]) only accepts frames with a counter greater than
+//! the last one accepted, so recorded frames can't be replayed. The counter
+//! is not secret. When the verifier receives a genuine frame with an old
+//! counter, it answers with a notice containing the last counter accepted,
+//! which allows the signer to catch up after losing its counter (e.g: after
+//! a reboot).
+
+use crate::{Frame, Message};
+
+/// Key shared by the remote unit and the repeater.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Key([u8; 16]);
+
+impl Key {
+    pub const fn new(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+
+    /// Parses a key from 32 hexadecimal digits. This is a `const fn` so that
+    /// keys can be passed to the firmwares through environment variables at
+    /// build time.
+    pub const fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.as_bytes();
+        if hex.len() != 32 {
+            return None;
+        }
+        let mut bytes = [0u8; 16];
+        let mut i = 0;
+        while i < 32 {
+            let digit = match hex[i] {
+                b @ b'0'..=b'9' => b - b'0',
+                b @ b'a'..=b'f' => b - b'a' + 10,
+                b @ b'A'..=b'F' => b - b'A' + 10,
+                _ => return None,
+            };
+            bytes[i / 2] = bytes[i / 2] << 4 | digit;
+            i += 1;
+        }
+        Some(Self(bytes))
+    }
+
+    /// Returns the authentication code for a message with the given counter.
+    /// Commands and notices use different codes, so that one can't be
+    /// passed off as the other.
+    fn tag(&self, domain: u8, message: &Message, counter: u32) -> u64 {
+        let bits = message.to_bits().to_le_bytes();
+        let counter = counter.to_le_bytes();
+        siphash24(
+            &self.0,
+            &[
+                domain, bits[0], bits[1], bits[2], counter[0], counter[1],
+                counter[2], counter[3],
+            ],
+        )
+    }
+}
+
+/// Keys are never printed.
+impl core::fmt::Debug for Key {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+const DOMAIN_COMMAND: u8 = b'C';
+const DOMAIN_NOTICE: u8 = b'N';
+
+/// Contents of a [`Frame::Authenticated`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Authenticated {
+    pub message: Message,
+    /// Rolling counter.
+    pub counter: u32,
+    /// Message authentication code.
+    pub tag: u64,
+}
+
+impl Authenticated {
+    /// Number of bytes that follow the message in the frame: the counter and
+    /// the tag, both little-endian.
+    pub const TRAILER_LEN: usize = 12;
+}
+
+/// Reasons for refusing a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AuthError {
+    /// The frame is not a [`Frame::Authenticated`].
+    Unauthenticated,
+    /// The authentication code is wrong.
+    BadTag,
+    /// The frame is genuine, but its counter is not greater than the last
+    /// one accepted. Contains the frame's message.
+    Replayed(Message),
+}
+
+/// Produces authenticated frames.
+#[derive(Clone, Debug)]
+pub struct Signer {
+    key: Key,
+    counter: u32,
+}
+
+impl Signer {
+    /// Creates a signer that will use `counter + 1` for the next frame.
+    pub const fn new(key: Key, counter: u32) -> Self {
+        Self { key, counter }
+    }
+
+    /// Returns the counter used in the last frame.
+    pub const fn counter(&self) -> u32 {
+        self.counter
+    }
+
+    /// Returns an authenticated frame containing `message`.
+    ///
+    /// Once the counter reaches `u32::MAX` it stops increasing, and the
+    /// frames will be refused. At one frame per second that would take more
+    /// than a hundred years.
+    pub fn sign(&mut self, message: Message) -> Frame {
+        self.counter = self.counter.saturating_add(1);
+        let tag = self.key.tag(DOMAIN_COMMAND, &message, self.counter);
+        Frame::Authenticated(Authenticated {
+            message,
+            counter: self.counter,
+            tag,
+        })
+    }
+
+    /// Processes a notice sent by the [`Verifier`] after refusing a frame
+    /// because of its counter. If the notice is genuine, and the verifier's
+    /// counter is ahead of the signer's one, the counter jumps past the
+    /// verifier's one. Returns true in that case, which means that the
+    /// refused frame can be signed and sent again.
+    ///
+    /// A notice with the signer's current counter, or an older one, means
+    /// that the verifier already accepted the last frame signed, and the
+    /// refused frame is a replay or a retransmission. Signing the message
+    /// again would execute it twice.
+    pub fn sync(&mut self, notice: &Authenticated) -> bool {
+        let tag = self.key.tag(DOMAIN_NOTICE, &notice.message, notice.counter);
+        if tag != notice.tag || notice.counter <= self.counter {
+            return false;
+        }
+        self.counter = notice.counter;
+        true
+    }
+}
+
+/// Checks authenticated frames.
+#[derive(Clone, Debug)]
+pub struct Verifier {
+    key: Key,
+    counter: u32,
+}
+
+impl Verifier {
+    /// Creates a verifier that accepts frames with counters greater than
+    /// `counter`.
+    pub const fn new(key: Key, counter: u32) -> Self {
+        Self { key, counter }
+    }
+
+    /// Returns the counter of the last frame accepted. This must be stored
+    /// in non-volatile memory, otherwise frames could be replayed after a
+    /// reboot.
+    pub const fn counter(&self) -> u32 {
+        self.counter
+    }
+
+    /// Returns the message in `frame` if the frame is authenticated with the
+    /// shared key and it's not a replay.
+    pub fn verify(&mut self, frame: &Frame) -> Result<Message, AuthError> {
+        let Frame::Authenticated(frame) = frame else {
+            return Err(AuthError::Unauthenticated);
+        };
+        let tag = self.key.tag(DOMAIN_COMMAND, &frame.message, frame.counter);
+        if tag != frame.tag {
+            return Err(AuthError::BadTag);
+        }
+        if frame.counter <= self.counter {
+            return Err(AuthError::Replayed(frame.message));
+        }
+        self.counter = frame.counter;
+        Ok(frame.message)
+    }
+
+    /// Returns the notice that must be sent back after refusing `message`
+    /// with [`AuthError::Replayed`].
+    pub fn notice(&self, message: Message) -> Frame {
+        Frame::Authenticated(Authenticated {
+            message,
+            counter: self.counter,
+            tag: self.key.tag(DOMAIN_NOTICE, &message, self.counter),
+        })
+    }
+}
+
+/// SipHash-2-4, as described in <https://www.aumasson.jp/siphash/>.
+pub(crate) fn siphash24(key: &[u8; 16], data: &[u8]) -> u64 {
+    let k0 = u64::from_le_bytes(read_u64(&key[..8]));
+    let k1 = u64::from_le_bytes(read_u64(&key[8..]));
+    let mut v = [
+        k0 ^ 0x736f6d6570736575,
+        k1 ^ 0x646f72616e646f6d,
+        k0 ^ 0x6c7967656e657261,
+        k1 ^ 0x7465646279746573,
+    ];
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let m = u64::from_le_bytes(read_u64(chunk));
+        v[3] ^= m;
+        sip_round(&mut v);
+        sip_round(&mut v);
+        v[0] ^= m;
+    }
+
+    // The last block contains the remaining bytes and the data's length in
+    // the most significant byte.
+    let mut last = read_u64(chunks.remainder());
+    last[7] = data.len() as u8;
+    let m = u64::from_le_bytes(last);
+    v[3] ^= m;
+    sip_round(&mut v);
+    sip_round(&mut v);
+    v[0] ^= m;
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        sip_round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+/// Copies up to 8 bytes, padding with zeroes.
+fn read_u64(bytes: &[u8]) -> [u8; 8] {
+    let mut buf = [0; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    buf
+}
+
+/// Not inlined, as on AVR the unrolled rounds take a stack frame of almost
+/// 200 bytes, which the repeater can't afford.
+#[inline(never)]
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13);
+    v[1] ^= v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16);
+    v[3] ^= v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21);
+    v[3] ^= v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17);
+    v[1] ^= v[2];
+    v[2] = v[2].rotate_left(32);
+}
@@ -0,0 +1,225 @@
+//! Table of the addresses and codes seen on the bus.
+//!
+//! Every message on the bus is relayed by the repeater, not only the ones
+//! for our intercom. A [`Census`] counts the messages seen for every address
+//! and code, which helps finding out which address belongs to which flat,
+//! and spotting unknown devices or codes.
+//!
+//! The table has a fixed capacity. When it's full, messages for addresses
+//! that are not in the table are counted as dropped. The census doesn't have
+//! a clock of its own, timestamps in milliseconds are passed by the caller.
+//! They are allowed to wrap around, so times longer than ~49 days are not
+//! reported correctly.
+
+use crate::{Code, Message};
+
+/// Number of possible codes. Codes are 6 bits long.
+const NUM_CODES: usize = 64;
+
+/// Result of recording a message in the [`Census`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Seen {
+    /// The address was seen for the first time.
+    NewAddress,
+    /// The address was seen before, but not with this code.
+    NewCode,
+    /// Both the address and the code were seen before with each other.
+    Known,
+    /// The address is not in the table and the table is full.
+    Dropped,
+}
+
+/// Messages seen for an address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Device {
+    address: u8,
+    first_seen_ms: u32,
+    last_seen_ms: u32,
+    counts: [u16; NUM_CODES],
+}
+
+impl Device {
+    const EMPTY: Device = Device::new(0, 0);
+
+    const fn new(address: u8, now_ms: u32) -> Self {
+        Self {
+            address,
+            first_seen_ms: now_ms,
+            last_seen_ms: now_ms,
+            counts: [0; NUM_CODES],
+        }
+    }
+
+    pub const fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Time when the first message for this address was seen.
+    pub const fn first_seen_ms(&self) -> u32 {
+        self.first_seen_ms
+    }
+
+    /// Time when the last message for this address was seen.
+    pub const fn last_seen_ms(&self) -> u32 {
+        self.last_seen_ms
+    }
+
+    /// Returns the number of messages seen with the given code. Counts
+    /// saturate at `u16::MAX`.
+    pub const fn count(&self, code: Code) -> u16 {
+        self.counts[code.as_u8() as usize % NUM_CODES]
+    }
+
+    /// Returns the total number of messages seen for this address.
+    pub fn total(&self) -> u32 {
+        self.counts.iter().map(|count| *count as u32).sum()
+    }
+
+    /// Returns the codes seen for this address and their counts, sorted
+    /// by code.
+    pub fn codes(&self) -> impl Iterator<Item = (Code, u16)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(code, count)| (Code::from_u8(code as u8), *count))
+    }
+
+    /// Returns a report of this address that can be serialized, with the
+    /// time since the last message relative to `now_ms`.
+    pub const fn report(&self, now_ms: u32) -> DeviceReport<'_> {
+        DeviceReport { device: self, now_ms }
+    }
+}
+
+/// Report of the messages seen for an address, returned by
+/// [`Device::report`].
+///
+/// It's serialized like
+/// `{"address":12,"last_seen_ms_ago":5000,"codes":[{"code":"call","count":2}]}`.
+#[derive(Clone, Copy, Debug)]
+pub struct DeviceReport<'a> {
+    device: &'a Device,
+    now_ms: u32,
+}
+
+impl DeviceReport<'_> {
+    /// Time elapsed since the last message for the address was seen.
+    pub const fn last_seen_ms_ago(&self) -> u32 {
+        self.now_ms.wrapping_sub(self.device.last_seen_ms)
+    }
+}
+
+#[cfg(any(test, feature = "serde"))]
+impl serde::Serialize for DeviceReport<'_> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        use serde::ser::{SerializeSeq, SerializeStruct};
+
+        #[derive(serde::Serialize)]
+        struct CodeCount {
+            code: Code,
+            count: u16,
+        }
+
+        struct Codes<'a>(&'a Device);
+
+        impl serde::Serialize for Codes<'_> {
+            fn serialize<S: serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                let mut seq = serializer.serialize_seq(None)?;
+                for (code, count) in self.0.codes() {
+                    seq.serialize_element(&CodeCount { code, count })?;
+                }
+                seq.end()
+            }
+        }
+
+        let mut report = serializer.serialize_struct("DeviceReport", 3)?;
+        report.serialize_field("address", &self.device.address)?;
+        report
+            .serialize_field("last_seen_ms_ago", &self.last_seen_ms_ago())?;
+        report.serialize_field("codes", &Codes(self.device))?;
+        report.end()
+    }
+}
+
+/// Table of the addresses and codes seen on the bus, with room for `N`
+/// addresses. See the [module](self) documentation.
+#[derive(Clone, Debug)]
+pub struct Census<const N: usize> {
+    /// Devices sorted by address. Only the first `len` are used.
+    devices: [Device; N],
+    len: usize,
+    dropped: u32,
+}
+
+impl<const N: usize> Census<N> {
+    pub const fn new() -> Self {
+        Self { devices: [Device::EMPTY; N], len: 0, dropped: 0 }
+    }
+
+    /// Records a message seen on the bus at `now_ms`.
+    pub fn record(&mut self, message: &Message, now_ms: u32) -> Seen {
+        let address = message.address;
+        let index = self.devices[..self.len]
+            .binary_search_by_key(&address, |device| device.address);
+        let (device, mut seen) = match index {
+            Ok(i) => (&mut self.devices[i], Seen::Known),
+            Err(_) if self.len == N => {
+                self.dropped = self.dropped.saturating_add(1);
+                return Seen::Dropped;
+            }
+            Err(i) => {
+                self.devices[i..=self.len].rotate_right(1);
+                self.devices[i] = Device::new(address, now_ms);
+                self.len += 1;
+                (&mut self.devices[i], Seen::NewAddress)
+            }
+        };
+        let count =
+            &mut device.counts[message.code.as_u8() as usize % NUM_CODES];
+        if *count == 0 && seen == Seen::Known {
+            seen = Seen::NewCode;
+        }
+        *count = count.saturating_add(1);
+        device.last_seen_ms = now_ms;
+        seen
+    }
+
+    /// Returns the addresses seen, sorted by address.
+    pub fn devices(&self) -> &[Device] {
+        &self.devices[..self.len]
+    }
+
+    /// Returns the messages seen for the given address, if any.
+    pub fn device(&self, address: u8) -> Option<&Device> {
+        self.devices()
+            .binary_search_by_key(&address, |device| device.address)
+            .ok()
+            .map(|i| &self.devices[i])
+    }
+
+    /// Returns the number of messages that were not recorded because the
+    /// table was full.
+    pub const fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Removes all the addresses from the table.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.dropped = 0;
+    }
+}
+
+impl<const N: usize> Default for Census<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
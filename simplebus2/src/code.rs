@@ -0,0 +1,260 @@
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::str::FromStr;
+
+/// SimpleBus2 message codes.
+///
+/// Codes are 6 bits long, values greater than 63 can't be transmitted over
+/// the bus. Codes whose meaning is not known are represented by
+/// [`Code::Unknown`], which keeps the original value.
+///
+/// Two codes are equal if their numeric values are equal, so
+/// `Code::Unknown(16)` is equal to `Code::OpenDoor`. Use [`Code::from`] for
+/// obtaining the canonical variant for a given value.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Code {
+    /// Open the building's entry door. Sent by intercoms when the open door
+    /// button is pressed.
+    OpenDoor,
+    /// Handset hook off.
+    HookOff,
+    /// Handset hook on.
+    HookOn,
+    /// Call the switchboard.
+    CallSwitchboard,
+    /// Turn on the door camera and the video screen.
+    CameraOn,
+    /// Ring tone of the apartment's floor door. Sent by the intercom when the
+    /// push button at the apartment's door is pressed.
+    CallFloorDoor,
+    /// Ring tone of the building's entry door. A full call consists of two
+    /// of these messages followed by [`Code::CallEnd`].
+    Call,
+    /// Ring tone of the building's entry door, and end of call.
+    CallEnd,
+    /// A code with unknown meaning.
+    Unknown(u8),
+}
+
+impl Code {
+    /// All the codes with a known meaning.
+    pub const KNOWN: [Code; 8] = [
+        Code::OpenDoor,
+        Code::HookOff,
+        Code::HookOn,
+        Code::CallSwitchboard,
+        Code::CameraOn,
+        Code::CallFloorDoor,
+        Code::Call,
+        Code::CallEnd,
+    ];
+
+    /// Returns the code corresponding to the given value.
+    pub const fn from_u8(value: u8) -> Self {
+        match value {
+            16 => Code::OpenDoor,
+            17 => Code::HookOff,
+            18 => Code::HookOn,
+            19 => Code::CallSwitchboard,
+            20 => Code::CameraOn,
+            21 => Code::CallFloorDoor,
+            48 => Code::Call,
+            50 => Code::CallEnd,
+            value => Code::Unknown(value),
+        }
+    }
+
+    /// Returns the code's numeric value.
+    pub const fn as_u8(self) -> u8 {
+        match self {
+            Code::OpenDoor => 16,
+            Code::HookOff => 17,
+            Code::HookOn => 18,
+            Code::CallSwitchboard => 19,
+            Code::CameraOn => 20,
+            Code::CallFloorDoor => 21,
+            Code::Call => 48,
+            Code::CallEnd => 50,
+            Code::Unknown(value) => value,
+        }
+    }
+
+    /// Returns the code's name (e.g: `"open_door"`), or `None` if the code
+    /// is unknown.
+    pub const fn name(self) -> Option<&'static str> {
+        match Code::from_u8(self.as_u8()) {
+            Code::OpenDoor => Some("open_door"),
+            Code::HookOff => Some("hook_off"),
+            Code::HookOn => Some("hook_on"),
+            Code::CallSwitchboard => Some("call_switchboard"),
+            Code::CameraOn => Some("camera_on"),
+            Code::CallFloorDoor => Some("call_floor_door"),
+            Code::Call => Some("call"),
+            Code::CallEnd => Some("call_end"),
+            Code::Unknown(_) => None,
+        }
+    }
+
+    /// Returns a short description of the code, or `None` if the code is
+    /// unknown.
+    pub const fn description(self) -> Option<&'static str> {
+        match Code::from_u8(self.as_u8()) {
+            Code::OpenDoor => Some(
+                "Open door. Sent from intercoms to the building's entry door \
+                 when the open door button is pressed.",
+            ),
+            Code::HookOff => Some("Handset hook off."),
+            Code::HookOn => Some("Handset hook on."),
+            Code::CallSwitchboard => Some("Call switchboard."),
+            Code::CameraOn => Some("Turn on door camera and video screen."),
+            Code::CallFloorDoor => Some(
+                "Floor door's ring tone. Sent by the intercom when the push \
+                 button at the apartment's door is pressed.",
+            ),
+            Code::Call => Some(
+                "Building door's ring tone. Sent from the building's entry \
+                 door when someone is calling.",
+            ),
+            Code::CallEnd => Some(
+                "Building door's ring tone and end of call. Also turns on the \
+                 video screen.",
+            ),
+            Code::Unknown(_) => None,
+        }
+    }
+
+    /// Returns the code with the given name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Code::KNOWN.into_iter().find(|code| code.name() == Some(name))
+    }
+}
+
+impl From<u8> for Code {
+    fn from(value: u8) -> Self {
+        Code::from_u8(value)
+    }
+}
+
+impl From<Code> for u8 {
+    fn from(code: Code) -> Self {
+        code.as_u8()
+    }
+}
+
+impl PartialEq for Code {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_u8() == other.as_u8()
+    }
+}
+
+impl Eq for Code {}
+
+impl Hash for Code {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_u8().hash(state)
+    }
+}
+
+/// Known codes are displayed by name, unknown codes by value.
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{}", self.as_u8()),
+        }
+    }
+}
+
+/// Error returned when parsing a [`Code`] from a string fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseCodeError;
+
+impl fmt::Display for ParseCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid message code")
+    }
+}
+
+/// Parses either a code name (e.g: `"open_door"`) or a numeric value
+/// (e.g: `"16"`). This is the inverse of the [`fmt::Display`]
+/// implementation.
+impl FromStr for Code {
+    type Err = ParseCodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(code) = Code::from_name(s) {
+            return Ok(code);
+        }
+        s.parse::<u8>().map(Code::from_u8).map_err(|_| ParseCodeError)
+    }
+}
+
+/// Known codes are serialized as strings with their names, unknown codes
+/// are serialized as integers.
+#[cfg(any(test, feature = "serde"))]
+impl serde::Serialize for Code {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match self.name() {
+            Some(name) => serializer.serialize_str(name),
+            None => serializer.serialize_u8(self.as_u8()),
+        }
+    }
+}
+
+/// Accepts both names and integers, regardless of whether the code is known
+/// or not. This requires a self-describing format, like JSON.
+#[cfg(any(test, feature = "serde"))]
+impl<'de> serde::Deserialize<'de> for Code {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = Code;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a message code name or an integer")
+            }
+
+            fn visit_u64<E: serde::de::Error>(
+                self,
+                value: u64,
+            ) -> Result<Code, E> {
+                u8::try_from(value).map(Code::from_u8).map_err(|_| {
+                    E::invalid_value(
+                        serde::de::Unexpected::Unsigned(value),
+                        &self,
+                    )
+                })
+            }
+
+            fn visit_i64<E: serde::de::Error>(
+                self,
+                value: i64,
+            ) -> Result<Code, E> {
+                u8::try_from(value).map(Code::from_u8).map_err(|_| {
+                    E::invalid_value(
+                        serde::de::Unexpected::Signed(value),
+                        &self,
+                    )
+                })
+            }
+
+            fn visit_str<E: serde::de::Error>(
+                self,
+                value: &str,
+            ) -> Result<Code, E> {
+                Code::from_name(value).ok_or_else(|| {
+                    E::invalid_value(serde::de::Unexpected::Str(value), &self)
+                })
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
@@ -0,0 +1,494 @@
+//! Configuration of the repeater from the remote unit.
+//!
+//! Configuration commands are sent over the radio link as messages with
+//! codes reserved for them (see [`Command::CODES`]), which are never
+//! transmitted on the bus: the code selects the command and the address
+//! carries its argument. This way configuration commands are retransmitted
+//! (see [`crate::link`]) and authenticated (see [`crate::auth`]) like any
+//! other command. The repeater answers the commands it applies with a
+//! [`Frame::Ack`](crate::Frame::Ack) containing the same message, and the
+//! ones it refuses with a [`Frame::NoAck`](crate::Frame::NoAck).
+//!
+//! The repeater's [`Settings`] are stored in its EEPROM. They include the
+//! [`Filter`] of the messages forwarded over the radio link, an allowlist of
+//! addresses and codes which saves airtime, and keeps the neighbours'
+//! traffic off the air. Settings are read with [`Command::Get`] and written
+//! with [`Command::Set`], which also give access to the repeater's counters
+//! (see [`Value`]).
+//!
+//! The repeater's oscillator can also be calibrated from the remote unit,
+//! which sends [`CALIBRATION_LEN`] bytes equal to [`CALIBRATION_BYTE`]
+//! back-to-back, outside of any frame. The only falling edges in that stream
+//! are the start bits of the bytes, so the repeater can time them without
+//! decoding anything, even when its oscillator is too far off for receiving
+//! frames. The repeater adjusts OSCCAL until the bytes last what they should
+//! at the link's baud rate, then saves the value and reports it like
+//! [`Command::Get`] does.
+
+use core::fmt;
+use core::ops::RangeInclusive;
+use core::str::FromStr;
+
+use crate::pulse::Timing;
+use crate::{Code, Message};
+
+/// Byte repeated in the stream sent for calibrating the repeater's
+/// oscillator. It's never part of a valid frame header.
+pub const CALIBRATION_BYTE: u8 = 0xFF;
+
+/// Number of bytes in the calibration stream, one second at 4800 bps.
+pub const CALIBRATION_LEN: usize = 480;
+
+/// Changes to the repeater's configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    /// Forward the messages with the given address.
+    AllowAddress(u8),
+    /// Don't forward the messages with the given address.
+    BlockAddress(u8),
+    /// Forward the messages with the given code.
+    AllowCode(Code),
+    /// Don't forward the messages with the given code.
+    BlockCode(Code),
+    /// Forward the messages with any address.
+    AllowAllAddresses,
+    /// Don't forward messages with any address, except the ones allowed
+    /// afterwards.
+    BlockAllAddresses,
+    /// Forward the messages with any code.
+    AllowAllCodes,
+    /// Don't forward messages with any code, except the ones allowed
+    /// afterwards.
+    BlockAllCodes,
+    /// Read a value. The repeater answers with a [`Command::Set`] containing
+    /// the current value.
+    Get(Value),
+    /// Write a value.
+    Set(Value, u8),
+}
+
+impl Command {
+    /// Message codes reserved for configuration commands. No known device
+    /// uses them.
+    pub const CODES: RangeInclusive<u8> = 32..=47;
+
+    const ALLOW_ADDRESS: u8 = 32;
+    const BLOCK_ADDRESS: u8 = 33;
+    const ALLOW_CODE: u8 = 34;
+    const BLOCK_CODE: u8 = 35;
+    /// The commands that allow or block everything share this code, the
+    /// address selects the command.
+    const ALL: u8 = 36;
+    const GET: u8 = 37;
+    /// [`Command::Set`] uses one code per value, starting at this one.
+    const SET: u8 = 38;
+
+    /// Returns the message that carries the command.
+    pub const fn to_message(&self) -> Message {
+        let (code, address) = match *self {
+            Command::AllowAddress(address) => (Self::ALLOW_ADDRESS, address),
+            Command::BlockAddress(address) => (Self::BLOCK_ADDRESS, address),
+            Command::AllowCode(code) => (Self::ALLOW_CODE, code.as_u8()),
+            Command::BlockCode(code) => (Self::BLOCK_CODE, code.as_u8()),
+            Command::AllowAllAddresses => (Self::ALL, 0),
+            Command::BlockAllAddresses => (Self::ALL, 1),
+            Command::AllowAllCodes => (Self::ALL, 2),
+            Command::BlockAllCodes => (Self::ALL, 3),
+            Command::Get(value) => (Self::GET, value as u8),
+            Command::Set(value, data) => (Self::SET + value as u8, data),
+        };
+        Message::new(Code::from_u8(code), address)
+    }
+
+    /// Returns the command carried by a message, or `None` if the message
+    /// is not a configuration command.
+    pub fn from_message(message: &Message) -> Option<Self> {
+        let address = message.address;
+        let command = match message.code.as_u8() {
+            Self::ALLOW_ADDRESS => Command::AllowAddress(address),
+            Self::BLOCK_ADDRESS => Command::BlockAddress(address),
+            Self::ALLOW_CODE if address <= 0b11_1111 => {
+                Command::AllowCode(Code::from_u8(address))
+            }
+            Self::BLOCK_CODE if address <= 0b11_1111 => {
+                Command::BlockCode(Code::from_u8(address))
+            }
+            Self::ALL => match address {
+                0 => Command::AllowAllAddresses,
+                1 => Command::BlockAllAddresses,
+                2 => Command::AllowAllCodes,
+                3 => Command::BlockAllCodes,
+                _ => return None,
+            },
+            Self::GET => Command::Get(Value::from_u8(address)?),
+            code @ Self::SET.. => {
+                Command::Set(Value::from_u8(code - Self::SET)?, address)
+            }
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
+/// Error returned when parsing a [`Command`] from a string fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseCommandError;
+
+impl fmt::Display for ParseCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid configuration command")
+    }
+}
+
+/// Parses commands like `"allow_address 12"`, `"block_code call"`,
+/// `"allow_all_codes"`, `"get osccal"` or `"set tolerance 8"`. Codes are
+/// parsed like [`Code::from_str`] does, and values by their names (see
+/// [`Value::name`]).
+impl FromStr for Command {
+    type Err = ParseCommandError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next().ok_or(ParseCommandError)?;
+        let arg = words.next();
+        let data = words.next();
+        if words.next().is_some() {
+            return Err(ParseCommandError);
+        }
+        let address = || arg?.parse::<u8>().ok();
+        let code = || match arg?.parse::<Code>() {
+            Ok(code) if code.as_u8() <= 0b11_1111 => Some(code),
+            _ => None,
+        };
+        let value = || Value::from_name(arg?);
+        let command = match (name, arg, data) {
+            ("set", _, Some(data)) => {
+                value().zip(data.parse().ok()).map(|(v, d)| Command::Set(v, d))
+            }
+            (_, _, Some(_)) => None,
+            ("get", _, _) => value().map(Command::Get),
+            ("allow_address", _, _) => address().map(Command::AllowAddress),
+            ("block_address", _, _) => address().map(Command::BlockAddress),
+            ("allow_code", _, _) => code().map(Command::AllowCode),
+            ("block_code", _, _) => code().map(Command::BlockCode),
+            ("allow_all_addresses", None, _) => {
+                Some(Command::AllowAllAddresses)
+            }
+            ("block_all_addresses", None, _) => {
+                Some(Command::BlockAllAddresses)
+            }
+            ("allow_all_codes", None, _) => Some(Command::AllowAllCodes),
+            ("block_all_codes", None, _) => Some(Command::BlockAllCodes),
+            _ => None,
+        };
+        command.ok_or(ParseCommandError)
+    }
+}
+
+/// Values of the repeater that can be read and written with configuration
+/// commands. The first ones are [`Settings`], the rest are counters of the
+/// messages that the repeater dropped because one of its queues was full,
+/// and of the problems found by the repeater's UART. Counters are read as
+/// 255 when they are larger, and they are not saved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Value {
+    /// Calibration of the internal oscillator, the contents of the OSCCAL
+    /// register.
+    Osccal,
+    /// Maximum difference between the expected and the measured silences on
+    /// the bus, in hundreds of microseconds.
+    Tolerance,
+    /// Maximum silence between the end of a message and its
+    /// acknowledgement, in milliseconds.
+    AckTimeout,
+    /// Period of the carrier in the bursts transmitted on the bus, in
+    /// microseconds.
+    CarrierPeriod,
+    /// Messages received on the bus and not forwarded over UART.
+    BusRxOverflows,
+    /// Commands received over UART and not transmitted on the bus.
+    BusTxOverflows,
+    /// Packets received over UART and not processed.
+    UartRxOverflows,
+    /// Bits transmitted over UART later than they should, which garbles the
+    /// byte. This stays at zero unless the interrupt handlers are too slow.
+    UartTxLate,
+    /// Frames for the remote unit dropped because the radio link's queue was
+    /// full.
+    LinkOverflows,
+}
+
+impl Value {
+    /// All the values, in the order of their numeric identifiers.
+    pub const ALL: [Value; 9] = [
+        Value::Osccal,
+        Value::Tolerance,
+        Value::AckTimeout,
+        Value::CarrierPeriod,
+        Value::BusRxOverflows,
+        Value::BusTxOverflows,
+        Value::UartRxOverflows,
+        Value::UartTxLate,
+        Value::LinkOverflows,
+    ];
+
+    /// Returns the value with the given numeric identifier.
+    const fn from_u8(id: u8) -> Option<Self> {
+        if (id as usize) < Self::ALL.len() {
+            Some(Self::ALL[id as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value's name (e.g: `"osccal"`).
+    pub const fn name(self) -> &'static str {
+        match self {
+            Value::Osccal => "osccal",
+            Value::Tolerance => "tolerance",
+            Value::AckTimeout => "ack_timeout",
+            Value::CarrierPeriod => "carrier_period",
+            Value::BusRxOverflows => "bus_rx_overflows",
+            Value::BusTxOverflows => "bus_tx_overflows",
+            Value::UartRxOverflows => "uart_rx_overflows",
+            Value::UartTxLate => "uart_tx_late",
+            Value::LinkOverflows => "link_overflows",
+        }
+    }
+
+    /// Returns the value with the given name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|value| value.name() == name)
+    }
+}
+
+/// Settings of the repeater.
+///
+/// Settings are stored in the EEPROM as a block that starts with
+/// [`Settings::VERSION`] and ends with a CRC-8 of the preceding bytes, so
+/// that an erased EEPROM, a damaged block, or a block written by a firmware
+/// with a different layout, are detected and the defaults are used instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Settings {
+    /// See [`Value::Osccal`].
+    pub osccal: u8,
+    /// See [`Value::Tolerance`].
+    pub tolerance: u8,
+    /// See [`Value::AckTimeout`].
+    pub ack_timeout: u8,
+    /// See [`Value::CarrierPeriod`].
+    pub carrier_period: u8,
+    pub filter: Filter,
+}
+
+impl Settings {
+    /// Version of the layout of the settings in the EEPROM.
+    pub const VERSION: u8 = 1;
+
+    /// Size in bytes of the settings in the EEPROM.
+    pub const LEN: usize = 6 + Filter::LEN;
+
+    /// Tolerances accepted. The silences for zeros and ones differ in 3ms, so
+    /// the tolerance must be lower than half of that.
+    pub const TOLERANCES: RangeInclusive<u8> = 1..=14;
+
+    /// Carrier periods accepted, the bus uses 40us (25KHz).
+    pub const CARRIER_PERIODS: RangeInclusive<u8> = 30..=50;
+
+    /// Returns the default settings, with the given OSCCAL value. The
+    /// timing is [`Timing::DEFAULT`], and the filter forwards everything.
+    pub const fn new(osccal: u8) -> Self {
+        Self {
+            osccal,
+            tolerance: (Timing::DEFAULT.tolerance_us / 100) as u8,
+            ack_timeout: (Timing::DEFAULT.ack_timeout_us / 1000) as u8,
+            carrier_period: 40,
+            filter: Filter::ALLOW_ALL,
+        }
+    }
+
+    /// Returns the timing of the pulses transmitted and received over the
+    /// bus.
+    pub const fn timing(&self) -> Timing {
+        Timing {
+            tolerance_us: self.tolerance as u32 * 100,
+            ack_timeout_us: self.ack_timeout as u32 * 1000,
+            ..Timing::DEFAULT
+        }
+    }
+
+    /// Returns a value, or `None` if the value is a counter.
+    pub const fn get(&self, value: Value) -> Option<u8> {
+        match value {
+            Value::Osccal => Some(self.osccal),
+            Value::Tolerance => Some(self.tolerance),
+            Value::AckTimeout => Some(self.ack_timeout),
+            Value::CarrierPeriod => Some(self.carrier_period),
+            _ => None,
+        }
+    }
+
+    /// Applies a configuration command. Returns false if the command doesn't
+    /// change the settings (i.e: [`Command::Get`], or [`Command::Set`] for a
+    /// counter), or if the value is out of range.
+    pub fn apply(&mut self, command: Command) -> bool {
+        let Command::Set(value, data) = command else {
+            self.filter.apply(command);
+            return !matches!(command, Command::Get(_));
+        };
+        let setting = match value {
+            Value::Osccal => &mut self.osccal,
+            Value::Tolerance if Self::TOLERANCES.contains(&data) => {
+                &mut self.tolerance
+            }
+            Value::AckTimeout if data > 0 => &mut self.ack_timeout,
+            Value::CarrierPeriod if Self::CARRIER_PERIODS.contains(&data) => {
+                &mut self.carrier_period
+            }
+            _ => return false,
+        };
+        *setting = data;
+        true
+    }
+
+    /// Returns the block stored in the EEPROM.
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[..5].copy_from_slice(&[
+            Self::VERSION,
+            self.osccal,
+            self.tolerance,
+            self.ack_timeout,
+            self.carrier_period,
+        ]);
+        bytes[5..Self::LEN - 1].copy_from_slice(self.filter.as_bytes());
+        bytes[Self::LEN - 1] = crc8(&bytes[..Self::LEN - 1]);
+        bytes
+    }
+
+    /// Creates the settings from the block stored in the EEPROM. Returns
+    /// `None` if the version or the checksum don't match, or if a setting is
+    /// out of the range accepted by [`Settings::apply`].
+    pub fn from_bytes(bytes: &[u8; Self::LEN]) -> Option<Self> {
+        let (block, checksum) = bytes.split_at(Self::LEN - 1);
+        if block[0] != Self::VERSION || crc8(block) != checksum[0] {
+            return None;
+        }
+        let mut filter = [0; Filter::LEN];
+        filter.copy_from_slice(&block[5..]);
+        let mut settings = Self::new(block[1]);
+        settings.filter = Filter::from_bytes(filter);
+        let valid = [
+            (Value::Tolerance, block[2]),
+            (Value::AckTimeout, block[3]),
+            (Value::CarrierPeriod, block[4]),
+        ]
+        .into_iter()
+        .all(|(value, data)| settings.apply(Command::Set(value, data)));
+        valid.then_some(settings)
+    }
+}
+
+/// CRC-8 with polynomial 0x07.
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { crc << 1 ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Addresses and codes of the messages forwarded by the repeater.
+///
+/// A message is forwarded if both its address and its code are allowed. The
+/// filter is a bitmap with one bit per address followed by one bit per code,
+/// where allowed values are set to 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Filter {
+    bits: [u8; Filter::LEN],
+}
+
+impl Filter {
+    /// Size in bytes of the filter's bitmap.
+    pub const LEN: usize = 40;
+
+    /// Offset of the codes' bits in the bitmap.
+    const CODES_OFFSET: usize = 32;
+
+    /// Filter that forwards every message.
+    pub const ALLOW_ALL: Self = Self { bits: [0xFF; Self::LEN] };
+
+    /// Creates a filter from its bitmap.
+    pub const fn from_bytes(bits: [u8; Self::LEN]) -> Self {
+        Self { bits }
+    }
+
+    /// Returns the filter's bitmap.
+    pub const fn as_bytes(&self) -> &[u8; Self::LEN] {
+        &self.bits
+    }
+
+    /// Returns true if the message must be forwarded.
+    pub const fn allows(&self, message: &Message) -> bool {
+        self.bit(message.address as usize)
+            && self.bit(Self::code_bit(message.code))
+    }
+
+    /// Applies a configuration command to the filter. Commands that are not
+    /// about the filter are ignored.
+    pub fn apply(&mut self, command: Command) {
+        match command {
+            Command::AllowAddress(address) => {
+                self.set_bit(address as usize, true)
+            }
+            Command::BlockAddress(address) => {
+                self.set_bit(address as usize, false)
+            }
+            Command::AllowCode(code) => {
+                self.set_bit(Self::code_bit(code), true)
+            }
+            Command::BlockCode(code) => {
+                self.set_bit(Self::code_bit(code), false)
+            }
+            Command::AllowAllAddresses => {
+                self.bits[..Self::CODES_OFFSET].fill(0xFF)
+            }
+            Command::BlockAllAddresses => {
+                self.bits[..Self::CODES_OFFSET].fill(0)
+            }
+            Command::AllowAllCodes => {
+                self.bits[Self::CODES_OFFSET..].fill(0xFF)
+            }
+            Command::BlockAllCodes => self.bits[Self::CODES_OFFSET..].fill(0),
+            Command::Get(_) | Command::Set(..) => {}
+        }
+    }
+
+    /// Returns the index of a code's bit in the bitmap.
+    const fn code_bit(code: Code) -> usize {
+        Self::CODES_OFFSET * 8 + (code.as_u8() & 0b11_1111) as usize
+    }
+
+    const fn bit(&self, i: usize) -> bool {
+        self.bits[i / 8] & 1 << (i % 8) != 0
+    }
+
+    fn set_bit(&mut self, i: usize, value: bool) {
+        if value {
+            self.bits[i / 8] |= 1 << (i % 8);
+        } else {
+            self.bits[i / 8] &= !(1 << (i % 8));
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::ALLOW_ALL
+    }
+}
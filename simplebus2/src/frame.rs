@@ -0,0 +1,314 @@
+use core::ops::Deref;
+
+use crate::auth::Authenticated;
+use crate::Message;
+
+/// Frames exchanged over UART between the repeater and the remote unit.
+///
+/// Frames start with a little-endian 24-bit word where the 18 bits returned
+/// by [`Message::to_bits`] are shifted 6 positions to the left, and the 6
+/// least significant bits contain the frame's kind. [`Frame::Message`]
+/// consists of this word alone, [`Frame::Ack`] and [`Frame::NoAck`] share the
+/// same kind and are followed by a byte that tells them apart, and
+/// [`Frame::Authenticated`] is followed by its counter and authentication code
+/// (see [`crate::auth`]).
+///
+/// Frames are transmitted inside a [`Packet`], which may add a sequence
+/// number to the frame's kind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Frame {
+    /// A message seen on the bus, or a message that must be transmitted on
+    /// the bus.
+    Message(Message),
+    /// The target intercom acknowledged the given message on the bus.
+    Ack(Message),
+    /// The repeater transmitted the given message on the bus, but it wasn't
+    /// acknowledged, or it was garbled, after all the attempts.
+    NoAck(Message),
+    /// A message that must be transmitted on the bus, authenticated with the
+    /// key shared by the remote unit and the repeater.
+    Authenticated(Authenticated),
+}
+
+impl Frame {
+    /// Length in bytes of frames containing only a message.
+    pub const LEN: usize = 3;
+
+    /// Length in bytes of [`Frame::Ack`] and [`Frame::NoAck`].
+    pub const ACK_LEN: usize = Self::LEN + 1;
+
+    /// Length in bytes of the longest frame.
+    pub const MAX_LEN: usize = Self::LEN + Authenticated::TRAILER_LEN;
+
+    /// Returns the message contained in the frame.
+    pub const fn message(&self) -> &Message {
+        match self {
+            Frame::Message(message)
+            | Frame::Ack(message)
+            | Frame::NoAck(message) => message,
+            Frame::Authenticated(authenticated) => &authenticated.message,
+        }
+    }
+
+    /// Returns the frame's length in bytes.
+    pub const fn raw_len(&self) -> usize {
+        match self {
+            Frame::Message(_) => Self::LEN,
+            Frame::Ack(_) | Frame::NoAck(_) => Self::ACK_LEN,
+            Frame::Authenticated(_) => Self::MAX_LEN,
+        }
+    }
+
+    /// Creates a frame from its raw bytes. Returns `None` if the frame's kind
+    /// is unknown, the message is not valid, the frame's length doesn't
+    /// match its kind, or the bytes contain a [`Packet`] other than
+    /// [`Packet::Plain`].
+    pub fn from_raw_bytes(b: &[u8]) -> Option<Self> {
+        match Packet::from_raw_bytes(b)? {
+            Packet::Plain(frame) => Some(frame),
+            _ => None,
+        }
+    }
+
+    /// Returns the frame's raw bytes. This is the same as a
+    /// [`Packet::Plain`] containing this frame.
+    pub fn to_raw_bytes(&self) -> RawFrame {
+        Packet::Plain(*self).to_raw_bytes()
+    }
+
+    /// Returns the first 3 bytes of a frame.
+    pub(crate) const fn header(kind: u8, message: &Message) -> [u8; 3] {
+        let word = (message.to_bits() << 6 | kind as u32).to_le_bytes();
+        [word[0], word[1], word[2]]
+    }
+}
+
+/// Unit of transmission over UART.
+///
+/// The kind of the frame inside a packet is stored in the two least
+/// significant bits of the packet's first byte. Bit 5 is set in sequenced
+/// packets, which contain the sequence number in bits 2 to 4. Packets with
+/// bit 5 cleared are identical to the frames they contain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Packet {
+    /// A frame that is not acknowledged by the receiver.
+    Plain(Frame),
+    /// A frame that must be acknowledged by the receiver with a
+    /// [`Packet::LinkAck`] (see [`crate::link`]).
+    Sequenced {
+        /// Sequence number, from 0 to [`Packet::MAX_SEQ`].
+        seq: u8,
+        frame: Frame,
+    },
+    /// Acknowledges the reception of a [`Packet::Sequenced`] with the given
+    /// sequence number. Contains the message in the acknowledged frame.
+    LinkAck { seq: u8, message: Message },
+}
+
+impl Packet {
+    /// Maximum sequence number.
+    pub const MAX_SEQ: u8 = 0b111;
+
+    const KIND_MASK: u8 = 0b11;
+    pub(crate) const KIND_MESSAGE: u8 = 0;
+    const KIND_ACK: u8 = 1;
+    const KIND_AUTHENTICATED: u8 = 2;
+    const KIND_LINK_ACK: u8 = 3;
+    const ACKNOWLEDGED: u8 = 0;
+    const NOT_ACKNOWLEDGED: u8 = 1;
+    const SEQ_SHIFT: u8 = 2;
+    const SEQUENCED: u8 = 0b10_0000;
+
+    /// Returns the packet's length in bytes.
+    pub const fn raw_len(&self) -> usize {
+        match self {
+            Packet::Plain(frame) | Packet::Sequenced { frame, .. } => {
+                frame.raw_len()
+            }
+            Packet::LinkAck { .. } => Frame::LEN,
+        }
+    }
+
+    /// Creates a packet from its raw bytes. Returns `None` if the bytes are
+    /// not a valid packet, or the packet's length doesn't match its kind.
+    pub fn from_raw_bytes(b: &[u8]) -> Option<Self> {
+        match Self::parse(b)? {
+            Parse::Packet(packet) if packet.raw_len() == b.len() => {
+                Some(packet)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the packet's raw bytes.
+    pub fn to_raw_bytes(&self) -> RawFrame {
+        let mut raw = RawFrame { bytes: [0; Frame::MAX_LEN], len: 0 };
+        let (seq, frame) = match self {
+            Packet::Plain(frame) => (0, frame),
+            Packet::Sequenced { seq, frame } => (
+                Self::SEQUENCED | ((seq & Self::MAX_SEQ) << Self::SEQ_SHIFT),
+                frame,
+            ),
+            Packet::LinkAck { seq, message } => {
+                let kind = Self::KIND_LINK_ACK
+                    | Self::SEQUENCED
+                    | ((seq & Self::MAX_SEQ) << Self::SEQ_SHIFT);
+                raw.bytes[..3].copy_from_slice(&Frame::header(kind, message));
+                raw.len = Frame::LEN as u8;
+                return raw;
+            }
+        };
+        let (kind, message) = match frame {
+            Frame::Message(message) => (Self::KIND_MESSAGE, message),
+            Frame::Ack(message) => {
+                raw.bytes[3] = Self::ACKNOWLEDGED;
+                (Self::KIND_ACK, message)
+            }
+            Frame::NoAck(message) => {
+                raw.bytes[3] = Self::NOT_ACKNOWLEDGED;
+                (Self::KIND_ACK, message)
+            }
+            Frame::Authenticated(authenticated) => {
+                raw.bytes[3..7]
+                    .copy_from_slice(&authenticated.counter.to_le_bytes());
+                raw.bytes[7..]
+                    .copy_from_slice(&authenticated.tag.to_le_bytes());
+                (Self::KIND_AUTHENTICATED, &authenticated.message)
+            }
+        };
+        raw.bytes[..3].copy_from_slice(&Frame::header(kind | seq, message));
+        raw.len = frame.raw_len() as u8;
+        raw
+    }
+
+    /// Returns the packet at the start of `b`. The packet may be followed by
+    /// other bytes.
+    fn parse(b: &[u8]) -> Option<Parse> {
+        let [b0, b1, b2, trailer @ ..] = b else {
+            return Some(Parse::Incomplete);
+        };
+        let message =
+            Message::from_bits(u32::from_le_bytes([*b0, *b1, *b2, 0]) >> 6)?;
+        let kind = b0 & Self::KIND_MASK;
+        let seq = (b0 >> Self::SEQ_SHIFT) & Self::MAX_SEQ;
+        let sequenced = b0 & Self::SEQUENCED != 0;
+        let frame = match kind {
+            _ if !sequenced && seq != 0 => return None,
+            Self::KIND_MESSAGE => Frame::Message(message),
+            Self::KIND_ACK => match trailer.first() {
+                None => return Some(Parse::Incomplete),
+                Some(&Self::ACKNOWLEDGED) => Frame::Ack(message),
+                Some(&Self::NOT_ACKNOWLEDGED) => Frame::NoAck(message),
+                Some(_) => return None,
+            },
+            Self::KIND_AUTHENTICATED => {
+                if trailer.len() < Authenticated::TRAILER_LEN {
+                    return Some(Parse::Incomplete);
+                }
+                let mut counter = [0; 4];
+                let mut tag = [0; 8];
+                counter.copy_from_slice(&trailer[..4]);
+                tag.copy_from_slice(&trailer[4..12]);
+                Frame::Authenticated(Authenticated {
+                    message,
+                    counter: u32::from_le_bytes(counter),
+                    tag: u64::from_le_bytes(tag),
+                })
+            }
+            _ if sequenced => {
+                return Some(Parse::Packet(Packet::LinkAck { seq, message }))
+            }
+            _ => return None,
+        };
+        Some(Parse::Packet(if sequenced {
+            Packet::Sequenced { seq, frame }
+        } else {
+            Packet::Plain(frame)
+        }))
+    }
+}
+
+/// Result of parsing the start of a sequence of bytes.
+enum Parse {
+    /// More bytes are needed.
+    Incomplete,
+    Packet(Packet),
+}
+
+/// Raw bytes of a packet, returned by [`Packet::to_raw_bytes`] and
+/// [`Frame::to_raw_bytes`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawFrame {
+    bytes: [u8; Frame::MAX_LEN],
+    len: u8,
+}
+
+impl Deref for RawFrame {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+/// Splits a stream of bytes received over UART into packets.
+///
+/// Frames don't have any delimiter, so if a byte is lost or an extra byte is
+/// received, the bytes belonging to different frames would be mixed up.
+/// The decoder deals with this by sliding a window one byte at a time over
+/// the received bytes until the window starts with a valid packet header.
+///
+/// Receivers that can measure the time between bytes should also call
+/// [`FrameDecoder::reset`] when the line has been idle for longer than the
+/// time it takes to transmit a couple of bytes, as the bytes in a frame are
+/// always transmitted back-to-back. This way a damaged frame never affects
+/// the next one.
+#[derive(Clone, Debug, Default)]
+pub struct FrameDecoder {
+    buf: [u8; Frame::MAX_LEN],
+    len: u8,
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        Self { buf: [0; Frame::MAX_LEN], len: 0 }
+    }
+
+    /// Number of bytes received that are not part of a frame yet.
+    pub const fn pending(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Discards the bytes that are not part of a frame yet.
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    /// Processes a received byte. Returns a packet if the byte completes
+    /// one.
+    pub fn push(&mut self, byte: u8) -> Option<Packet> {
+        self.buf[self.len as usize] = byte;
+        self.len += 1;
+        loop {
+            let len = self.len as usize;
+            match Packet::parse(&self.buf[..len]) {
+                Some(Parse::Incomplete) => return None,
+                Some(Parse::Packet(packet)) => {
+                    self.len = 0;
+                    return Some(packet);
+                }
+                // The header, or the result byte of an acknowledgement, is
+                // not valid, discard the oldest byte. The remaining ones may
+                // start with a valid header, or even contain a whole packet.
+                // Once the header is valid the packet is complete, or it
+                // will be complete before the buffer is full.
+                None => {
+                    self.buf.copy_within(1..len, 0);
+                    self.len -= 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! SimpleBus2 protocol definitions shared by the intercom devices.
+//!
+//! SimpleBus2 messages are 18 bits long: 6 bits for the message code, 8 bits
+//! for the intercom address and 4 bits for the checksum, which is simply the
+//! number of bits set to 1 in the code and the address. This crate contains
+//! the [`Message`] type used by all the firmwares, together with the 3-byte
+//! packing used when messages are relayed over UART by the HC-12 modules
+//! (see [`Frame`]), the authentication of the commands sent over the radio
+//! link (see [`auth`]), the acknowledgement and retransmission of frames
+//! over that link (see [`link`]), the pulse length encoding used on the bus
+//! itself (see [`pulse`]), the grouping of the messages sent during a call
+//! (see [`session`]), and the table of addresses and codes seen on the bus
+//! (see [`census`]). The firmwares pass messages around in fixed-capacity
+//! queues (see [`queue`]), and the repeater can be configured from the
+//! remote unit (see [`config`]).
+//!
+//! The crate is `no_std` and builds for the ATtiny85 (repeater and buzzer),
+//! the RP2040 (remote unit) and the host. With the `std` feature it also
+//! contains the format of the traffic logs written and read by the host
+//! tools (see [`traffic`]).
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+pub use code::{Code, ParseCodeError};
+pub use frame::{Frame, FrameDecoder, Packet, RawFrame};
+pub use message::Message;
+
+pub mod auth;
+pub mod census;
+pub mod config;
+pub mod link;
+pub mod pulse;
+pub mod queue;
+pub mod session;
+#[cfg(feature = "std")]
+pub mod traffic;
+
+mod code;
+mod frame;
+mod message;
+
+#[cfg(test)]
+mod tests;
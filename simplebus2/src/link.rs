@@ -0,0 +1,247 @@
+//! Reliable delivery of frames over the radio link.
+//!
+//! The HC-12 modules don't tell the sender whether a frame was received. A
+//! [`Link`] sends every frame in a [`Packet::Sequenced`] and waits for the
+//! receiver's [`Packet::LinkAck`], retransmitting the frame if the
+//! acknowledgement doesn't arrive in time. Frames are sent one at a time, in
+//! the order they were queued.
+//!
+//! The receiver acknowledges every sequenced packet, including duplicates,
+//! which occur when an acknowledgement is lost. Duplicates are recognized by
+//! their sequence number and are not delivered twice. Plain packets are
+//! delivered as usual, so a link works with peers that don't use sequenced
+//! packets.
+//!
+//! The link doesn't have a clock of its own, timestamps in milliseconds are
+//! passed by the caller. They are allowed to wrap around.
+
+use crate::queue::Queue;
+use crate::{Frame, Packet};
+
+/// Link settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinkConfig {
+    /// If false, frames are sent in plain packets, which are neither
+    /// acknowledged nor retransmitted.
+    pub sequenced: bool,
+    /// Time to wait for an acknowledgement before retransmitting a frame.
+    pub timeout_ms: u32,
+    /// Number of retransmissions before giving up.
+    pub retries: u8,
+}
+
+impl LinkConfig {
+    /// Settings suitable for the HC-12 modules at 4800 bps. Transmitting the
+    /// longest packet takes ~30ms, and the repeater's main loop, which sends
+    /// the acknowledgements, doesn't run during the 3ms bursts it transmits
+    /// on the bus.
+    pub const DEFAULT: LinkConfig =
+        LinkConfig { sequenced: true, timeout_ms: 500, retries: 3 };
+
+    /// Packets with the same sequence number received within this time are
+    /// duplicates. Outside this window they are new frames, which happens
+    /// when the sender restarts its sequence numbers after a reboot.
+    pub const fn duplicate_window_ms(&self) -> u32 {
+        self.timeout_ms * (self.retries as u32 + 1)
+    }
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Counters kept by a [`Link`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinkStats {
+    /// Frames acknowledged by the receiver.
+    pub delivered: u16,
+    /// Frames retransmitted.
+    pub retransmitted: u16,
+    /// Frames dropped after all the retransmissions failed.
+    pub failed: u16,
+    /// Frames dropped because the queue was full.
+    pub overflows: u16,
+    /// Duplicated packets received.
+    pub duplicates: u16,
+}
+
+/// Actions requested by [`Link::poll`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Output {
+    /// The packet must be transmitted.
+    Transmit(Packet),
+    /// The frame was not acknowledged after all the retransmissions.
+    Failed(Frame),
+}
+
+/// A sequenced packet waiting for its acknowledgement.
+#[derive(Clone, Copy, Debug)]
+struct InFlight {
+    seq: u8,
+    frame: Frame,
+    sent_ms: u32,
+    retries: u8,
+}
+
+/// One end of the radio link, with room for `N` queued frames.
+#[derive(Clone, Debug)]
+pub struct Link<const N: usize> {
+    config: LinkConfig,
+    queue: Queue<Frame, N>,
+    next_seq: u8,
+    in_flight: Option<InFlight>,
+    /// Acknowledgement that must be transmitted.
+    ack: Option<Packet>,
+    /// Sequence number and time of the last sequenced packet received.
+    last_received: Option<(u8, u32)>,
+    stats: LinkStats,
+}
+
+impl<const N: usize> Link<N> {
+    pub const fn new(config: LinkConfig) -> Self {
+        Self {
+            config,
+            queue: Queue::new(),
+            next_seq: 0,
+            in_flight: None,
+            ack: None,
+            last_received: None,
+            stats: LinkStats {
+                delivered: 0,
+                retransmitted: 0,
+                failed: 0,
+                overflows: 0,
+                duplicates: 0,
+            },
+        }
+    }
+
+    pub const fn config(&self) -> &LinkConfig {
+        &self.config
+    }
+
+    pub const fn stats(&self) -> &LinkStats {
+        &self.stats
+    }
+
+    /// Returns the counters, which can be reset.
+    pub fn stats_mut(&mut self) -> &mut LinkStats {
+        &mut self.stats
+    }
+
+    /// Returns the number of frames that can be queued before the queue is
+    /// full.
+    pub const fn room(&self) -> usize {
+        N - self.queue.len()
+    }
+
+    /// Returns true if there are no frames waiting to be sent or
+    /// acknowledged.
+    pub const fn is_idle(&self) -> bool {
+        self.queue.is_empty() && self.in_flight.is_none() && self.ack.is_none()
+    }
+
+    /// Queues a frame for being sent. The frame is dropped if the queue is
+    /// full, in that case it's returned back as an error.
+    pub fn send(&mut self, frame: Frame) -> Result<(), Frame> {
+        let result = self.queue.push(frame);
+        if result.is_err() {
+            self.stats.overflows = self.stats.overflows.wrapping_add(1);
+        }
+        result
+    }
+
+    /// Processes a packet received from the other end. Returns the frame in
+    /// the packet, unless the packet is an acknowledgement or a duplicate.
+    ///
+    /// Sequenced packets must be acknowledged, so [`Link::poll`] must be
+    /// called after this.
+    pub fn receive(&mut self, packet: Packet, now_ms: u32) -> Option<Frame> {
+        match packet {
+            Packet::Plain(frame) => Some(frame),
+            Packet::Sequenced { seq, frame } => {
+                self.ack =
+                    Some(Packet::LinkAck { seq, message: *frame.message() });
+                let duplicate = matches!(
+                    self.last_received,
+                    Some((last_seq, last_ms)) if last_seq == seq
+                        && now_ms.wrapping_sub(last_ms)
+                            < self.config.duplicate_window_ms()
+                );
+                self.last_received = Some((seq, now_ms));
+                if duplicate {
+                    self.stats.duplicates =
+                        self.stats.duplicates.wrapping_add(1);
+                    None
+                } else {
+                    Some(frame)
+                }
+            }
+            Packet::LinkAck { seq, message } => {
+                if let Some(in_flight) = self.in_flight {
+                    if in_flight.seq == seq
+                        && in_flight.frame.message() == &message
+                    {
+                        self.in_flight = None;
+                        self.stats.delivered =
+                            self.stats.delivered.wrapping_add(1);
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    /// Returns the next action required by the link, if any. Must be called
+    /// repeatedly until it returns `None` after queueing or receiving
+    /// frames, and periodically while the link is not idle, so that lost
+    /// frames are retransmitted.
+    pub fn poll(&mut self, now_ms: u32) -> Option<Output> {
+        if let Some(ack) = self.ack.take() {
+            return Some(Output::Transmit(ack));
+        }
+        if let Some(in_flight) = &mut self.in_flight {
+            if now_ms.wrapping_sub(in_flight.sent_ms) < self.config.timeout_ms
+            {
+                return None;
+            }
+            if in_flight.retries == self.config.retries {
+                let frame = in_flight.frame;
+                self.in_flight = None;
+                self.stats.failed = self.stats.failed.wrapping_add(1);
+                return Some(Output::Failed(frame));
+            }
+            in_flight.retries += 1;
+            in_flight.sent_ms = now_ms;
+            self.stats.retransmitted =
+                self.stats.retransmitted.wrapping_add(1);
+            return Some(Output::Transmit(Packet::Sequenced {
+                seq: in_flight.seq,
+                frame: in_flight.frame,
+            }));
+        }
+        let frame = self.queue.pop()?;
+        if !self.config.sequenced {
+            return Some(Output::Transmit(Packet::Plain(frame)));
+        }
+        let seq = self.next_seq;
+        self.next_seq = (seq + 1) & Packet::MAX_SEQ;
+        self.in_flight =
+            Some(InFlight { seq, frame, sent_ms: now_ms, retries: 0 });
+        Some(Output::Transmit(Packet::Sequenced { seq, frame }))
+    }
+
+    /// Returns the time until [`Link::poll`] must be called for
+    /// retransmitting a frame, or `None` if no frame is waiting for its
+    /// acknowledgement.
+    pub fn next_timeout_ms(&self, now_ms: u32) -> Option<u32> {
+        let in_flight = self.in_flight.as_ref()?;
+        let elapsed = now_ms.wrapping_sub(in_flight.sent_ms);
+        Some(self.config.timeout_ms.saturating_sub(elapsed))
+    }
+}
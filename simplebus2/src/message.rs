@@ -0,0 +1,84 @@
+use crate::{Code, Frame, Packet};
+
+/// Describes a SimpleBus2 message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(
+    any(test, feature = "serde"),
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct Message {
+    /// Message code. Serialized by name when the code is known.
+    pub code: Code,
+    /// Intercom address. This is the target or the source of the message,
+    /// depending on the type of message. For instance, in `OpenDoor`
+    /// messages this contains message's source (i.e: the address of the
+    /// intercom sending the message), but in `Call` messages this contains
+    /// the message's target (i.e: the address of the intercom that it being
+    /// called).
+    pub address: u8,
+}
+
+impl Message {
+    /// Number of bits in a message transmitted over the bus.
+    pub const NUM_BITS: u8 = 18;
+
+    pub const fn new(code: Code, address: u8) -> Self {
+        Self { code, address }
+    }
+
+    /// Returns the message's checksum, which is the number of bits set to 1
+    /// in the code and the address.
+    pub const fn checksum(&self) -> u8 {
+        (u8::count_ones(self.code.as_u8() & 0b11_1111)
+            + u8::count_ones(self.address)) as u8
+    }
+
+    /// Creates a message from its code, address and checksum, as they were
+    /// received. Returns `None` if the checksum doesn't match or the code
+    /// doesn't fit in 6 bits.
+    ///
+    /// A message where all the bits are zero is rejected too, as this is
+    /// what we get from a line that is stuck.
+    pub fn validate(code: u8, address: u8, checksum: u8) -> Option<Self> {
+        let msg = Self::new(Code::from_u8(code), address);
+        if code <= 0b11_1111 && checksum != 0 && msg.checksum() == checksum {
+            Some(msg)
+        } else {
+            None
+        }
+    }
+
+    /// Creates a message from the 18 bits received over the bus. The first
+    /// bit received is the least significant one.
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        let code = (bits & 0b11_1111) as u8;
+        let address = ((bits >> 6) & 0b1111_1111) as u8;
+        let checksum = ((bits >> 14) & 0b1111) as u8;
+        Self::validate(code, address, checksum)
+    }
+
+    /// Returns the 18 bits that must be transmitted over the bus, the least
+    /// significant bit goes first.
+    pub const fn to_bits(&self) -> u32 {
+        (self.code.as_u8() & 0b11_1111) as u32
+            | (self.address as u32) << 6
+            | (self.checksum() as u32) << 14
+    }
+
+    /// Creates a message from the 3 bytes used for relaying messages over
+    /// UART. Returns `None` if the message is not valid, or if the bytes
+    /// don't correspond to a [`Frame::Message`].
+    pub fn from_raw_bytes(b: &[u8; 3]) -> Option<Self> {
+        match Frame::from_raw_bytes(b)? {
+            Frame::Message(message) => Some(message),
+            _ => None,
+        }
+    }
+
+    /// Returns the 3 bytes used for relaying the message over UART. This is
+    /// the same as a [`Frame::Message`] containing this message.
+    pub const fn to_raw_bytes(&self) -> [u8; 3] {
+        Frame::header(Packet::KIND_MESSAGE, self)
+    }
+}
@@ -0,0 +1,375 @@
+//! Pulse length encoding used on the SimpleBus2 line.
+//!
+//! Each bit consists of a burst of pulses at 25kHz during 3ms, followed by a
+//! silence that is 3ms long for zeros and 6ms long for ones. Messages start
+//! with a preamble consisting of a burst followed by a 17ms silence.
+//!
+//! The [`Decoder`] is a state machine that receives the timestamps of the
+//! edges seen on the line (i.e: the comparator's output) and produces
+//! [`Event`]s. The [`encode`] function does the opposite, converting a
+//! [`Message`] into the sequence of bursts and silences that must be
+//! transmitted.
+//!
+//! After each message the target intercom responds with an acknowledgement
+//! consisting of [`ACK_BURSTS`] bursts separated by 3ms silences. The decoder
+//! reports every valid message with [`Event::Message`], followed by either
+//! [`Event::Ack`] or [`Event::NoAck`].
+
+use crate::Message;
+
+/// Durations used in the pulse length encoding, in microseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timing {
+    /// Duration of every burst.
+    pub burst_us: u32,
+    /// Silence after a burst that represents a zero.
+    pub zero_us: u32,
+    /// Silence after a burst that represents a one.
+    pub one_us: u32,
+    /// Silence after the first burst in a message.
+    pub preamble_us: u32,
+    /// Maximum difference between the expected and the measured silence.
+    pub tolerance_us: u32,
+    /// Maximum silence between the end of a message and the first burst of
+    /// the acknowledgement.
+    pub ack_timeout_us: u32,
+}
+
+impl Timing {
+    /// Timing used by the devices in the bus.
+    pub const DEFAULT: Timing = Timing {
+        burst_us: 3_000,
+        zero_us: 3_000,
+        one_us: 6_000,
+        preamble_us: 17_000,
+        tolerance_us: 1_000,
+        ack_timeout_us: 20_000,
+    };
+
+    /// Classifies a silence according to its duration.
+    ///
+    /// Returns `None` if the duration doesn't correspond to any symbol. This
+    /// is also the case for the short intervals between consecutive pulses in
+    /// a burst.
+    pub const fn classify(&self, gap_us: u32) -> Option<Symbol> {
+        if self.matches(gap_us, self.zero_us) {
+            Some(Symbol::Zero)
+        } else if self.matches(gap_us, self.one_us) {
+            Some(Symbol::One)
+        } else if self.matches(gap_us, self.preamble_us) {
+            Some(Symbol::Preamble)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if `gap_us` is shorter than any silence, which means that
+    /// it's the interval between two edges within the same burst.
+    pub const fn is_burst(&self, gap_us: u32) -> bool {
+        gap_us < self.zero_us.saturating_sub(self.tolerance_us)
+    }
+
+    /// Returns the silence that represents the given symbol.
+    pub const fn gap_us(&self, symbol: Symbol) -> u32 {
+        match symbol {
+            Symbol::Zero => self.zero_us,
+            Symbol::One => self.one_us,
+            Symbol::Preamble => self.preamble_us,
+        }
+    }
+
+    const fn matches(&self, gap_us: u32, expected_us: u32) -> bool {
+        gap_us >= expected_us.saturating_sub(self.tolerance_us)
+            && gap_us <= expected_us.saturating_add(self.tolerance_us)
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Meaning of the silence that follows a burst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Symbol {
+    /// A bit set to 0.
+    Zero,
+    /// A bit set to 1.
+    One,
+    /// Start of a message.
+    Preamble,
+}
+
+/// A burst of pulses followed by a silence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Pulse {
+    /// Duration of the burst, in microseconds.
+    pub burst_us: u32,
+    /// Duration of the silence after the burst, in microseconds. This is
+    /// zero for the last burst in a message.
+    pub gap_us: u32,
+}
+
+/// Returns the bursts and silences that must be transmitted for sending
+/// `message` over the bus.
+pub const fn encode(message: &Message, timing: &Timing) -> Pulses {
+    Pulses { timing: *timing, bits: message.to_bits(), index: 0 }
+}
+
+/// Iterator returned by [`encode`].
+///
+/// The message is encoded on the fly so that the whole sequence of pulses
+/// doesn't need to be stored in memory.
+#[derive(Clone, Debug)]
+pub struct Pulses {
+    timing: Timing,
+    bits: u32,
+    index: u8,
+}
+
+impl Pulses {
+    /// Number of pulses in a message: the preamble, one pulse per bit and a
+    /// final burst that terminates the last bit.
+    pub const LEN: u8 = Message::NUM_BITS + 2;
+}
+
+impl Iterator for Pulses {
+    type Item = Pulse;
+
+    fn next(&mut self) -> Option<Pulse> {
+        let gap_us = match self.index {
+            0 => self.timing.preamble_us,
+            i if i <= Message::NUM_BITS => {
+                if self.bits & (1 << (i - 1)) != 0 {
+                    self.timing.one_us
+                } else {
+                    self.timing.zero_us
+                }
+            }
+            i if i < Self::LEN => 0,
+            _ => return None,
+        };
+        self.index += 1;
+        Some(Pulse { burst_us: self.timing.burst_us, gap_us })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = Self::LEN.saturating_sub(self.index) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Pulses {}
+
+/// Number of bursts in the acknowledgement sent by the target intercom after
+/// receiving a message. The bursts are separated by the same silence used for
+/// zeros.
+pub const ACK_BURSTS: u8 = 4;
+
+/// Returns the bursts and silences sent by an intercom for acknowledging a
+/// message.
+pub fn encode_ack(timing: &Timing) -> impl Iterator<Item = Pulse> {
+    let timing = *timing;
+    (0..ACK_BURSTS).map(move |i| Pulse {
+        burst_us: timing.burst_us,
+        gap_us: if i < ACK_BURSTS - 1 { timing.zero_us } else { 0 },
+    })
+}
+
+/// Events produced by the [`Decoder`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// A valid message was received.
+    Message(Message),
+    /// All the bits in a message were received, but the checksum is wrong.
+    /// Contains the received bits, the first one is the least significant.
+    BadChecksum(u32),
+    /// The target intercom acknowledged the message.
+    Ack(Message),
+    /// The message was not acknowledged. This is reported when the
+    /// acknowledgement is malformed, when another message starts, or when
+    /// [`Decoder::timeout`] is called.
+    NoAck(Message),
+    /// A message was interrupted by a silence that doesn't correspond to any
+    /// symbol, or by a new preamble. Contains the bits received so far.
+    Truncated { bits: u32, count: u8 },
+}
+
+/// Decodes the edges seen on the line into messages.
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    timing: Timing,
+    last_edge_us: Option<u32>,
+    state: State,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Waiting for a preamble.
+    Idle,
+    /// A preamble was received, `count` bits received so far.
+    Receiving { bits: u32, count: u8 },
+    /// A message was received, waiting for the first burst of the
+    /// acknowledgement.
+    AwaitingAck { message: Message },
+    /// Receiving the acknowledgement, `gaps` silences received so far.
+    Ack { message: Message, gaps: u8 },
+}
+
+impl Decoder {
+    pub const fn new(timing: Timing) -> Self {
+        Self { timing, last_edge_us: None, state: State::Idle }
+    }
+
+    /// Returns the timing used by the decoder.
+    pub const fn timing(&self) -> &Timing {
+        &self.timing
+    }
+
+    /// Returns true if the decoder is neither in the middle of a message nor
+    /// waiting for an acknowledgement.
+    pub const fn is_idle(&self) -> bool {
+        matches!(self.state, State::Idle)
+    }
+
+    /// Discards any partially received message.
+    pub fn reset(&mut self) {
+        self.last_edge_us = None;
+        self.state = State::Idle;
+    }
+
+    /// Waits for the acknowledgement of `message`, which has been transmitted
+    /// by some other means. This is used by devices that transmit messages
+    /// and want to know if they were acknowledged.
+    ///
+    /// With [`Decoder::edge`], the first edge seen afterwards starts the
+    /// acknowledgement. With [`Decoder::gap`], the first interval must be the
+    /// silence between the end of the message and the acknowledgement.
+    pub fn expect_ack(&mut self, message: Message) {
+        self.last_edge_us = None;
+        self.state = State::AwaitingAck { message };
+    }
+
+    /// Signals that the line has been silent for longer than any of the
+    /// silences in the encoding. This completes the wait for an
+    /// acknowledgement, or discards a partially received message.
+    pub fn timeout(&mut self) -> Option<Event> {
+        let state = self.state;
+        self.reset();
+        match state {
+            State::Idle => None,
+            State::Receiving { bits, count } => {
+                Some(Event::Truncated { bits, count })
+            }
+            State::AwaitingAck { message } | State::Ack { message, .. } => {
+                Some(Event::NoAck(message))
+            }
+        }
+    }
+
+    /// Processes an edge seen at `timestamp_us`. Timestamps are expected to
+    /// be monotonic, but they are allowed to wrap around.
+    pub fn edge(&mut self, timestamp_us: u32) -> Option<Event> {
+        let Some(last_edge_us) = self.last_edge_us.replace(timestamp_us)
+        else {
+            // The silence before the acknowledgement is unknown after
+            // `expect_ack`, the first edge is its first burst.
+            if let State::AwaitingAck { message } = self.state {
+                self.state = State::Ack { message, gaps: 0 };
+            }
+            return None;
+        };
+        self.gap(timestamp_us.wrapping_sub(last_edge_us))
+    }
+
+    /// Processes an edge that occurred `gap_us` microseconds after the
+    /// previous one. This is an alternative to [`Decoder::edge`] for callers
+    /// that measure the interval between edges directly.
+    pub fn gap(&mut self, gap_us: u32) -> Option<Event> {
+        if self.timing.is_burst(gap_us) {
+            return None;
+        }
+        let symbol = self.timing.classify(gap_us);
+        match (self.state, symbol) {
+            (State::Idle, Some(Symbol::Preamble)) => {
+                self.state = State::Receiving { bits: 0, count: 0 };
+                None
+            }
+            (State::Idle, _) => None,
+            (State::Receiving { bits, count }, Some(Symbol::Preamble)) => {
+                self.state = State::Receiving { bits: 0, count: 0 };
+                Some(Event::Truncated { bits, count })
+            }
+            (State::Receiving { bits, count }, None) => {
+                self.state = State::Idle;
+                Some(Event::Truncated { bits, count })
+            }
+            (State::Receiving { mut bits, count }, Some(symbol)) => {
+                if symbol == Symbol::One {
+                    bits |= 1 << count;
+                }
+                let count = count + 1;
+                if count < Message::NUM_BITS {
+                    self.state = State::Receiving { bits, count };
+                    return None;
+                }
+                match Message::from_bits(bits) {
+                    Some(message) => {
+                        self.state = State::AwaitingAck { message };
+                        Some(Event::Message(message))
+                    }
+                    None => {
+                        self.state = State::Idle;
+                        Some(Event::BadChecksum(bits))
+                    }
+                }
+            }
+            // A new message starts without an acknowledgement for the
+            // previous one.
+            (
+                State::AwaitingAck { message } | State::Ack { message, .. },
+                Some(Symbol::Preamble),
+            ) => {
+                self.state = State::Receiving { bits: 0, count: 0 };
+                Some(Event::NoAck(message))
+            }
+            // The silence between the message and the acknowledgement may be
+            // longer than the silences within the acknowledgement, and it's
+            // not one of them.
+            (State::AwaitingAck { message }, _)
+                if gap_us <= self.timing.ack_timeout_us =>
+            {
+                self.state = State::Ack { message, gaps: 0 };
+                None
+            }
+            (State::Ack { message, gaps }, Some(Symbol::Zero)) => {
+                let gaps = gaps + 1;
+                if gaps < ACK_BURSTS - 1 {
+                    self.state = State::Ack { message, gaps };
+                    None
+                } else {
+                    self.state = State::Idle;
+                    Some(Event::Ack(message))
+                }
+            }
+            (
+                State::AwaitingAck { message } | State::Ack { message, .. },
+                _,
+            ) => {
+                self.state = State::Idle;
+                Some(Event::NoAck(message))
+            }
+        }
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new(Timing::DEFAULT)
+    }
+}
@@ -0,0 +1,115 @@
+//! Fixed-capacity FIFO queue.
+//!
+//! The firmwares don't have an allocator, a [`Queue`] holds up to `N` items
+//! in a ring buffer, which is used for passing messages between interrupt
+//! handlers and the main loop, and for holding messages until they can be
+//! transmitted.
+//!
+//! The slots are left uninitialized instead of holding an `Option`, which
+//! would take an extra byte for most items. The ATtiny85 of the repeater
+//! only has 512 bytes of RAM.
+
+use core::fmt;
+use core::mem::MaybeUninit;
+
+/// A first-in first-out queue with room for `N` items.
+pub struct Queue<T, const N: usize> {
+    /// Only the `len` slots starting from `head`, wrapping around, are
+    /// initialized.
+    items: [MaybeUninit<T>; N],
+    /// Index of the oldest item.
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> Queue<T, N> {
+    pub const fn new() -> Self {
+        Self { items: [MaybeUninit::uninit(); N], head: 0, len: 0 }
+    }
+
+    /// Adds an item at the end of the queue. If the queue is full the item
+    /// is returned back.
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.len == N {
+            return Err(item);
+        }
+        self.items[(self.head + self.len) % N] = MaybeUninit::new(item);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes the oldest item from the queue.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        // SAFETY: The oldest item is initialized, and it's `Copy` so the
+        // slot can be read more than once.
+        let item = unsafe { self.items[self.head].assume_init() };
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(item)
+    }
+
+    /// Returns the oldest item without removing it.
+    pub fn peek(&self) -> Option<&T> {
+        if self.len == 0 {
+            return None;
+        }
+        // SAFETY: The oldest item is initialized.
+        Some(unsafe { self.items[self.head].assume_init_ref() })
+    }
+
+    /// Returns the oldest item without removing it, allowing to modify it.
+    pub fn peek_mut(&mut self) -> Option<&mut T> {
+        if self.len == 0 {
+            return None;
+        }
+        // SAFETY: The oldest item is initialized.
+        Some(unsafe { self.items[self.head].assume_init_mut() })
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub const fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Removes all the items.
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    /// Returns the items from the oldest to the newest.
+    fn iter(&self) -> impl Iterator<Item = &T> {
+        // SAFETY: The `len` slots starting from `head` are initialized.
+        (0..self.len).map(move |i| unsafe {
+            self.items[(self.head + i) % N].assume_init_ref()
+        })
+    }
+}
+
+impl<T: Copy, const N: usize> Default for Queue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy, const N: usize> Clone for Queue<T, N> {
+    fn clone(&self) -> Self {
+        Self { items: self.items, head: self.head, len: self.len }
+    }
+}
+
+impl<T: Copy + fmt::Debug, const N: usize> fmt::Debug for Queue<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
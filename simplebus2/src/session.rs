@@ -0,0 +1,184 @@
+//! Grouping of the messages sent during a call into a single session.
+//!
+//! When someone calls an apartment from the building's entry door, the entry
+//! panel sends two [`Code::Call`] messages followed by a [`Code::CallEnd`],
+//! and the intercom rings once for each of them. A [`CallTracker`] receives
+//! the messages seen on the bus and produces a [`CallEvent::Started`] when a
+//! call starts, a [`CallEvent::Ringing`] for every ring after the first one,
+//! and a [`CallEvent::Ended`] when the call ends, so that notifications are
+//! sent once per visitor.
+//!
+//! Calls end with the [`Code::CallEnd`] message, or after some time without
+//! rings if that message is lost. The tracker doesn't have a clock of its
+//! own, timestamps in milliseconds are passed by the caller. They are allowed
+//! to wrap around.
+
+use crate::{Code, Message};
+
+/// Events produced by the [`CallTracker`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(
+    any(test, feature = "serde"),
+    derive(serde::Serialize),
+    serde(tag = "event", rename_all = "snake_case")
+)]
+pub enum CallEvent {
+    /// Someone is calling the intercom with the given address. This is
+    /// the first ring.
+    #[cfg_attr(any(test, feature = "serde"), serde(rename = "call_started"))]
+    Started { address: u8 },
+    /// The intercom rings again. `rings` is the number of rings so far,
+    /// including this one.
+    Ringing { address: u8, rings: u8 },
+    /// The call ended after the given number of rings. The duration goes
+    /// from the first ring to the last one.
+    #[cfg_attr(any(test, feature = "serde"), serde(rename = "call_ended"))]
+    Ended { address: u8, duration_ms: u32, rings: u8 },
+}
+
+impl CallEvent {
+    /// Returns the address of the intercom being called.
+    pub const fn address(&self) -> u8 {
+        match self {
+            CallEvent::Started { address }
+            | CallEvent::Ringing { address, .. }
+            | CallEvent::Ended { address, .. } => *address,
+        }
+    }
+}
+
+/// A call in progress.
+#[derive(Clone, Copy, Debug)]
+struct Call {
+    address: u8,
+    started_ms: u32,
+    last_ring_ms: u32,
+    rings: u8,
+    /// The call has ended, but [`CallTracker::poll`] hasn't reported it yet.
+    ended: bool,
+}
+
+impl Call {
+    const fn ended(&self) -> CallEvent {
+        CallEvent::Ended {
+            address: self.address,
+            duration_ms: self.last_ring_ms.wrapping_sub(self.started_ms),
+            rings: self.rings,
+        }
+    }
+}
+
+/// Groups the messages sent during a call. See the [module](self)
+/// documentation.
+#[derive(Clone, Debug)]
+pub struct CallTracker {
+    timeout_ms: u32,
+    call: Option<Call>,
+    /// Event produced by the last message that hasn't been returned yet.
+    pending: Option<CallEvent>,
+}
+
+impl CallTracker {
+    /// Time without rings after which a call is considered ended, if the
+    /// [`Code::CallEnd`] message is not received. The entry panel rings every
+    /// few seconds.
+    pub const DEFAULT_TIMEOUT_MS: u32 = 15_000;
+
+    pub const fn new(timeout_ms: u32) -> Self {
+        Self { timeout_ms, call: None, pending: None }
+    }
+
+    /// Returns true if a call is in progress.
+    pub const fn in_call(&self) -> bool {
+        self.call.is_some()
+    }
+
+    /// Processes a message seen on the bus at `now_ms`. Returns the event
+    /// produced by the message, if any.
+    ///
+    /// A message may produce more than one event, for instance when a call
+    /// starts while another one is in progress. The remaining ones are
+    /// returned by [`CallTracker::poll`], which must be called after every
+    /// message.
+    pub fn message(
+        &mut self,
+        message: &Message,
+        now_ms: u32,
+    ) -> Option<CallEvent> {
+        let ends = match message.code {
+            Code::Call => false,
+            Code::CallEnd => true,
+            _ => return None,
+        };
+        let address = message.address;
+        if let Some(call) = &mut self.call {
+            if call.address == address && !call.ended {
+                call.rings = call.rings.saturating_add(1);
+                call.last_ring_ms = now_ms;
+                if ends {
+                    let ended = call.ended();
+                    self.call = None;
+                    return Some(ended);
+                }
+                return Some(CallEvent::Ringing {
+                    address,
+                    rings: call.rings,
+                });
+            }
+        }
+        // A new call, which ends the previous one if any. If the message
+        // is a `CallEnd` the previous messages were lost, and the call ends
+        // right after starting.
+        let previous = self.call.take().map(|call| call.ended());
+        self.call = Some(Call {
+            address,
+            started_ms: now_ms,
+            last_ring_ms: now_ms,
+            rings: 1,
+            ended: ends,
+        });
+        let started = CallEvent::Started { address };
+        match previous {
+            Some(ended) => {
+                self.pending = Some(started);
+                Some(ended)
+            }
+            None => Some(started),
+        }
+    }
+
+    /// Returns the events pending from the last message, or ends the call in
+    /// progress if there hasn't been any ring for longer than the timeout.
+    pub fn poll(&mut self, now_ms: u32) -> Option<CallEvent> {
+        if let Some(event) = self.pending.take() {
+            return Some(event);
+        }
+        let call = self.call?;
+        if !call.ended
+            && now_ms.wrapping_sub(call.last_ring_ms) < self.timeout_ms
+        {
+            return None;
+        }
+        self.call = None;
+        Some(call.ended())
+    }
+
+    /// Returns the time left until [`CallTracker::poll`] may return an event,
+    /// or `None` if there's no call in progress. This tells how long the
+    /// caller can wait before polling again.
+    pub fn next_timeout_ms(&self, now_ms: u32) -> Option<u32> {
+        let call = self.call?;
+        if self.pending.is_some() || call.ended {
+            return Some(0);
+        }
+        let elapsed = now_ms.wrapping_sub(call.last_ring_ms);
+        Some(self.timeout_ms.saturating_sub(elapsed))
+    }
+}
+
+impl Default for CallTracker {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_TIMEOUT_MS)
+    }
+}
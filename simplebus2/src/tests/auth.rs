@@ -0,0 +1,163 @@
+use crate::auth::{siphash24, AuthError, Key, Signer, Verifier};
+use crate::{Code, Frame, Message};
+
+const KEY: Key = match Key::from_hex("000102030405060708090a0b0c0d0e0f") {
+    Some(key) => key,
+    None => panic!(),
+};
+
+/// Test vectors from the SipHash paper.
+#[test]
+fn siphash() {
+    let key: [u8; 16] = core::array::from_fn(|i| i as u8);
+    let data: Vec<u8> = (0..15).collect();
+    assert_eq!(siphash24(&key, &[]), 0x726fdb47dd0e0e31);
+    assert_eq!(siphash24(&key, &data), 0xa129ca6149be45e5);
+    assert_eq!(siphash24(&key, &data[..8]), 0x93f5f5799a932462);
+}
+
+#[test]
+fn key_from_hex() {
+    let key: [u8; 16] = core::array::from_fn(|i| i as u8);
+    assert_eq!(KEY, Key::new(key));
+    assert_eq!(
+        Key::from_hex("000102030405060708090A0B0C0D0E0F"),
+        Some(Key::new(key))
+    );
+    assert_eq!(Key::from_hex("000102030405060708090a0b0c0d0e"), None);
+    assert_eq!(Key::from_hex("000102030405060708090a0b0c0d0e0f0"), None);
+    assert_eq!(Key::from_hex("000102030405060708090a0b0c0d0e0g"), None);
+    assert_eq!(format!("{KEY:?}"), "Key(..)");
+}
+
+#[test]
+fn verify() {
+    let open_door = Message::new(Code::OpenDoor, 12);
+    let mut signer = Signer::new(KEY, 0);
+    let mut verifier = Verifier::new(KEY, 0);
+
+    let first = signer.sign(open_door);
+    let second = signer.sign(open_door);
+    assert_eq!(signer.counter(), 2);
+    assert_eq!(verifier.verify(&first), Ok(open_door));
+    assert_eq!(verifier.verify(&second), Ok(open_door));
+    assert_eq!(verifier.counter(), 2);
+
+    // Recorded frames can't be replayed.
+    assert_eq!(verifier.verify(&first), Err(AuthError::Replayed(open_door)));
+    assert_eq!(verifier.verify(&second), Err(AuthError::Replayed(open_door)));
+
+    // Frames can be lost.
+    signer.sign(open_door);
+    assert_eq!(verifier.verify(&signer.sign(open_door)), Ok(open_door));
+    assert_eq!(verifier.counter(), 4);
+}
+
+#[test]
+fn refused() {
+    let open_door = Message::new(Code::OpenDoor, 12);
+    let mut verifier = Verifier::new(KEY, 0);
+
+    assert_eq!(
+        verifier.verify(&Frame::Message(open_door)),
+        Err(AuthError::Unauthenticated)
+    );
+    assert_eq!(
+        verifier.verify(&Frame::Ack(open_door)),
+        Err(AuthError::Unauthenticated)
+    );
+
+    // Signed with another key.
+    let mut signer = Signer::new(Key::new([0; 16]), 0);
+    assert_eq!(
+        verifier.verify(&signer.sign(open_door)),
+        Err(AuthError::BadTag)
+    );
+
+    // The message, the counter or the tag were altered.
+    let Frame::Authenticated(genuine) = Signer::new(KEY, 0).sign(open_door)
+    else {
+        unreachable!()
+    };
+    let mut altered = genuine;
+    altered.message.address = 11;
+    assert_eq!(
+        verifier.verify(&Frame::Authenticated(altered)),
+        Err(AuthError::BadTag)
+    );
+    let mut altered = genuine;
+    altered.counter += 1;
+    assert_eq!(
+        verifier.verify(&Frame::Authenticated(altered)),
+        Err(AuthError::BadTag)
+    );
+    let mut altered = genuine;
+    altered.tag ^= 1;
+    assert_eq!(
+        verifier.verify(&Frame::Authenticated(altered)),
+        Err(AuthError::BadTag)
+    );
+
+    // None of the above changed the counter.
+    assert_eq!(verifier.counter(), 0);
+    assert_eq!(verifier.verify(&Frame::Authenticated(genuine)), Ok(open_door));
+}
+
+#[test]
+fn replayed() {
+    let open_door = Message::new(Code::OpenDoor, 12);
+    let mut verifier = Verifier::new(KEY, 0);
+    let mut signer = Signer::new(KEY, 0);
+    let frame = signer.sign(open_door);
+    assert_eq!(verifier.verify(&frame), Ok(open_door));
+
+    // Someone recorded the frame and transmits it again. The verifier
+    // refuses it, and its notice doesn't make the signer sign the message
+    // again.
+    assert_eq!(verifier.verify(&frame), Err(AuthError::Replayed(open_door)));
+    let Frame::Authenticated(notice) = verifier.notice(open_door) else {
+        unreachable!()
+    };
+    assert!(!signer.sync(&notice));
+    assert_eq!(signer.counter(), 1);
+}
+
+#[test]
+fn sync() {
+    let open_door = Message::new(Code::OpenDoor, 12);
+    let mut verifier = Verifier::new(KEY, 100);
+
+    // The signer lost its counter.
+    let mut signer = Signer::new(KEY, 0);
+    let refused = signer.sign(open_door);
+    assert_eq!(verifier.verify(&refused), Err(AuthError::Replayed(open_door)));
+
+    let Frame::Authenticated(notice) = verifier.notice(open_door) else {
+        unreachable!()
+    };
+    assert_eq!(notice.counter, 100);
+    assert!(signer.sync(&notice));
+    assert_eq!(verifier.verify(&signer.sign(open_door)), Ok(open_door));
+    assert_eq!(verifier.counter(), 101);
+
+    // Old notices are ignored.
+    assert!(!signer.sync(&notice));
+    assert_eq!(signer.counter(), 101);
+
+    // Notices must be genuine.
+    let mut forged = notice;
+    forged.counter = u32::MAX - 1;
+    assert!(!signer.sync(&forged));
+    assert_eq!(signer.counter(), 101);
+
+    // A notice is not a command, even if its counter is new.
+    let Frame::Authenticated(notice) =
+        Verifier::new(KEY, 1000).notice(open_door)
+    else {
+        unreachable!()
+    };
+    assert_eq!(
+        verifier.verify(&Frame::Authenticated(notice)),
+        Err(AuthError::BadTag)
+    );
+}
@@ -0,0 +1,71 @@
+use crate::census::{Census, Seen};
+use crate::{Code, Message};
+
+#[test]
+fn record() {
+    let mut census = Census::<4>::new();
+    let call = Message::new(Code::Call, 12);
+    assert_eq!(census.record(&call, 1_000), Seen::NewAddress);
+    assert_eq!(census.record(&call, 2_000), Seen::Known);
+    assert_eq!(
+        census.record(&Message::new(Code::OpenDoor, 12), 3_000),
+        Seen::NewCode
+    );
+    assert_eq!(
+        census.record(&Message::new(Code::Unknown(33), 3), 4_000),
+        Seen::NewAddress
+    );
+
+    // Devices are sorted by address.
+    let addresses: Vec<u8> =
+        census.devices().iter().map(|device| device.address()).collect();
+    assert_eq!(addresses, [3, 12]);
+
+    let device = census.device(12).unwrap();
+    assert_eq!(device.first_seen_ms(), 1_000);
+    assert_eq!(device.last_seen_ms(), 3_000);
+    assert_eq!(device.count(Code::Call), 2);
+    assert_eq!(device.count(Code::CameraOn), 0);
+    assert_eq!(device.total(), 3);
+    assert_eq!(
+        device.codes().collect::<Vec<_>>(),
+        [(Code::OpenDoor, 1), (Code::Call, 2)]
+    );
+    assert!(census.device(7).is_none());
+}
+
+#[test]
+fn full() {
+    let mut census = Census::<2>::new();
+    census.record(&Message::new(Code::Call, 20), 0);
+    census.record(&Message::new(Code::Call, 10), 0);
+    assert_eq!(census.record(&Message::new(Code::Call, 15), 0), Seen::Dropped);
+    // Known addresses are still recorded.
+    assert_eq!(census.record(&Message::new(Code::Call, 20), 0), Seen::Known);
+    assert_eq!(census.dropped(), 1);
+    assert_eq!(census.devices().len(), 2);
+
+    census.clear();
+    assert!(census.devices().is_empty());
+    assert_eq!(census.dropped(), 0);
+    assert_eq!(
+        census.record(&Message::new(Code::Call, 15), 0),
+        Seen::NewAddress
+    );
+}
+
+#[test]
+fn report() {
+    let mut census = Census::<4>::new();
+    census.record(&Message::new(Code::Call, 12), u32::MAX - 999);
+    census.record(&Message::new(Code::Unknown(33), 12), u32::MAX - 999);
+    census.record(&Message::new(Code::Call, 12), u32::MAX - 999);
+
+    // The time since the last message wraps around.
+    let report = census.device(12).unwrap().report(4_000);
+    assert_eq!(report.last_seen_ms_ago(), 5_000);
+    assert_eq!(
+        serde_json::to_string(&report).unwrap(),
+        r#"{"address":12,"last_seen_ms_ago":5000,"codes":[{"code":33,"count":1},{"code":"call","count":2}]}"#
+    );
+}
@@ -0,0 +1,63 @@
+use crate::{Code, Message};
+
+#[test]
+fn values() {
+    for value in 0..=u8::MAX {
+        assert_eq!(Code::from_u8(value).as_u8(), value);
+    }
+    assert_eq!(Code::from(48), Code::Call);
+    assert_eq!(u8::from(Code::OpenDoor), 16);
+    assert!(matches!(Code::from(22), Code::Unknown(22)));
+}
+
+#[test]
+fn unknown_equals_known() {
+    assert_eq!(Code::Unknown(16), Code::OpenDoor);
+    assert_eq!(Code::Unknown(16).name(), Some("open_door"));
+    assert_ne!(Code::Unknown(17), Code::OpenDoor);
+}
+
+#[test]
+fn descriptions() {
+    for code in Code::KNOWN {
+        assert!(code.description().is_some());
+    }
+    assert_eq!(Code::Unknown(20).description(), Code::CameraOn.description());
+    assert_eq!(Code::Unknown(33).description(), None);
+}
+
+#[test]
+fn display_and_parse() {
+    for code in Code::KNOWN {
+        let name = code.to_string();
+        assert_eq!(Some(name.as_str()), code.name());
+        assert_eq!(name.parse::<Code>(), Ok(code));
+    }
+    assert_eq!(Code::Unknown(33).to_string(), "33");
+    assert_eq!("33".parse::<Code>(), Ok(Code::Unknown(33)));
+    assert_eq!("16".parse::<Code>(), Ok(Code::OpenDoor));
+    assert!("foo".parse::<Code>().is_err());
+    assert!("256".parse::<Code>().is_err());
+}
+
+#[test]
+fn serde() {
+    assert_eq!(
+        serde_json::to_string(&Code::OpenDoor).unwrap(),
+        r#""open_door""#
+    );
+    assert_eq!(serde_json::to_string(&Code::Unknown(33)).unwrap(), "33");
+    assert_eq!(
+        serde_json::from_str::<Code>(r#""call_end""#).unwrap(),
+        Code::CallEnd
+    );
+    assert_eq!(serde_json::from_str::<Code>("50").unwrap(), Code::CallEnd);
+    assert_eq!(serde_json::from_str::<Code>("33").unwrap(), Code::Unknown(33));
+    assert!(serde_json::from_str::<Code>(r#""foo""#).is_err());
+    assert!(serde_json::from_str::<Code>("300").is_err());
+
+    let msg = Message::new(Code::Call, 12);
+    let json = serde_json::to_string(&msg).unwrap();
+    assert_eq!(json, r#"{"code":"call","address":12}"#);
+    assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), msg);
+}
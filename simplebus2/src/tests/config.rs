@@ -0,0 +1,177 @@
+use crate::config::{
+    Command, Filter, Settings, Value, CALIBRATION_BYTE, CALIBRATION_LEN,
+};
+use crate::pulse::Timing;
+use crate::{Code, Frame, FrameDecoder, Message};
+
+#[test]
+fn messages() {
+    let commands = [
+        Command::AllowAddress(12),
+        Command::BlockAddress(255),
+        Command::AllowCode(Code::Call),
+        Command::BlockCode(Code::Unknown(33)),
+        Command::AllowAllAddresses,
+        Command::BlockAllAddresses,
+        Command::AllowAllCodes,
+        Command::BlockAllCodes,
+        Command::Get(Value::Osccal),
+        Command::Get(Value::UartRxOverflows),
+        Command::Set(Value::Tolerance, 8),
+        Command::Set(Value::UartRxOverflows, 0),
+    ];
+    for command in commands {
+        let message = command.to_message();
+        assert!(Command::CODES.contains(&message.code.as_u8()));
+        assert_eq!(Command::from_message(&message), Some(command));
+        // Configuration commands travel in frames like any other message.
+        let bits = message.to_bits();
+        assert_eq!(Message::from_bits(bits), Some(message));
+    }
+    assert_eq!(Command::from_message(&Message::new(Code::OpenDoor, 12)), None);
+    assert_eq!(Command::from_message(&Message::new(Code::from(34), 64)), None);
+    assert_eq!(Command::from_message(&Message::new(Code::from(36), 4)), None);
+    // There are 9 values.
+    assert_eq!(Command::from_message(&Message::new(Code::from(37), 9)), None);
+    for value in Value::ALL {
+        let set = Command::Set(value, 1).to_message();
+        assert!(Command::CODES.contains(&set.code.as_u8()));
+    }
+}
+
+#[test]
+fn parse() {
+    assert_eq!("allow_address 12".parse(), Ok(Command::AllowAddress(12)));
+    assert_eq!("block_code call".parse(), Ok(Command::BlockCode(Code::Call)));
+    assert_eq!(
+        "allow_code 33".parse(),
+        Ok(Command::AllowCode(Code::Unknown(33)))
+    );
+    assert_eq!("block_all_codes".parse(), Ok(Command::BlockAllCodes));
+    assert!("allow_address".parse::<Command>().is_err());
+    assert!("allow_address 256".parse::<Command>().is_err());
+    assert!("allow_code 64".parse::<Command>().is_err());
+    assert_eq!("get ack_timeout".parse(), Ok(Command::Get(Value::AckTimeout)));
+    assert_eq!(
+        "set carrier_period 42".parse(),
+        Ok(Command::Set(Value::CarrierPeriod, 42))
+    );
+    assert!("get foo".parse::<Command>().is_err());
+    assert!("get osccal 1".parse::<Command>().is_err());
+    assert!("set osccal".parse::<Command>().is_err());
+    assert!("set osccal 256".parse::<Command>().is_err());
+    assert!("allow_all_codes 1".parse::<Command>().is_err());
+    assert!("block_address 1 2".parse::<Command>().is_err());
+    assert!("".parse::<Command>().is_err());
+}
+
+#[test]
+fn filter() {
+    let call = Message::new(Code::Call, 12);
+    let neighbour = Message::new(Code::Call, 13);
+    let open = Message::new(Code::OpenDoor, 12);
+
+    let mut filter = Filter::default();
+    assert_eq!(filter, Filter::from_bytes([0xFF; Filter::LEN]));
+    assert!(filter.allows(&call) && filter.allows(&neighbour));
+
+    filter.apply(Command::BlockAllAddresses);
+    filter.apply(Command::AllowAddress(12));
+    assert!(filter.allows(&call) && filter.allows(&open));
+    assert!(!filter.allows(&neighbour));
+
+    filter.apply(Command::BlockCode(Code::OpenDoor));
+    assert!(filter.allows(&call) && !filter.allows(&open));
+
+    filter.apply(Command::BlockAllCodes);
+    assert!(!filter.allows(&call));
+    filter.apply(Command::AllowCode(Code::Call));
+    filter.apply(Command::BlockAddress(12));
+    assert!(!filter.allows(&call));
+
+    // The bitmap survives a round trip through the EEPROM.
+    let copy = Filter::from_bytes(*filter.as_bytes());
+    assert_eq!(copy, filter);
+    filter.apply(Command::AllowAllAddresses);
+    filter.apply(Command::AllowAllCodes);
+    assert_eq!(filter, Filter::ALLOW_ALL);
+}
+
+#[test]
+fn settings() {
+    let mut settings = Settings::new(105);
+    assert_eq!(settings.timing(), Timing::DEFAULT);
+    assert_eq!(settings.get(Value::Osccal), Some(105));
+    assert_eq!(settings.get(Value::CarrierPeriod), Some(40));
+    assert_eq!(settings.get(Value::BusRxOverflows), None);
+
+    assert!(settings.apply(Command::Set(Value::Tolerance, 8)));
+    assert!(settings.apply(Command::Set(Value::AckTimeout, 30)));
+    assert!(settings.apply(Command::BlockAllAddresses));
+    assert_eq!(settings.timing().tolerance_us, 800);
+    assert_eq!(settings.timing().ack_timeout_us, 30_000);
+    assert!(!settings.filter.allows(&Message::new(Code::Call, 12)));
+
+    // Values out of range, counters and reads don't change the settings.
+    let before = settings;
+    assert!(!settings.apply(Command::Set(Value::Tolerance, 15)));
+    assert!(!settings.apply(Command::Set(Value::AckTimeout, 0)));
+    assert!(!settings.apply(Command::Set(Value::CarrierPeriod, 25)));
+    assert!(!settings.apply(Command::Set(Value::BusTxOverflows, 0)));
+    assert!(!settings.apply(Command::Get(Value::Osccal)));
+    assert_eq!(settings, before);
+}
+
+#[test]
+fn settings_block() {
+    let mut settings = Settings::new(105);
+    settings.apply(Command::Set(Value::CarrierPeriod, 42));
+    settings.apply(Command::BlockCode(Code::Call));
+    let bytes = settings.to_bytes();
+    assert_eq!(bytes[0], Settings::VERSION);
+    assert_eq!(Settings::from_bytes(&bytes), Some(settings));
+
+    // An erased EEPROM, a damaged block and a different version are
+    // refused.
+    assert_eq!(Settings::from_bytes(&[0xFF; Settings::LEN]), None);
+    let mut damaged = bytes;
+    damaged[10] ^= 1;
+    assert_eq!(Settings::from_bytes(&damaged), None);
+    let mut other = bytes;
+    other[0] = Settings::VERSION + 1;
+    assert_eq!(Settings::from_bytes(&other), None);
+}
+
+#[test]
+fn settings_block_out_of_range() {
+    // Blocks with a valid checksum but a setting out of range, which
+    // `Settings::apply` refuses, are refused too.
+    let valid = Settings::new(105);
+    for settings in [
+        Settings { tolerance: 0, ..valid },
+        Settings { tolerance: 15, ..valid },
+        Settings { ack_timeout: 0, ..valid },
+        Settings { carrier_period: 0, ..valid },
+        Settings { carrier_period: 51, ..valid },
+    ] {
+        assert_eq!(Settings::from_bytes(&settings.to_bytes()), None);
+    }
+    assert_eq!(Settings::from_bytes(&valid.to_bytes()), Some(valid));
+}
+
+#[test]
+fn calibration_stream() {
+    // The stream doesn't contain packets, and it doesn't prevent the decoder
+    // from finding the next one.
+    let mut decoder = FrameDecoder::new();
+    for _ in 0..CALIBRATION_LEN {
+        assert_eq!(decoder.push(CALIBRATION_BYTE), None);
+    }
+    let frame = Frame::Message(Message::new(Code::OpenDoor, 12));
+    let packets: Vec<_> = frame
+        .to_raw_bytes()
+        .iter()
+        .filter_map(|&byte| decoder.push(byte))
+        .collect();
+    assert_eq!(packets.len(), 1);
+}
@@ -0,0 +1,214 @@
+use crate::auth::{Key, Signer};
+use crate::{Code, Frame, FrameDecoder, Message, Packet};
+
+#[test]
+fn kinds() {
+    let msg = Message::new(Code::Call, 12);
+    let bytes = Frame::Message(msg).to_raw_bytes();
+    assert_eq!(*bytes, msg.to_raw_bytes());
+    assert_eq!(Frame::from_raw_bytes(&bytes), Some(Frame::Message(msg)));
+
+    let bytes = Frame::Ack(msg).to_raw_bytes();
+    assert_eq!(bytes.len(), Frame::ACK_LEN);
+    assert_eq!(bytes[0] & 0b11_1111, 1);
+    assert_eq!(bytes[1..3], msg.to_raw_bytes()[1..]);
+    assert_eq!(bytes[3], 0);
+    assert_eq!(Frame::from_raw_bytes(&bytes), Some(Frame::Ack(msg)));
+    assert_eq!(Frame::from_raw_bytes(&bytes).unwrap().message(), &msg);
+
+    // An acknowledgement is not a message.
+    assert_eq!(Message::from_raw_bytes(&bytes[..3].try_into().unwrap()), None);
+
+    // A failed transmission differs from an acknowledgement in the last
+    // byte only.
+    let failed = Frame::NoAck(msg).to_raw_bytes();
+    assert_eq!(failed[..3], bytes[..3]);
+    assert_eq!(failed[3], 1);
+    assert_eq!(Frame::from_raw_bytes(&failed), Some(Frame::NoAck(msg)));
+
+    // The length and the last byte must be valid.
+    assert_eq!(Frame::from_raw_bytes(&bytes[..3]), None);
+    let mut invalid = bytes.to_vec();
+    invalid[3] = 2;
+    assert_eq!(Frame::from_raw_bytes(&invalid), None);
+}
+
+#[test]
+fn authenticated() {
+    let msg = Message::new(Code::OpenDoor, 12);
+    let mut signer = Signer::new(Key::new([7; 16]), 0x1234_5678);
+    let frame = signer.sign(msg);
+    let bytes = frame.to_raw_bytes();
+    assert_eq!(bytes.len(), Frame::MAX_LEN);
+    assert_eq!(bytes[0] & 0b11_1111, 2);
+    assert_eq!(bytes[1..3], msg.to_raw_bytes()[1..]);
+    assert_eq!(bytes[3..7], [0x79, 0x56, 0x34, 0x12]);
+    assert_eq!(Frame::from_raw_bytes(&bytes), Some(frame));
+    assert_eq!(frame.message(), &msg);
+
+    // The length must match the frame's kind.
+    assert_eq!(Frame::from_raw_bytes(&bytes[..Frame::LEN]), None);
+    assert_eq!(Frame::from_raw_bytes(&bytes[..Frame::MAX_LEN - 1]), None);
+    let mut longer = bytes.to_vec();
+    longer.push(0);
+    assert_eq!(Frame::from_raw_bytes(&longer), None);
+    assert_eq!(Frame::from_raw_bytes(&msg.to_raw_bytes()[..2]), None);
+}
+
+#[test]
+fn unknown_kind() {
+    let bytes = Message::new(Code::Call, 12).to_raw_bytes();
+    // Sequence number in a plain packet.
+    for seq in 1..=Packet::MAX_SEQ {
+        let mut bytes = bytes;
+        bytes[0] |= seq << 2;
+        assert_eq!(Packet::from_raw_bytes(&bytes), None);
+    }
+    // Link acknowledgement without sequence number.
+    let mut bytes = bytes;
+    bytes[0] |= 0b11;
+    assert_eq!(Packet::from_raw_bytes(&bytes), None);
+}
+
+#[test]
+fn sequenced() {
+    let msg = Message::new(Code::Call, 12);
+    let frame = Frame::Ack(msg);
+    assert_eq!(*Packet::Plain(frame).to_raw_bytes(), *frame.to_raw_bytes());
+
+    for seq in 0..=Packet::MAX_SEQ {
+        let packet = Packet::Sequenced { seq, frame };
+        let bytes = packet.to_raw_bytes();
+        assert_eq!(bytes[0] & 0b11_1111, 0b10_0001 | seq << 2);
+        assert_eq!(bytes[1..], frame.to_raw_bytes()[1..]);
+        assert_eq!(Packet::from_raw_bytes(&bytes), Some(packet));
+        // A sequenced packet is not a plain frame.
+        assert_eq!(Frame::from_raw_bytes(&bytes), None);
+
+        let packet = Packet::LinkAck { seq, message: msg };
+        let bytes = packet.to_raw_bytes();
+        assert_eq!(bytes[0] & 0b11_1111, 0b10_0011 | seq << 2);
+        assert_eq!(Packet::from_raw_bytes(&bytes), Some(packet));
+    }
+
+    let frame = Signer::new(Key::new([7; 16]), 0).sign(msg);
+    let packet = Packet::Sequenced { seq: 5, frame };
+    let bytes = packet.to_raw_bytes();
+    assert_eq!(bytes.len(), Frame::MAX_LEN);
+    assert_eq!(bytes[3..], frame.to_raw_bytes()[3..]);
+    assert_eq!(Packet::from_raw_bytes(&bytes), Some(packet));
+}
+
+/// A sequence of packets like the ones seen during a call, followed by the
+/// door being opened with both plain and authenticated commands, the first
+/// one failing, over plain and sequenced packets.
+fn packets() -> Vec<Packet> {
+    let call = Message::new(Code::Call, 12);
+    let call_end = Message::new(Code::CallEnd, 12);
+    let open_door = Message::new(Code::OpenDoor, 12);
+    let mut signer = Signer::new(Key::new([7; 16]), 0);
+    let frames = vec![
+        Frame::Message(call),
+        Frame::Ack(call),
+        Frame::Message(call),
+        Frame::Ack(call),
+        Frame::Message(call_end),
+        Frame::Ack(call_end),
+        Frame::Message(open_door),
+        Frame::NoAck(open_door),
+        Frame::Message(open_door),
+        Frame::Ack(open_door),
+        signer.sign(open_door),
+        Frame::Ack(open_door),
+        signer.sign(open_door),
+        Frame::Ack(open_door),
+    ];
+    let mut packets: Vec<Packet> =
+        frames.iter().copied().map(Packet::Plain).collect();
+    for (seq, frame) in frames.into_iter().enumerate() {
+        let seq = seq as u8 & Packet::MAX_SEQ;
+        let message = *frame.message();
+        packets.push(Packet::Sequenced { seq, frame });
+        packets.push(Packet::LinkAck { seq, message });
+    }
+    packets
+}
+
+fn decode(decoder: &mut FrameDecoder, bytes: &[u8]) -> Vec<Packet> {
+    bytes.iter().filter_map(|b| decoder.push(*b)).collect()
+}
+
+#[test]
+fn decoder() {
+    let packets = packets();
+    let bytes: Vec<u8> =
+        packets.iter().flat_map(|p| p.to_raw_bytes().to_vec()).collect();
+    let mut decoder = FrameDecoder::new();
+    assert_eq!(decode(&mut decoder, &bytes), packets);
+    assert_eq!(decoder.pending(), 0);
+}
+
+/// Damages one of the packets by dropping one of its bytes, duplicating it,
+/// or inserting a garbage byte before it.
+fn damaged(packet: &Packet, n: usize) -> Vec<u8> {
+    let mut bytes = packet.to_raw_bytes().to_vec();
+    let pos = n / 3 % bytes.len();
+    match n % 3 {
+        0 => {
+            bytes.remove(pos);
+        }
+        1 => bytes.insert(pos, bytes[pos]),
+        _ => bytes.insert(pos, 0xff),
+    }
+    bytes
+}
+
+/// When the receiver resets the decoder after the gap between frames, the
+/// frames after the damaged one are always decoded correctly.
+#[test]
+fn recover_with_gaps() {
+    let packets = packets();
+    for damaged_idx in 0..packets.len() {
+        for n in 0..3 * Frame::MAX_LEN {
+            let mut decoder = FrameDecoder::new();
+            for (i, packet) in packets.iter().enumerate() {
+                if i == damaged_idx {
+                    decode(&mut decoder, &damaged(packet, n));
+                } else {
+                    let bytes = packet.to_raw_bytes();
+                    assert_eq!(
+                        decode(&mut decoder, &bytes),
+                        [*packet],
+                        "frame {damaged_idx}, n = {n}"
+                    );
+                }
+                decoder.reset();
+            }
+        }
+    }
+}
+
+/// Without gaps, the decoder resynchronizes in a continuous stream of bytes
+/// and all the frames after the next one are decoded correctly.
+#[test]
+fn recover_without_gaps() {
+    let packets = packets();
+    for damaged_idx in 0..packets.len() - 2 {
+        for n in 0..3 * Frame::MAX_LEN {
+            let mut bytes = Vec::new();
+            for (i, packet) in packets.iter().enumerate() {
+                if i == damaged_idx {
+                    bytes.extend(damaged(packet, n));
+                } else {
+                    bytes.extend_from_slice(&packet.to_raw_bytes());
+                }
+            }
+            let mut decoder = FrameDecoder::new();
+            let decoded = decode(&mut decoder, &bytes);
+            assert!(
+                decoded.ends_with(&packets[damaged_idx + 2..]),
+                "frame {damaged_idx}, n = {n}: {decoded:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::VecDeque;
+
+use crate::link::{Link, LinkConfig, Output};
+use crate::{Code, Frame, Message, Packet};
+
+/// One direction of a radio link that delays packets and loses some of them
+/// at random. The random generator has a fixed seed, so the tests are
+/// deterministic.
+struct LossyChannel {
+    rng: u32,
+    loss_percent: u32,
+    latency_ms: u32,
+    in_transit: VecDeque<(u32, Packet)>,
+    transmitted: usize,
+}
+
+impl LossyChannel {
+    fn new(seed: u32, loss_percent: u32) -> Self {
+        Self {
+            rng: seed,
+            loss_percent,
+            latency_ms: 40,
+            in_transit: VecDeque::new(),
+            transmitted: 0,
+        }
+    }
+
+    fn transmit(&mut self, packet: Packet, now_ms: u32) {
+        self.transmitted += 1;
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        if self.rng % 100 >= self.loss_percent {
+            self.in_transit.push_back((now_ms + self.latency_ms, packet));
+        }
+    }
+
+    fn receive(&mut self, now_ms: u32) -> Option<Packet> {
+        match self.in_transit.front() {
+            Some((at_ms, _)) if *at_ms <= now_ms => {
+                self.in_transit.pop_front().map(|(_, packet)| packet)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Frames received and failed at one end of the link.
+#[derive(Default)]
+struct Events {
+    received: Vec<Frame>,
+    failed: Vec<Frame>,
+}
+
+fn poll<const N: usize>(
+    link: &mut Link<N>,
+    channel: &mut LossyChannel,
+    events: &mut Events,
+    t: u32,
+    now_ms: u32,
+) {
+    while let Some(output) = link.poll(now_ms) {
+        match output {
+            Output::Transmit(packet) => channel.transmit(packet, t),
+            Output::Failed(frame) => events.failed.push(frame),
+        }
+    }
+}
+
+fn queue<const N: usize>(link: &mut Link<N>, frames: &mut VecDeque<Frame>) {
+    while let Some(frame) = frames.front() {
+        if link.send(*frame).is_err() {
+            break;
+        }
+        frames.pop_front();
+    }
+}
+
+/// Runs a simulation where `a` sends `frames` to `b`, and `b` sends them back
+/// to `a`. Returns both ends of the link and the events at each of them.
+fn simulate(
+    config: LinkConfig,
+    ab: &mut LossyChannel,
+    ba: &mut LossyChannel,
+    frames: &[Frame],
+) -> (Link<4>, Events, Link<4>, Events) {
+    let mut a = Link::<4>::new(config);
+    let mut b = Link::<4>::new(config);
+    let mut a_events = Events::default();
+    let mut b_events = Events::default();
+    let mut pending: VecDeque<Frame> = frames.iter().copied().collect();
+    let mut echo = VecDeque::new();
+
+    // The links' clock starts close to the wrap around, `t` is the channels'
+    // clock.
+    let start_ms = u32::MAX - 10_000;
+    for t in (0..1_000_000).step_by(10) {
+        let now_ms = start_ms.wrapping_add(t);
+        // Queue frames while there's room for them.
+        queue(&mut a, &mut pending);
+        queue(&mut b, &mut echo);
+        while let Some(packet) = ab.receive(t) {
+            if let Some(frame) = b.receive(packet, now_ms) {
+                b_events.received.push(frame);
+                echo.push_back(frame);
+            }
+        }
+        while let Some(packet) = ba.receive(t) {
+            if let Some(frame) = a.receive(packet, now_ms) {
+                a_events.received.push(frame);
+            }
+        }
+        poll(&mut a, ab, &mut a_events, t, now_ms);
+        poll(&mut b, ba, &mut b_events, t, now_ms);
+        if pending.is_empty()
+            && echo.is_empty()
+            && a.is_idle()
+            && b.is_idle()
+            && ab.in_transit.is_empty()
+            && ba.in_transit.is_empty()
+        {
+            break;
+        }
+    }
+    (a, a_events, b, b_events)
+}
+
+fn frames(n: usize) -> Vec<Frame> {
+    (1..=n)
+        .map(|i| {
+            let msg = Message::new(Code::from_u8(i as u8 % 64), i as u8);
+            if i % 2 == 0 {
+                Frame::Message(msg)
+            } else {
+                Frame::Ack(msg)
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn lossless() {
+    let frames = frames(50);
+    let mut ab = LossyChannel::new(1, 0);
+    let mut ba = LossyChannel::new(2, 0);
+    let (a, a_events, b, b_events) =
+        simulate(LinkConfig::DEFAULT, &mut ab, &mut ba, &frames);
+    assert_eq!(b_events.received, frames);
+    assert_eq!(a_events.received, frames);
+    assert!(a_events.failed.is_empty() && b_events.failed.is_empty());
+    // Every frame is transmitted once, and acknowledged once.
+    assert_eq!(ab.transmitted, 2 * frames.len());
+    assert_eq!(ba.transmitted, 2 * frames.len());
+    assert_eq!(a.stats().delivered, 50);
+    assert_eq!(b.stats().delivered, 50);
+    assert_eq!(a.stats().retransmitted, 0);
+    assert_eq!(b.stats().duplicates, 0);
+}
+
+/// Lost frames are retransmitted, and frames whose acknowledgement was lost
+/// are delivered only once.
+#[test]
+fn lossy() {
+    let frames = frames(200);
+    let config = LinkConfig { retries: 10, ..LinkConfig::DEFAULT };
+    for seed in 1..10 {
+        let mut ab = LossyChannel::new(seed, 20);
+        let mut ba = LossyChannel::new(seed * 7919, 20);
+        let (a, a_events, b, b_events) =
+            simulate(config, &mut ab, &mut ba, &frames);
+        assert_eq!(b_events.received, frames, "seed {seed}");
+        assert_eq!(a_events.received, frames, "seed {seed}");
+        assert!(a_events.failed.is_empty() && b_events.failed.is_empty());
+        assert!(a.stats().retransmitted > 0);
+        assert!(b.stats().duplicates > 0);
+    }
+}
+
+/// With the default number of retries a few frames are lost in a very bad
+/// channel, but they are reported, and the link recovers.
+#[test]
+fn failures() {
+    let frames = frames(200);
+    let mut ab = LossyChannel::new(3, 50);
+    let mut ba = LossyChannel::new(4, 50);
+    let (a, a_events, _, b_events) =
+        simulate(LinkConfig::DEFAULT, &mut ab, &mut ba, &frames);
+    assert!(!a_events.failed.is_empty());
+    assert_eq!(a.stats().failed as usize, a_events.failed.len());
+    assert_eq!(a.stats().delivered as usize + a_events.failed.len(), 200);
+    // Frames are received in order, and each one at most once. A failed
+    // frame may have been received if only its acknowledgements were lost.
+    let mut expected = frames.iter();
+    for frame in &b_events.received {
+        assert!(expected.any(|f| f == frame));
+    }
+    assert!(b_events.received.len() >= 200 - a_events.failed.len());
+}
+
+#[test]
+fn link_down() {
+    let frames = frames(3);
+    let mut ab = LossyChannel::new(5, 100);
+    let mut ba = LossyChannel::new(6, 100);
+    let (_, a_events, _, b_events) =
+        simulate(LinkConfig::DEFAULT, &mut ab, &mut ba, &frames);
+    assert_eq!(a_events.failed, frames);
+    assert!(b_events.received.is_empty());
+    let attempts = LinkConfig::DEFAULT.retries as usize + 1;
+    assert_eq!(ab.transmitted, frames.len() * attempts);
+}
+
+/// Without sequence numbers, frames are sent once and lost frames are not
+/// retransmitted.
+#[test]
+fn plain() {
+    let frames = frames(50);
+    let config = LinkConfig { sequenced: false, ..LinkConfig::DEFAULT };
+    let mut ab = LossyChannel::new(7, 30);
+    let mut ba = LossyChannel::new(8, 0);
+    let (_, _, _, b_events) = simulate(config, &mut ab, &mut ba, &frames);
+    assert_eq!(ab.transmitted, frames.len());
+    assert_eq!(ba.transmitted, b_events.received.len());
+    assert!(b_events.received.len() < frames.len());
+}
+
+#[test]
+fn acknowledgements() {
+    let msg = Message::new(Code::Call, 12);
+    let mut link = Link::<2>::new(LinkConfig::DEFAULT);
+
+    // Plain packets are delivered and not acknowledged.
+    assert_eq!(
+        link.receive(Packet::Plain(Frame::Ack(msg)), 0),
+        Some(Frame::Ack(msg))
+    );
+    assert_eq!(link.poll(0), None);
+
+    // Sequenced packets are acknowledged, even if duplicated.
+    let packet = Packet::Sequenced { seq: 3, frame: Frame::Message(msg) };
+    let ack = Packet::LinkAck { seq: 3, message: msg };
+    assert_eq!(link.receive(packet, 0), Some(Frame::Message(msg)));
+    assert_eq!(link.poll(0), Some(Output::Transmit(ack)));
+    assert_eq!(link.poll(0), None);
+    assert_eq!(link.receive(packet, 100), None);
+    assert_eq!(link.poll(100), Some(Output::Transmit(ack)));
+    assert_eq!(link.stats().duplicates, 1);
+
+    // Outside the duplicates window, a packet with the same sequence number
+    // is a new frame, the sender was probably restarted.
+    let window = LinkConfig::DEFAULT.duplicate_window_ms();
+    assert_eq!(link.receive(packet, 100 + window), Some(Frame::Message(msg)));
+    assert_eq!(link.poll(100 + window), Some(Output::Transmit(ack)));
+
+    // Acknowledgements must match the frame sent.
+    link.send(Frame::Message(msg)).unwrap();
+    let Some(Output::Transmit(Packet::Sequenced { seq, .. })) = link.poll(0)
+    else {
+        panic!()
+    };
+    link.poll(0);
+    let other = Message::new(Code::CallEnd, 12);
+    link.receive(Packet::LinkAck { seq: seq + 1, message: msg }, 10);
+    link.receive(Packet::LinkAck { seq, message: other }, 10);
+    assert!(!link.is_idle());
+    assert_eq!(link.next_timeout_ms(10), Some(490));
+    link.receive(Packet::LinkAck { seq, message: msg }, 10);
+    assert!(link.is_idle());
+    assert_eq!(link.next_timeout_ms(10), None);
+}
+
+#[test]
+fn overflow() {
+    let msg = Message::new(Code::Call, 12);
+    let mut link = Link::<2>::new(LinkConfig::DEFAULT);
+    assert_eq!(link.room(), 2);
+    link.send(Frame::Message(msg)).unwrap();
+    link.send(Frame::Ack(msg)).unwrap();
+    assert_eq!(link.room(), 0);
+    assert_eq!(link.send(Frame::Ack(msg)), Err(Frame::Ack(msg)));
+    assert_eq!(link.stats().overflows, 1);
+    link.stats_mut().overflows = 0;
+    assert_eq!(link.stats().overflows, 0);
+
+    // The frames are sent one at a time, in order.
+    let Some(Output::Transmit(Packet::Sequenced { seq, frame })) =
+        link.poll(0)
+    else {
+        panic!()
+    };
+    assert_eq!(frame, Frame::Message(msg));
+    assert_eq!(link.poll(0), None);
+    assert_eq!(link.room(), 1);
+    link.send(Frame::Ack(msg)).unwrap();
+    link.receive(Packet::LinkAck { seq, message: msg }, 10);
+    assert_eq!(
+        link.poll(10),
+        Some(Output::Transmit(Packet::Sequenced {
+            seq: seq + 1,
+            frame: Frame::Ack(msg)
+        }))
+    );
+}
@@ -0,0 +1,92 @@
+// Bit literals are grouped by field: checksum, address and code.
+#![allow(clippy::unusual_byte_groupings)]
+
+use crate::{Code, Message};
+
+#[test]
+fn checksum() {
+    // Example from the oscilloscope capture in the README.
+    let msg = Message::new(Code::Call, 12);
+    assert_eq!(msg.checksum(), 4);
+}
+
+#[test]
+fn bits() {
+    let msg = Message::new(Code::Call, 12);
+    assert_eq!(msg.to_bits(), 0b0100_00001100_110000);
+    assert_eq!(Message::from_bits(msg.to_bits()), Some(msg));
+}
+
+#[test]
+fn raw_bytes() {
+    let msg = Message::new(Code::CallFloorDoor, 12);
+    let bytes = msg.to_raw_bytes();
+    assert_eq!(bytes, [0b0100_0000, 0b1100_0101, 0b0101_0000]);
+    assert_eq!(Message::from_raw_bytes(&bytes), Some(msg));
+}
+
+#[test]
+fn invalid_checksum() {
+    assert_eq!(Message::validate(48, 12, 3), None);
+    assert_eq!(Message::from_bits(0b0011_00001100_110000), None);
+    assert_eq!(Message::from_raw_bytes(&[0b0100_0000, 0b1100_0101, 0]), None);
+}
+
+#[test]
+fn all_zeros() {
+    assert_eq!(Message::from_bits(0), None);
+    assert_eq!(Message::from_raw_bytes(&[0, 0, 0]), None);
+}
+
+#[test]
+fn raw_bytes_padding() {
+    let mut bytes = Message::new(Code::Call, 12).to_raw_bytes();
+    bytes[0] |= 0b0000_0001;
+    assert_eq!(Message::from_raw_bytes(&bytes), None);
+}
+
+/// Packing and unpacking every possible (code, address) pair must give back
+/// the original message, both over the bus and over UART.
+#[test]
+fn round_trip_all() {
+    for code in 0..=0b11_1111 {
+        for address in 0..=u8::MAX {
+            let msg = Message::new(Code::from_u8(code), address);
+            // The all-zeros message is rejected on purpose.
+            let expected =
+                if code == 0 && address == 0 { None } else { Some(msg) };
+            let bits = msg.to_bits();
+            let bytes = msg.to_raw_bytes();
+
+            assert_eq!(Message::from_bits(bits), expected);
+            assert_eq!(Message::from_raw_bytes(&bytes), expected);
+
+            // The UART frame is the bus word shifted 6 bits to the left, so
+            // both representations must agree bit by bit.
+            assert_eq!(
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]),
+                bits << 6
+            );
+            assert_eq!((bits & 0b11_1111) as u8, code);
+            assert_eq!((bits >> 6) as u8, address);
+        }
+    }
+}
+
+/// Frames with a wrong checksum are rejected, no matter which bits differ.
+#[test]
+fn wrong_checksum_all() {
+    for code in 0..=0b11_1111 {
+        for address in 0..=u8::MAX {
+            let msg = Message::new(Code::from_u8(code), address);
+            for checksum in 0..16 {
+                if checksum != msg.checksum() {
+                    assert_eq!(
+                        Message::validate(code, address, checksum),
+                        None
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,12 @@
+mod auth;
+mod census;
+mod code;
+mod config;
+mod frame;
+mod link;
+mod message;
+mod pulse;
+mod queue;
+mod session;
+#[cfg(feature = "std")]
+mod traffic;
@@ -0,0 +1,262 @@
+use crate::pulse::{
+    encode, encode_ack, Decoder, Event, Pulse, Pulses, Symbol, Timing,
+};
+use crate::{Code, Message};
+
+/// Interval between edges within a burst, as seen at the comparator's output
+/// when the 25kHz signal toggles it.
+const EDGE_INTERVAL_US: u32 = 20;
+
+/// Converts a sequence of pulses into the timestamps of the edges seen at the
+/// comparator's output. `jitter` is called for every silence and returns
+/// a value that is added to its duration.
+fn to_edges(
+    pulses: impl Iterator<Item = Pulse>,
+    start_us: u32,
+    mut jitter: impl FnMut() -> i32,
+) -> Vec<u32> {
+    let mut edges = Vec::new();
+    let mut t = start_us;
+    for pulse in pulses {
+        for i in 0..=pulse.burst_us / EDGE_INTERVAL_US {
+            edges.push(t.wrapping_add(i * EDGE_INTERVAL_US));
+        }
+        let gap_us = pulse.gap_us.saturating_add_signed(jitter());
+        t = t.wrapping_add(pulse.burst_us).wrapping_add(gap_us);
+    }
+    edges
+}
+
+fn decode(decoder: &mut Decoder, edges: &[u32]) -> Vec<Event> {
+    edges.iter().filter_map(|t| decoder.edge(*t)).collect()
+}
+
+#[test]
+fn classify() {
+    let timing = Timing::DEFAULT;
+    assert_eq!(timing.classify(3_000), Some(Symbol::Zero));
+    assert_eq!(timing.classify(2_000), Some(Symbol::Zero));
+    assert_eq!(timing.classify(6_900), Some(Symbol::One));
+    assert_eq!(timing.classify(16_500), Some(Symbol::Preamble));
+    assert_eq!(timing.classify(4_500), None);
+    assert_eq!(timing.classify(30_000), None);
+    assert!(timing.is_burst(EDGE_INTERVAL_US));
+    assert!(!timing.is_burst(3_000));
+}
+
+#[test]
+fn encode_message() {
+    // Example from the oscilloscope capture in the README.
+    let msg = Message::new(Code::Call, 12);
+    let pulses: Vec<Pulse> = encode(&msg, &Timing::DEFAULT).collect();
+    let gaps: Vec<u32> = pulses.iter().map(|p| p.gap_us / 1000).collect();
+
+    assert_eq!(pulses.len(), Pulses::LEN as usize);
+    assert!(pulses.iter().all(|p| p.burst_us == 3_000));
+    #[rustfmt::skip]
+    assert_eq!(
+        gaps,
+        [17, 3, 3, 3, 3, 6, 6, 3, 3, 6, 6, 3, 3, 3, 3, 3, 3, 6, 3, 0]
+    );
+}
+
+#[test]
+fn round_trip() {
+    for code in 0..=0b11_1111 {
+        for address in [0, 1, 12, 0b1010_1010, 0b0101_0101, 0xff] {
+            let msg = Message::new(Code::from_u8(code), address);
+            let edges = to_edges(encode(&msg, &Timing::DEFAULT), 1_000, || 0);
+            let expected = match Message::from_bits(msg.to_bits()) {
+                Some(msg) => Event::Message(msg),
+                None => Event::BadChecksum(msg.to_bits()),
+            };
+            let mut decoder = Decoder::default();
+            assert_eq!(decode(&mut decoder, &edges), [expected]);
+        }
+    }
+}
+
+#[test]
+fn round_trip_with_jitter() {
+    let msg = Message::new(Code::CallEnd, 12);
+    let mut decoder = Decoder::default();
+    let mut jitter = [-900, 900, -500, 0, 700].into_iter().cycle();
+    // Start close to the end of the timestamp range, so that it wraps around
+    // in the middle of the message.
+    let edges =
+        to_edges(encode(&msg, &Timing::DEFAULT), u32::MAX - 50_000, || {
+            jitter.next().unwrap()
+        });
+    assert_eq!(decode(&mut decoder, &edges), [Event::Message(msg)]);
+}
+
+#[test]
+fn custom_tolerance() {
+    let msg = Message::new(Code::OpenDoor, 12);
+    let edges = to_edges(encode(&msg, &Timing::DEFAULT), 0, || 1_200);
+
+    // With the default tolerance the preamble is not recognized.
+    let mut decoder = Decoder::default();
+    assert_eq!(decode(&mut decoder, &edges), []);
+
+    let mut decoder =
+        Decoder::new(Timing { tolerance_us: 1_300, ..Timing::DEFAULT });
+    assert_eq!(decode(&mut decoder, &edges), [Event::Message(msg)]);
+}
+
+#[test]
+fn truncated_by_preamble() {
+    let msg = Message::new(Code::Call, 12);
+    let mut pulses: Vec<Pulse> = encode(&msg, &Timing::DEFAULT).collect();
+    // Interrupt the message after 5 bits and send it again.
+    pulses[5].gap_us = 17_000;
+    pulses.truncate(6);
+    pulses.extend(encode(&msg, &Timing::DEFAULT).skip(1));
+
+    let mut decoder = Decoder::default();
+    let edges = to_edges(pulses.into_iter(), 0, || 0);
+    assert_eq!(
+        decode(&mut decoder, &edges),
+        [Event::Truncated { bits: 0, count: 4 }, Event::Message(msg)]
+    );
+}
+
+#[test]
+fn bad_checksum() {
+    let mut decoder = Decoder::default();
+    let bits = Message::new(Code::Call, 12).to_bits() ^ (1 << 17);
+    let mut edges = Vec::new();
+    let mut t = 0;
+    for gap in [17_000].into_iter().chain((0..18).map(|i| {
+        if bits & (1 << i) != 0 {
+            6_000
+        } else {
+            3_000
+        }
+    })) {
+        edges.push(t);
+        t += gap;
+    }
+    edges.push(t);
+    assert_eq!(decode(&mut decoder, &edges), [Event::BadChecksum(bits)]);
+}
+
+#[test]
+fn gap() {
+    let mut decoder = Decoder::default();
+    let msg = Message::new(Code::Call, 12);
+    let events: Vec<Event> = encode(&msg, &Timing::DEFAULT)
+        .filter_map(|pulse| {
+            decoder.gap(EDGE_INTERVAL_US);
+            decoder.gap(pulse.gap_us)
+        })
+        .collect();
+    assert_eq!(events, [Event::Message(msg)]);
+}
+
+/// Returns the pulses for `msg` followed by an acknowledgement that starts
+/// `delay_us` after the end of the message.
+fn with_ack(msg: &Message, delay_us: u32) -> Vec<Pulse> {
+    let mut pulses: Vec<Pulse> = encode(msg, &Timing::DEFAULT).collect();
+    pulses.last_mut().unwrap().gap_us = delay_us;
+    pulses.extend(encode_ack(&Timing::DEFAULT));
+    pulses
+}
+
+#[test]
+fn ack() {
+    let msg = Message::new(Code::Call, 12);
+    for delay_us in [3_000, 5_000, 10_000, 20_000] {
+        let mut decoder = Decoder::default();
+        let edges = to_edges(with_ack(&msg, delay_us).into_iter(), 0, || 0);
+        assert_eq!(
+            decode(&mut decoder, &edges),
+            [Event::Message(msg), Event::Ack(msg)]
+        );
+        assert!(decoder.is_idle());
+    }
+}
+
+#[test]
+fn no_ack() {
+    let msg = Message::new(Code::OpenDoor, 12);
+
+    // Nothing after the message.
+    let mut decoder = Decoder::default();
+    let edges = to_edges(encode(&msg, &Timing::DEFAULT), 0, || 0);
+    assert_eq!(decode(&mut decoder, &edges), [Event::Message(msg)]);
+    assert!(!decoder.is_idle());
+    assert_eq!(decoder.timeout(), Some(Event::NoAck(msg)));
+    assert!(decoder.is_idle());
+    assert_eq!(decoder.timeout(), None);
+
+    // The acknowledgement arrives too late.
+    let mut decoder = Decoder::default();
+    let edges = to_edges(with_ack(&msg, 30_000).into_iter(), 0, || 0);
+    assert_eq!(
+        decode(&mut decoder, &edges),
+        [Event::Message(msg), Event::NoAck(msg)]
+    );
+
+    // Only two bursts.
+    let mut decoder = Decoder::default();
+    let mut pulses = with_ack(&msg, 5_000);
+    pulses.truncate(pulses.len() - 2);
+    let edges = to_edges(pulses.into_iter(), 0, || 0);
+    assert_eq!(decode(&mut decoder, &edges), [Event::Message(msg)]);
+    assert_eq!(decoder.timeout(), Some(Event::NoAck(msg)));
+
+    // Only three bursts, after a silence as long as the ones within the
+    // acknowledgement.
+    let mut decoder = Decoder::default();
+    let mut pulses = with_ack(&msg, 3_000);
+    pulses.truncate(pulses.len() - 1);
+    let edges = to_edges(pulses.into_iter(), 0, || 0);
+    assert_eq!(decode(&mut decoder, &edges), [Event::Message(msg)]);
+    assert_eq!(decoder.timeout(), Some(Event::NoAck(msg)));
+
+    // Another message starts right after.
+    let mut decoder = Decoder::default();
+    let mut pulses: Vec<Pulse> = encode(&msg, &Timing::DEFAULT).collect();
+    pulses.last_mut().unwrap().gap_us = 10_000;
+    pulses.extend(encode(&msg, &Timing::DEFAULT));
+    let edges = to_edges(pulses.into_iter(), 0, || 0);
+    assert_eq!(
+        decode(&mut decoder, &edges),
+        [Event::Message(msg), Event::NoAck(msg), Event::Message(msg)]
+    );
+}
+
+#[test]
+fn expect_ack() {
+    let msg = Message::new(Code::OpenDoor, 12);
+    let mut decoder = Decoder::default();
+    decoder.expect_ack(msg);
+    let edges = to_edges(encode_ack(&Timing::DEFAULT), 50_000, || 0);
+    assert_eq!(decode(&mut decoder, &edges), [Event::Ack(msg)]);
+
+    // The same, measuring the intervals between edges.
+    let mut decoder = Decoder::default();
+    decoder.expect_ack(msg);
+    let mut events = vec![decoder.gap(10_000)];
+    for pulse in encode_ack(&Timing::DEFAULT) {
+        events.push(decoder.gap(EDGE_INTERVAL_US));
+        events.push(decoder.gap(pulse.gap_us));
+    }
+    assert_eq!(
+        events.into_iter().flatten().collect::<Vec<_>>(),
+        [Event::Ack(msg)]
+    );
+}
+
+#[test]
+fn timeout_while_receiving() {
+    let msg = Message::new(Code::Call, 12);
+    let mut decoder = Decoder::default();
+    let edges = to_edges(encode(&msg, &Timing::DEFAULT).take(4), 0, || 0);
+    assert_eq!(decode(&mut decoder, &edges), []);
+    assert_eq!(
+        decoder.timeout(),
+        Some(Event::Truncated { bits: 0, count: 2 })
+    );
+}
@@ -0,0 +1,40 @@
+use crate::queue::Queue;
+
+#[test]
+fn fifo() {
+    let mut queue = Queue::<u8, 3>::new();
+    assert!(queue.is_empty());
+    assert_eq!(queue.pop(), None);
+    assert_eq!(queue.push(1), Ok(()));
+    assert_eq!(queue.push(2), Ok(()));
+    assert_eq!(queue.push(3), Ok(()));
+    assert!(queue.is_full());
+    assert_eq!(queue.push(4), Err(4));
+    assert_eq!(queue.peek(), Some(&1));
+    *queue.peek_mut().unwrap() = 0;
+    assert_eq!(queue.pop(), Some(0));
+    // The buffer wraps around.
+    assert_eq!(queue.push(4), Ok(()));
+    assert_eq!(queue.len(), 3);
+    assert_eq!(queue.pop(), Some(2));
+    assert_eq!(queue.pop(), Some(3));
+    assert_eq!(queue.pop(), Some(4));
+    assert_eq!(queue.pop(), None);
+
+    queue.push(5).unwrap();
+    queue.clear();
+    assert!(queue.is_empty());
+    assert_eq!(queue.peek(), None);
+}
+
+#[test]
+fn debug() {
+    let mut queue = Queue::<u8, 3>::new();
+    for item in 1..=3 {
+        queue.push(item).unwrap();
+    }
+    queue.pop();
+    queue.push(4).unwrap();
+    assert_eq!(format!("{queue:?}"), "[2, 3, 4]");
+    assert_eq!(format!("{:?}", queue.clone()), "[2, 3, 4]");
+}
@@ -0,0 +1,100 @@
+use crate::session::{CallEvent, CallTracker};
+use crate::{Code, Message};
+
+const CALL: Message = Message::new(Code::Call, 12);
+const CALL_END: Message = Message::new(Code::CallEnd, 12);
+
+#[test]
+fn call() {
+    let mut tracker = CallTracker::new(15_000);
+    assert_eq!(
+        tracker.message(&CALL, 1_000),
+        Some(CallEvent::Started { address: 12 })
+    );
+    assert_eq!(tracker.poll(1_000), None);
+    assert_eq!(tracker.next_timeout_ms(5_000), Some(11_000));
+    assert_eq!(
+        tracker.message(&CALL, 5_000),
+        Some(CallEvent::Ringing { address: 12, rings: 2 })
+    );
+    // Other messages are ignored.
+    assert_eq!(
+        tracker.message(&Message::new(Code::OpenDoor, 12), 6_000),
+        None
+    );
+    assert_eq!(
+        tracker.message(&CALL_END, 9_000),
+        Some(CallEvent::Ended { address: 12, duration_ms: 8_000, rings: 3 })
+    );
+    assert!(!tracker.in_call());
+    assert_eq!(tracker.poll(9_000), None);
+    assert_eq!(tracker.next_timeout_ms(9_000), None);
+}
+
+#[test]
+fn timeout() {
+    let mut tracker = CallTracker::new(15_000);
+    // The timestamps wrap around.
+    let start = u32::MAX - 1_000;
+    tracker.message(&CALL, start);
+    tracker.message(&CALL, start.wrapping_add(4_000));
+    assert_eq!(tracker.poll(start.wrapping_add(18_999)), None);
+    assert_eq!(
+        tracker.poll(start.wrapping_add(19_000)),
+        Some(CallEvent::Ended { address: 12, duration_ms: 4_000, rings: 2 })
+    );
+    assert!(!tracker.in_call());
+    // The next call starts a new session.
+    assert_eq!(
+        tracker.message(&CALL, start.wrapping_add(20_000)),
+        Some(CallEvent::Started { address: 12 })
+    );
+}
+
+#[test]
+fn lost_messages() {
+    let mut tracker = CallTracker::new(15_000);
+    // A call to another intercom ends the one in progress.
+    tracker.message(&CALL, 0);
+    assert_eq!(
+        tracker.message(&Message::new(Code::Call, 7), 3_000),
+        Some(CallEvent::Ended { address: 12, duration_ms: 0, rings: 1 })
+    );
+    assert_eq!(tracker.next_timeout_ms(3_000), Some(0));
+    assert_eq!(tracker.poll(3_000), Some(CallEvent::Started { address: 7 }));
+    assert_eq!(tracker.poll(3_000), None);
+
+    // A call end without the previous calls starts and ends a call.
+    assert_eq!(
+        tracker.message(&CALL_END, 6_000),
+        Some(CallEvent::Ended { address: 7, duration_ms: 0, rings: 1 })
+    );
+    assert_eq!(tracker.poll(6_000), Some(CallEvent::Started { address: 12 }));
+    assert_eq!(
+        tracker.poll(6_000),
+        Some(CallEvent::Ended { address: 12, duration_ms: 0, rings: 1 })
+    );
+    assert_eq!(tracker.poll(6_000), None);
+}
+
+#[test]
+fn serde() {
+    assert_eq!(
+        serde_json::to_string(&CallEvent::Started { address: 12 }).unwrap(),
+        r#"{"event":"call_started","address":12}"#
+    );
+    assert_eq!(
+        serde_json::to_string(&CallEvent::Ringing { address: 12, rings: 2 })
+            .unwrap(),
+        r#"{"event":"ringing","address":12,"rings":2}"#
+    );
+    assert_eq!(
+        serde_json::to_string(&CallEvent::Ended {
+            address: 12,
+            duration_ms: 8_000,
+            rings: 3
+        })
+        .unwrap(),
+        r#"{"event":"call_ended","address":12,"duration_ms":8000,"rings":3}"#
+    );
+}
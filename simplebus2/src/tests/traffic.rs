@@ -0,0 +1,95 @@
+use crate::pulse::Pulse;
+use crate::traffic::{self, Direction, Record, Recorder};
+use crate::{Code, Message};
+
+const CALL: Message = Message::new(Code::Call, 12);
+const OPEN: Message = Message::new(Code::OpenDoor, 12);
+
+#[test]
+fn parse() {
+    let text = "\
+# Recorded at home.
+sb2-traffic 1
+5210 rx call 12 ack
+
+1204 rx call_end 12 -
+12030 tx 16 12 ack
+20500 rx 33 3 - 3000/17000,3000/0
+";
+    let records = traffic::parse(text).unwrap();
+    // Records are sorted by time.
+    assert_eq!(
+        records[..3],
+        [
+            Record::new(1204, Direction::Rx, Message::new(Code::CallEnd, 12)),
+            Record {
+                acknowledged: true,
+                ..Record::new(5210, Direction::Rx, CALL)
+            },
+            Record {
+                acknowledged: true,
+                ..Record::new(12030, Direction::Tx, OPEN)
+            },
+        ]
+    );
+    assert_eq!(
+        records[3].pulses,
+        [
+            Pulse { burst_us: 3000, gap_us: 17000 },
+            Pulse { burst_us: 3000, gap_us: 0 }
+        ]
+    );
+    // Records are formatted the way they are parsed.
+    assert_eq!(records[2].to_string(), "12030 tx open_door 12 ack");
+    assert_eq!(records[3].to_string(), "20500 rx 33 3 - 3000/17000,3000/0");
+}
+
+#[test]
+fn parse_errors() {
+    let error = |text: &str| traffic::parse(text).unwrap_err().to_string();
+    assert_eq!(error(""), "line 1: empty file");
+    assert_eq!(error("1204 rx call 12 ack"), "line 1: not a traffic log");
+    assert_eq!(error("sb2-traffic 2"), "line 1: unsupported version 2");
+    assert_eq!(
+        error("sb2-traffic 1\n1204 rx call 12"),
+        "line 2: expecting time, direction, code, address and ack"
+    );
+    assert_eq!(
+        error("sb2-traffic 1\n1204 up call 12 ack"),
+        r#"line 2: invalid direction "up""#
+    );
+    assert_eq!(
+        error("sb2-traffic 1\n\n1204 rx 64 12 ack"),
+        r#"line 3: invalid code "64""#
+    );
+    assert_eq!(
+        error("sb2-traffic 1\n1204 rx call 12 yes"),
+        r#"line 2: invalid acknowledgement "yes""#
+    );
+    assert_eq!(
+        error("sb2-traffic 1\n1204 rx call 12 ack 3000"),
+        r#"line 2: invalid pulses "3000""#
+    );
+}
+
+#[test]
+fn recorder() {
+    let mut recorder = Recorder::new(Vec::new()).unwrap();
+    recorder.record(Record::new(0, Direction::Rx, CALL)).unwrap();
+    recorder.record(Record::new(100, Direction::Tx, OPEN)).unwrap();
+    // The acknowledgement of the open door arrives first.
+    assert!(recorder.ack(&OPEN));
+    assert!(recorder.ack(&CALL));
+    assert!(!recorder.ack(&CALL));
+    recorder.record(Record::new(4000, Direction::Rx, CALL)).unwrap();
+    // Records are written once their acknowledgement can't arrive anymore.
+    recorder.flush(4999).unwrap();
+    assert!(recorder.ack(&CALL));
+
+    let log = String::from_utf8(recorder.finish().unwrap()).unwrap();
+    assert_eq!(
+        log,
+        "sb2-traffic 1\n0 rx call 12 ack\n100 tx open_door 12 ack\n4000 rx call 12 ack\n"
+    );
+    assert_eq!(traffic::parse(&log).unwrap().len(), 3);
+}
@@ -0,0 +1,264 @@
+//! Recordings of the traffic seen on the bus.
+//!
+//! Traffic logs are text files with one line per message, preceded by a
+//! header with the version of the format:
+//!
+//! ```text
+//! sb2-traffic 1
+//! # Lines starting with # are comments.
+//! 1204 rx call 12 ack
+//! 5210 rx call 12 ack
+//! 9215 rx call_end 12 ack
+//! 12030 tx open_door 12 -
+//! 20500 rx 33 3 - 3000/17000,3000/3000,3000/6000
+//! ```
+//!
+//! The fields are the time in milliseconds since the recording started, the
+//! direction (`rx` for messages seen on the bus, `tx` for messages sent by
+//! us), the code, the address, whether the target acknowledged the message
+//! (`ack` or `-`) and, optionally, the raw pulses of the message as
+//! `burst/silence` durations in microseconds.
+//!
+//! Logs are written by `serial_bridge --record` and `sb2 record`, and played
+//! back by `sb2 replay`, either into a simulated bus or into the remote
+//! unit's UART. This module needs the `std` feature.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::pulse::Pulse;
+use crate::{Code, Message};
+
+/// Version of the format written by [`Recorder`].
+pub const VERSION: u32 = 1;
+
+/// First word in the header.
+const MAGIC: &str = "sb2-traffic";
+
+/// Maximum time between a message and its acknowledgement.
+pub const ACK_WINDOW_MS: u64 = 1000;
+
+/// Error found while parsing a traffic log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrafficError {
+    /// Line where the error was found, starting at 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl TrafficError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        Self { line, message: message.into() }
+    }
+}
+
+impl fmt::Display for TrafficError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for TrafficError {}
+
+/// Whether a message was seen on the bus or sent by us.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+/// A message in a traffic log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Time since the recording started.
+    pub time_ms: u64,
+    pub direction: Direction,
+    pub message: Message,
+    /// Whether the target intercom acknowledged the message.
+    pub acknowledged: bool,
+    /// Bursts and silences of the message as seen on the line, if known.
+    pub pulses: Vec<Pulse>,
+}
+
+impl Record {
+    pub fn new(time_ms: u64, direction: Direction, message: Message) -> Self {
+        Self {
+            time_ms,
+            direction,
+            message,
+            acknowledged: false,
+            pulses: vec![],
+        }
+    }
+}
+
+/// Formats the record as a line, without the line break.
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.time_ms,
+            match self.direction {
+                Direction::Rx => "rx",
+                Direction::Tx => "tx",
+            },
+            self.message.code,
+            self.message.address,
+            if self.acknowledged { "ack" } else { "-" }
+        )?;
+        for (i, pulse) in self.pulses.iter().enumerate() {
+            let sep = if i == 0 { ' ' } else { ',' };
+            write!(f, "{sep}{}/{}", pulse.burst_us, pulse.gap_us)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a traffic log. Records are returned sorted by time.
+pub fn parse(text: &str) -> Result<Vec<Record>, TrafficError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+    let Some((n, header)) = lines.next() else {
+        return Err(TrafficError::new(1, "empty file"));
+    };
+    match header.split_once(' ') {
+        Some((MAGIC, version)) => match version.trim().parse::<u32>() {
+            Ok(VERSION) => {}
+            _ => {
+                return Err(TrafficError::new(
+                    n,
+                    format!("unsupported version {version}"),
+                ))
+            }
+        },
+        _ => return Err(TrafficError::new(n, "not a traffic log")),
+    }
+
+    let mut records = lines
+        .map(|(n, line)| {
+            parse_record(line).map_err(|err| TrafficError::new(n, err))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    records.sort_by_key(|record| record.time_ms);
+    Ok(records)
+}
+
+fn parse_record(line: &str) -> Result<Record, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [time, direction, code, address, ack, ref rest @ ..] = fields[..]
+    else {
+        return Err("expecting time, direction, code, address and ack".into());
+    };
+    let time_ms =
+        time.parse().map_err(|_| format!("invalid time {time:?}"))?;
+    let direction = match direction {
+        "rx" => Direction::Rx,
+        "tx" => Direction::Tx,
+        _ => return Err(format!("invalid direction {direction:?}")),
+    };
+    let code = match code.parse::<Code>() {
+        Ok(code) if code.as_u8() <= 0b11_1111 => code,
+        _ => return Err(format!("invalid code {code:?}")),
+    };
+    let address =
+        address.parse().map_err(|_| format!("invalid address {address:?}"))?;
+    let acknowledged = match ack {
+        "ack" => true,
+        "-" => false,
+        _ => return Err(format!("invalid acknowledgement {ack:?}")),
+    };
+    let pulses = match rest {
+        [] => vec![],
+        [pulses] => pulses
+            .split(',')
+            .map(|pulse| {
+                let (burst, gap) = pulse.split_once('/')?;
+                Some(Pulse {
+                    burst_us: burst.parse().ok()?,
+                    gap_us: gap.parse().ok()?,
+                })
+            })
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("invalid pulses {pulses:?}"))?,
+        _ => return Err("too many fields".into()),
+    };
+    Ok(Record {
+        time_ms,
+        direction,
+        message: Message::new(code, address),
+        acknowledged,
+        pulses,
+    })
+}
+
+/// Writes a traffic log while the traffic is happening.
+///
+/// The acknowledgement of a message arrives after the message, so records
+/// are held until it arrives or until [`ACK_WINDOW_MS`] passes,
+/// and written in the order they were recorded.
+pub struct Recorder<W: Write> {
+    out: W,
+    pending: VecDeque<Record>,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Creates a recorder that writes to `out`, starting with the header.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        writeln!(out, "{MAGIC} {VERSION}")?;
+        out.flush()?;
+        Ok(Self { out, pending: VecDeque::new() })
+    }
+
+    /// Adds a record to the log. Records must be added in order.
+    pub fn record(&mut self, record: Record) -> io::Result<()> {
+        self.flush(record.time_ms)?;
+        self.pending.push_back(record);
+        Ok(())
+    }
+
+    /// Marks the oldest record of `message` that wasn't acknowledged yet as
+    /// acknowledged. Returns false if there isn't any.
+    pub fn ack(&mut self, message: &Message) -> bool {
+        match self
+            .pending
+            .iter_mut()
+            .find(|record| !record.acknowledged && record.message == *message)
+        {
+            Some(record) => {
+                record.acknowledged = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Writes the records that can't be acknowledged anymore at `now_ms`.
+    pub fn flush(&mut self, now_ms: u64) -> io::Result<()> {
+        let mut written = false;
+        while let Some(record) = self.pending.front() {
+            if now_ms < record.time_ms + ACK_WINDOW_MS {
+                break;
+            }
+            writeln!(self.out, "{record}")?;
+            self.pending.pop_front();
+            written = true;
+        }
+        if written {
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes all the records, regardless of their acknowledgement, and
+    /// returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush(u64::MAX)?;
+        Ok(self.out)
+    }
+}